@@ -0,0 +1,118 @@
+#![no_main]
+use interpreter::{CachedMachine, Error, HostCall, Machine};
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+
+const MAX_STEPS: usize = 10_000;
+
+const REGS: usize = 16;
+
+/// Service number of `host_write`
+const HOST_WRITE: u8 = 0;
+
+/// Store r11 at r10 from the host, which may overwrite decoded instructions.
+fn host_write(call: &mut HostCall) -> Result<bool, Error> {
+    call.write_u32(call.reg(10)?, call.reg(11)?)?;
+    Ok(false)
+}
+
+#[derive(Arbitrary, Clone, Debug)]
+struct MachineState {
+    regs: [u32; REGS],
+    memory: Vec<u8>,
+}
+
+impl MachineState {
+    fn machine(&self) -> Option<Machine> {
+        let mut machine = Machine::new(&self.memory).ok()?;
+        for (r, &value) in self.regs.iter().enumerate() {
+            machine.set_reg(r, value).unwrap();
+        }
+        machine.register_service(HOST_WRITE, host_write).unwrap();
+        Some(machine)
+    }
+}
+
+/// Run the reference interpreter and the cached engine side by side,
+/// and describe the first difference found after a step.
+fn divergence(state: &MachineState) -> Option<String> {
+    let mut reference = state.machine()?;
+    let mut cached = CachedMachine::new(state.machine()?);
+    let (mut reference_out, mut cached_out) = (Vec::new(), Vec::new());
+    for step in 0..MAX_STEPS {
+        let expected = reference.step_on(&mut reference_out);
+        let actual = cached.step_on(&mut cached_out);
+        let what = if expected != actual {
+            format!("result {expected:?} instead of {actual:?}")
+        } else if reference.regs() != cached.machine().regs() {
+            format!(
+                "registers {:?} instead of {:?}",
+                reference.regs(),
+                cached.machine().regs()
+            )
+        } else if reference.memory() != cached.machine().memory() {
+            String::from("memory")
+        } else if reference_out != cached_out {
+            format!("output {reference_out:?} instead of {cached_out:?}")
+        } else if let Ok(true) | Err(_) = expected {
+            return None;
+        } else {
+            continue;
+        };
+        return Some(format!("step {step}: {what}"));
+    }
+    None
+}
+
+/// Shrink a diverging state by truncating its memory and clearing bytes and
+/// registers, as long as the engines still diverge.
+fn minimize(mut state: MachineState) -> MachineState {
+    while let Some(end) = state.memory.iter().rposition(|&b| b != 0) {
+        if end + 1 == state.memory.len() {
+            break;
+        }
+        state.memory.truncate(end + 1);
+    }
+    loop {
+        let mut shrunk = false;
+        let len = state.memory.len();
+        let mut candidates = vec![];
+        if len > 0 {
+            let mut truncated = state.clone();
+            truncated.memory.truncate(len - 1);
+            candidates.push(truncated);
+        }
+        for i in (0..len).filter(|&i| state.memory[i] != 0) {
+            let mut cleared = state.clone();
+            cleared.memory[i] = 0;
+            candidates.push(cleared);
+        }
+        for r in (0..REGS).filter(|&r| state.regs[r] != 0) {
+            let mut cleared = state.clone();
+            cleared.regs[r] = 0;
+            candidates.push(cleared);
+        }
+        for candidate in candidates {
+            if divergence(&candidate).is_some() {
+                state = candidate;
+                shrunk = true;
+                break;
+            }
+        }
+        if !shrunk {
+            return state;
+        }
+    }
+}
+
+fuzz_target!(|state: MachineState| {
+    if divergence(&state).is_some() {
+        let state = minimize(state);
+        panic!(
+            "engines diverge at {}\nregisters: {:?}\nmemory: {:?}",
+            divergence(&state).unwrap(),
+            state.regs,
+            state.memory
+        );
+    }
+});
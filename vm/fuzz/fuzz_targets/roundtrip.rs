@@ -0,0 +1,15 @@
+#![no_main]
+use interpreter::{assemble, disassemble, Instruction};
+use libfuzzer_sys::fuzz_target;
+use std::convert::TryFrom;
+
+fuzz_target!(|bytes: &[u8]| {
+    if let Ok(instruction) = Instruction::try_from(bytes) {
+        let mut encoded = [0; Instruction::MAX_SIZE];
+        let len = instruction.encode(&mut encoded).unwrap();
+        assert_eq!(instruction.size() as usize, len);
+        assert_eq!(&bytes[..len], &encoded[..len]);
+        assert_eq!(Ok(instruction), instruction.to_string().parse());
+    }
+    assert_eq!(Ok(bytes.to_vec()), assemble(&disassemble(bytes)));
+});
@@ -3,8 +3,15 @@
 
 counters = {}
 symbols = {}
+functions = []
 code = []
 data = []
+# Line of the disassembly of each instruction
+lines = []
+# Position, kind, symbol and addend of each label reference
+relocations = []
+
+LOW, HIGH, WORD, RELATIVE = range(4)
 
 MEMORY_SIZE = 4096
 NREGS = 16
@@ -19,6 +26,10 @@ TRASH = 3
 
 BUSY_REGS = [IP, ZERO, SP, TRASH]
 
+# Framebuffer of the LED matrix (8x8 RGB pixels), followed by the present
+# register
+FRAMEBUFFER = 3072
+
 
 def find_reg(busy_regs):
     for i in range(NREGS):
@@ -65,13 +76,7 @@ def loadimm(target, value):
         l, h = "low:{}".format(value), "high:{}".format(value)
         code.extend([4, target, l, h])
     elif value >= 2**15 or value < -2**15:
-        if value < 0:
-            value = 2**32 + value
-        label = make_symbol("large_integer")
-        data.append((label, [value & 0xff, (value >> 8) &
-                             0xff, (value >> 16) & 0xff, (value >> 24) & 0xff]))
-        loadimm(TRASH, label)
-        load(target, TRASH)
+        loadimm32(target, value)
     else:
         if value < 0:
             value = value + 65536
@@ -79,6 +84,45 @@ def loadimm(target, value):
         code.extend([4, target, l, h])
 
 
+def loadimm32(target, value):
+    if type(value) == str:
+        code.extend([17, target] +
+                    ["byte{}:{}".format(i, value) for i in range(4)])
+    else:
+        if value < 0:
+            value = 2**32 + value
+        code.extend([17, target, value & 0xff, (value >> 8) & 0xff,
+                     (value >> 16) & 0xff, (value >> 24) & 0xff])
+
+
+def rel_offset(label, size):
+    # Offsets are relative to the end of the instruction being emitted
+    end = len(code) + size
+    return ["rlow:{}:{}".format(end, label), "rhigh:{}:{}".format(end, label)]
+
+
+def jump_rel(label):
+    code.extend([18] + rel_offset(label, 3))
+
+
+def jump_rel_if(cond, label):
+    code.extend([19, cond] + rel_offset(label, 4))
+
+
+def loadrel(target, label):
+    code.extend([20, target] + rel_offset(label, 4))
+
+
+def jsr_rel(label):
+    ret = make_symbol("return_from_{}".format(label))
+    loadimm(TRASH, 4)
+    sub(SP, SP, TRASH)
+    loadrel(TRASH, ret)
+    store(SP, TRASH)
+    jump_rel(label)
+    assign_here(ret)
+
+
 def sub(target, op1, op2):
     code.extend([5, target, op1, op2])
 
@@ -101,6 +145,54 @@ def exit():
     code.append(7)
 
 
+def trap(service):
+    code.extend([21, service])
+
+
+def enable_interrupts():
+    code.append(22)
+
+
+def disable_interrupts():
+    code.append(23)
+
+
+def return_from_interrupt():
+    code.append(24)
+
+
+def syscall():
+    code.append(25)
+
+
+def return_to_user(reg):
+    code.extend([26, reg])
+
+
+def compare_and_swap(target, source, expected, new):
+    code.extend([27, target, source, expected, new])
+
+
+def fetch_add(target, source, value):
+    code.extend([28, target, source, value])
+
+
+def core_id(target):
+    code.extend([29, target])
+
+
+def spawn(target, entry):
+    code.extend([30, target, entry])
+
+
+def yield_thread():
+    code.append(31)
+
+
+def join(reg):
+    code.extend([32, reg])
+
+
 def jump_if(busy_regs, target, cond):
     t, busy_regs = make_reg(busy_regs, target)
     c, _ = make_reg(busy_regs, cond)
@@ -162,6 +254,7 @@ def if_then_else(busy_regs, cond, then_code, else_code=None):
 
 
 def start_function(label):
+    functions.append(label)
     assign_here(label)
 
 
@@ -216,14 +309,38 @@ def append_data():
 
 
 def replace_labels():
+    # Symbols defined by other modules are left to the linker
+    relocations.clear()
     for (i, c) in enumerate(code):
         if type(c) == str:
             if c.startswith("low:"):
-                code[i] = symbols[c[4:]] % 256
+                code[i] = symbols.get(c[4:], 0) % 256
+                relocations.append((i, LOW, c[4:], 0))
             elif c.startswith("high:"):
-                code[i] = symbols[c[5:]] // 256
+                code[i] = symbols.get(c[5:], 0) // 256
+                relocations.append((i, HIGH, c[5:], 0))
+            elif c.startswith("byte"):
+                code[i] = (symbols.get(c[6:], 0) >> (8 * int(c[4]))) & 0xff
+                if c[4] == "0":
+                    relocations.append((i, WORD, c[6:], 0))
+            elif c.startswith("rlow:") or c.startswith("rhigh:"):
+                kind, end, label = c.split(":", 2)
+                offset = (symbols.get(label, 0) - int(end)) % 65536
+                code[i] = offset % 256 if kind == "rlow" else offset // 256
+                if kind == "rlow":
+                    relocations.append((i, RELATIVE, label, i - int(end)))
             else:
-                code[i] = symbols[c]
+                code[i] = symbols.get(c, 0)
+                relocations.append((i, LOW, c, 0))
+
+
+def undefined_symbols():
+    return sorted({label for (_, _, label, _) in relocations
+                   if label not in symbols})
+
+
+FLOAT_OPS = {9: ("fadd", "+"), 10: ("fsub", "-"), 11: ("fmul", "*"),
+             12: ("fdiv", "/"), 13: ("fcmp", "<=>")}
 
 
 def disassemble(fd):
@@ -231,10 +348,15 @@ def disassemble(fd):
     for (k, v) in symbols.items():
         rev.setdefault(v, []).append(k)
     i = 0
+    line = 1
+    lines.clear()
     while i < len(code):
         if i in rev:
             for s in sorted(rev[i]):
                 fd.write("{}:\n".format(s))
+                line += 1
+        lines.append((i, line))
+        line += 1
         fd.write("  {:04d} ".format(i))
         c = code[i:i+4]
         if c[0] == 1:
@@ -262,6 +384,61 @@ def disassemble(fd):
         elif c[0] == 8:
             fd.write("  out_number r{}".format(c[1]))
             i += 2
+        elif 9 <= c[0] <= 13:
+            op, sym = FLOAT_OPS[c[0]]
+            fd.write("  {} r{} <- r{} {} r{}".format(op, c[1], c[2], sym, c[3]))
+            i += 4
+        elif c[0] == 14 or c[0] == 15:
+            op = "itof" if c[0] == 14 else "ftoi"
+            fd.write("  {} r{} <- r{}".format(op, c[1], c[2]))
+            i += 3
+        elif c[0] == 16:
+            fd.write("  out_float r{}".format(c[1]))
+            i += 2
+        elif c[0] == 18:
+            fd.write("  jump_rel {}".format(rel_decode(c[1], c[2])))
+            i += 3
+        elif c[0] == 19:
+            fd.write("  jump_rel {} if r{} != 0".format(
+                rel_decode(c[2], c[3]), c[1]))
+            i += 4
+        elif c[0] == 20:
+            fd.write("  loadrel r{} <- {}".format(c[1], rel_decode(c[2], c[3])))
+            i += 4
+        elif c[0] == 21:
+            fd.write("  trap #{}".format(c[1]))
+            i += 2
+        elif c[0] in (22, 23, 24, 25):
+            fd.write("  {}".format(
+                {22: "ei", 23: "di", 24: "reti", 25: "syscall"}[c[0]]))
+            i += 1
+        elif c[0] == 26:
+            fd.write("  sysret r{}".format(c[1]))
+            i += 2
+        elif c[0] == 27:
+            c = code[i:i+5]
+            fd.write("  cas r{} <- [r{}] r{} r{}".format(c[1], c[2], c[3], c[4]))
+            i += 5
+        elif c[0] == 28:
+            fd.write("  fetch_add r{} <- [r{}] + r{}".format(c[1], c[2], c[3]))
+            i += 4
+        elif c[0] == 29:
+            fd.write("  coreid r{}".format(c[1]))
+            i += 2
+        elif c[0] == 30:
+            fd.write("  spawn r{} <- r{}".format(c[1], c[2]))
+            i += 3
+        elif c[0] == 31:
+            fd.write("  yield")
+            i += 1
+        elif c[0] == 32:
+            fd.write("  join r{}".format(c[1]))
+            i += 2
+        elif c[0] == 17:
+            c = code[i:i+6]
+            fd.write(
+                "  loadimm32 r{} <- #{}".format(c[1], load_imm32_decode(c[2:6])))
+            i += 6
         else:
             fd.write("  ???")
             i += 1
@@ -278,6 +455,75 @@ def load_imm_decode(l, h):
     return v - 65536 if v & 0x8000 else v
 
 
+def rel_decode(l, h):
+    if type(l) == str:
+        return "@" + l.split(":", 2)[2]
+    return "ip{:+d}".format(load_imm_decode(l, h))
+
+
+def load_imm32_decode(b):
+    if type(b[0]) == str:
+        return b[0][6:]
+    v = b[0] | (b[1] << 8) | (b[2] << 16) | (b[3] << 24)
+    return v - 2**32 if v & 0x80000000 else v
+
+
+def symbol_entries(main=True):
+    entries = [(symbols[f], f) for f in functions]
+    return sorted([(0, "main")] + entries if main else entries)
+
+
+def write_symbol_map(fd):
+    for (addr, name) in symbol_entries():
+        fd.write("{:04d} {}\n".format(addr, name))
+
+
+def uses_float():
+    return any(9 <= code[addr] <= 16 for (addr, _) in lines)
+
+
+def write_object(fd, code_end, source, main=True):
+    def u16(v):
+        return v.to_bytes(2, "little")
+
+    def u32(v):
+        return v.to_bytes(4, "little")
+
+    def text(s):
+        return u16(len(s)) + s.encode()
+
+    out = b"VMOB" + u16(2)
+    out += u16(1 if uses_float() else 0)
+    out += u32(0) + u32(MEMORY_SIZE)
+    sections = [(0, 0, code[:code_end]), (1, code_end, code[code_end:])]
+    sections = [s for s in sections if s[2]]
+    out += u32(len(sections))
+    for (kind, addr, content) in sections:
+        out += bytes([kind]) + u32(addr) + u32(len(content)) + bytes(content)
+
+    data_labels = [label for (label, _) in data]
+
+    def section(label):
+        return len(sections) - 1 if label in data_labels else 0
+    globals = symbol_entries(main)
+    entries = [(addr, name, section(name), 1) for (addr, name) in globals]
+    entries += [(addr, name, section(name), 0)
+                for (name, addr) in sorted(symbols.items())
+                if (addr, name) not in globals]
+    entries += [(0, name, 2**32 - 1, 1) for name in undefined_symbols()]
+    out += u32(len(entries))
+    for (addr, name, index, is_global) in entries:
+        out += u32(addr) + u32(index) + bytes([is_global]) + text(name)
+    out += u32(len(relocations))
+    for (pos, kind, label, addend) in relocations:
+        out += u32(0) + u32(pos) + bytes([kind])
+        out += addend.to_bytes(4, "little", signed=True) + text(label)
+    out += text(source) + u32(len(lines))
+    for (addr, line) in lines:
+        out += u32(addr) + u32(line)
+    fd.write(out)
+
+
 def print_test():
     hello_addr, hello_len = string(b"Hello, world!\n")
     loadimm(10, hello_addr)
@@ -506,6 +752,31 @@ def jump_to_function_test():
     end_function()
 
 
+def large_constant_test():
+    loadimm(10, 123456789)
+    loadimm(11, -1000000)
+    loadimm(12, 32768)
+    exit()
+
+
+def pic_test():
+    # Position-independent code: only IP-relative jumps and address loads,
+    # the stack pointer is the only absolute address being used.
+    loadimm(11, 0)
+    assign_here("pic_loop")
+    jsr_rel("pic_add")
+    loadimm(TRASH, 1)
+    sub(10, 10, TRASH)
+    jump_rel_if(10, "pic_loop")
+    loadrel(12, "pic_add")
+    exit()
+    # Add r10 to r11, uses r13 as a temporary
+    start_function("pic_add")
+    sub(13, ZERO, 10)
+    sub(11, 11, 13)
+    end_function()
+
+
 def push_pop_test():
     push(0)
     push(0)
@@ -520,6 +791,11 @@ def hello_world_example():
     add_print_function()
 
 
+def hello_main_module():
+    do_print(b"Hello, world!\n")
+    exit()
+
+
 def count_example():
     do_print(b"I will count from 1 to 10 (included)\n")
     assign_here("loop")
@@ -624,21 +900,58 @@ def beer_example():
     add_print_function()
 
 
-def make_example(f, basename):
+def animation_example():
+    # Light the pixels in red one after the other, presenting a frame after
+    # each of them. A word is stored one byte before the pixel so that the
+    # next pixel is left untouched.
+    loadimm(4, FRAMEBUFFER - 1)
+    loadimm(5, 0xff00)
+    loadimm(6, 64)
+    loadimm(7, FRAMEBUFFER + 192)
+    assign_here("animation_loop")
+    store(4, 5)
+    store(7, 5)
+    loadimm(TRASH, -3)
+    sub(4, 4, TRASH)
+    loadimm(TRASH, 1)
+    sub(6, 6, TRASH)
+    jump_rel_if(6, "animation_loop")
+    exit()
+
+
+def build(f, basename, main=True):
     counters.clear()
     symbols.clear()
+    functions.clear()
     code.clear()
     data.clear()
-    loadimm(SP, MEMORY_SIZE)
+    if main:
+        loadimm(SP, MEMORY_SIZE)
     f()
-    import sys
     with open("{}.dis".format(basename), "wt") as outfd:
         disassemble(outfd)
+    code_end = len(code)
     append_data()
     replace_labels()
+    with open("{}.obj".format(basename), "wb") as outfd:
+        write_object(outfd, code_end, "{}.dis".format(basename.split("/")[-1]),
+                     main)
+
+
+def make_example(f, basename):
+    build(f, basename)
+    assert not undefined_symbols(), undefined_symbols()
+    with open("{}.sym".format(basename), "wt") as outfd:
+        write_symbol_map(outfd)
     open("{}.bin".format(basename), "wb").write(bytes(code))
 
 
+def make_module(f, basename, main=False):
+    # Relocatable module, to be linked with the modules defining the
+    # symbols it references
+    build(f, basename, main)
+
+
 make_example(push_pop_test, "tests/push_pop")
 make_example(jump_to_function_test, "tests/function")
 make_example(mult_test, "tests/multiply")
@@ -647,9 +960,16 @@ make_example(afact_test, "tests/afact")
 make_example(rfact_test, "tests/rfact")
 make_example(rfact_tr_test, "tests/rfact_tr")
 make_example(fibo_test, "tests/fibo")
+make_example(large_constant_test, "tests/large_constant")
+make_example(pic_test, "tests/pic")
+
+make_module(hello_main_module, "tests/hello_main", main=True)
+make_module(add_print_function, "tests/print_lib")
+make_module(add_mult_function, "tests/mult_lib")
 
 make_example(hello_world_example, "examples/hello_world")
 make_example(count_example, "examples/count")
 make_example(fact_example, "examples/factorial")
 make_example(fibo_example, "examples/fibonacci")
 make_example(beer_example, "examples/99bottles")
+make_example(animation_example, "examples/animation")
@@ -0,0 +1,696 @@
+//! Disassembler and assembler for the textual form of programs. The
+//! disassembly uses the `.dis` layout of `generator.py`, one instruction per
+//! line preceded by its address, and bytes which do not decode to an
+//! instruction are written as `.byte` directives. Assembling a disassembly
+//! gives back the original bytes.
+//!
+//! Besides instructions, the assembler accepts:
+//!
+//! - labels, written `name:` at the start of a line, and `.equ name, value`
+//!   constants, both usable in immediates (`loadimm r4 <- #name+4`) and as
+//!   targets of IP-relative instructions (`jump_rel name`);
+//! - the data directives `.byte`, `.word` (32 bits) and `.ascii "text"`,
+//!   which take one or more comma-separated operands, `.space size[, fill]`
+//!   and `.align n`, which pads with zeros up to a multiple of `n`;
+//! - `.include "file"`, relative to the directory of the including file;
+//! - `.data`, which starts the data section on the next word, and
+//!   `.global name, ...`, which exports labels or imports the symbols defined
+//!   by other modules, both used when assembling an object with
+//!   [`assemble_object`];
+//! - macros, defined between `.macro name param, ...` and `.endm`, in which
+//!   `\param` is replaced by the argument and `\@` by a number unique to
+//!   the expansion. `push reg`, `pop reg` and `jsr label` are predefined,
+//!   and reject r3 as their register;
+//! - the pseudo-instructions `add rT <- rA + rB`, `jmp target`,
+//!   `jnz rC, target` and `nop`, where a target is a register or an
+//!   address.
+//!
+//! Registers may be written with their names in the calling convention,
+//! which are therefore not valid symbols. Macros and pseudo-instructions use
+//! r3, named `tmp`, as a scratch register.
+
+mod source;
+
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::path::Path;
+
+use crate::machine::register;
+use crate::{
+    Error, Instruction, Object, Relocation, RelocationKind, Section, SectionKind, Symbol,
+    MEMORY_SIZE, REGISTER_NAMES,
+};
+use source::{split_label, split_operands, Preprocessor, Statement};
+
+/// Error found while assembling the given line, starting at 1, of `file`
+/// or of the assembled source if `file` is `None`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct AssemblyError {
+    pub file: Option<String>,
+    pub line: usize,
+    pub error: Error,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{file}:")?;
+        }
+        write!(f, "line {}: {:?}", self.line, self.error)
+    }
+}
+
+/// How the disassembler writes instructions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DisassemblyOptions {
+    /// Write registers with their names in [`REGISTER_NAMES`], e.g. `sp`
+    pub aliases: bool,
+    /// Write the jumps and no-op of the assembler pseudo-instructions as
+    /// such, e.g. `jmp 42` instead of `loadimm r0 <- #42`
+    pub idioms: bool,
+}
+
+/// Disassemble `bytes`, which are supposed to be loaded at address 0.
+#[must_use]
+pub fn disassemble(bytes: &[u8]) -> String {
+    disassemble_with(bytes, DisassemblyOptions::default())
+}
+
+/// Disassemble `bytes`, which are supposed to be loaded at address 0, as
+/// specified by `options`. The result can still be assembled back.
+#[must_use]
+pub fn disassemble_with(bytes: &[u8], options: DisassemblyOptions) -> String {
+    let mut text = String::new();
+    let mut addr = 0;
+    while addr < bytes.len() {
+        write!(text, "  {addr:04}   ").unwrap();
+        if let Ok(instruction) = Instruction::try_from(&bytes[addr..]) {
+            let line = match idiom(instruction) {
+                Some(idiom) if options.idioms => idiom,
+                _ => instruction.to_string(),
+            };
+            if options.aliases {
+                writeln!(text, "{}", rename_registers(&line)).unwrap();
+            } else {
+                writeln!(text, "{line}").unwrap();
+            }
+            addr += instruction.size() as usize;
+        } else {
+            writeln!(text, ".byte {}", bytes[addr]).unwrap();
+            addr += 1;
+        }
+    }
+    text
+}
+
+/// Pseudo-instruction assembled into `instruction`.
+fn idiom(instruction: Instruction) -> Option<String> {
+    match instruction {
+        Instruction::MoveIf {
+            target: 0,
+            source,
+            cond: 0,
+        } => Some(format!("jmp r{source}")),
+        Instruction::MoveIf {
+            target: 0,
+            source,
+            cond,
+        } => Some(format!("jnz r{cond}, r{source}")),
+        Instruction::LoadImm { target: 0, value } => Some(format!("jmp {value}")),
+        Instruction::Sub {
+            target: 1,
+            op1: 1,
+            op2: 1,
+        } => Some(String::from("nop")),
+        _ => None,
+    }
+}
+
+/// Replace the `rN` registers of `text` by their names.
+fn rename_registers(text: &str) -> String {
+    let rename = |token: &str| {
+        let (open, rest) = token.strip_prefix('[').map_or(("", token), |r| ("[", r));
+        let end = rest
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(rest.len());
+        let (reg, close) = rest.split_at(end);
+        let name = (reg.strip_prefix('r'))
+            .and_then(|r| r.parse::<usize>().ok())
+            .and_then(|r| REGISTER_NAMES.get(r));
+        name.map_or_else(|| token.to_owned(), |name| format!("{open}{name}{close}"))
+    };
+    text.split(' ').map(rename).collect::<Vec<_>>().join(" ")
+}
+
+/// Assemble `source`, made of one statement per line, to be loaded at
+/// address 0. An instruction may be preceded by its address, as in the
+/// disassembly, in which case it must match the address at which it is
+/// assembled. Comments start with `;`. Included files are searched from
+/// the current directory.
+///
+/// # Errors
+/// This function returns an error if a line cannot be assembled.
+#[allow(clippy::result_large_err)]
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssemblyError> {
+    assemble_in(source, Path::new("."))
+}
+
+/// Assemble `source`, whose included files are searched from `dir`.
+///
+/// # Errors
+/// This function returns an error if a line cannot be assembled.
+#[allow(clippy::result_large_err)]
+pub fn assemble_in(source: &str, dir: &Path) -> Result<Vec<u8>, AssemblyError> {
+    Ok(Assembler::run(source, dir, false)?.bytes)
+}
+
+/// Assemble `source` into a relocatable object, made of a code section at
+/// address 0 and of a data section from the `.data` directive, if any. Its
+/// symbols are the labels, and references to them which depend on where
+/// the linker places the sections are relocated: immediates, words and
+/// IP-relative offsets from the other section. Such a reference must add a
+/// single label, other labels having to cancel out. Global symbols which are
+/// not defined are imported, and left to the linker.
+///
+/// # Errors
+/// This function returns an error if a line cannot be assembled.
+#[allow(clippy::result_large_err)]
+pub fn assemble_object(source: &str) -> Result<Object, AssemblyError> {
+    Ok(Assembler::run(source, Path::new("."), true)?.object())
+}
+
+/// Labels of an expression, with 1 if they are added or -1 if they are
+/// subtracted.
+type Labels<'a> = Vec<(&'a str, i64)>;
+
+const IP: &str = "r0";
+const TMP: usize = 3;
+
+#[derive(Default)]
+struct Assembler {
+    symbols: HashMap<String, i64>,
+    /// Section of the symbols defined as labels, 0 for code and 1 for data
+    labels: HashMap<String, usize>,
+    /// Labels exported by `.global`
+    globals: Vec<String>,
+    /// Undefined global symbols, whose references are always relocated
+    imports: Vec<String>,
+    bytes: Vec<u8>,
+    /// Start of the data section, after `.data`
+    data: Option<usize>,
+    /// Whether references to labels are relocated, when assembling an object
+    relocatable: bool,
+    relocations: Vec<Relocation>,
+    /// Whether all the symbols are known, in the second pass
+    resolved: bool,
+}
+
+fn is_symbol(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_' || c == '.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// Character following a `\` in a character or string literal.
+fn unescape(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        '0' => Some('\0'),
+        '\\' | '"' | '\'' => Some(c),
+        _ => None,
+    }
+}
+
+/// Content of a string literal.
+fn string(literal: &str) -> Result<Vec<u8>, Error> {
+    let content = literal
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or(Error::InvalidSyntax)?;
+    let mut text = String::new();
+    let mut chars = content.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => text.push(
+                chars
+                    .next()
+                    .and_then(unescape)
+                    .ok_or(Error::InvalidSyntax)?,
+            ),
+            '"' => return Err(Error::InvalidSyntax),
+            _ => text.push(c),
+        }
+    }
+    Ok(text.into_bytes())
+}
+
+impl Assembler {
+    #[allow(clippy::result_large_err)]
+    fn run(source: &str, dir: &Path, relocatable: bool) -> Result<Self, AssemblyError> {
+        let mut preprocessor = Preprocessor::new();
+        preprocessor.file(source, None, dir, 0)?;
+        // The first pass finds the value of the symbols
+        let mut assembler = Self {
+            relocatable,
+            ..Self::default()
+        };
+        assembler.pass(&preprocessor.statements)?;
+        if relocatable {
+            assembler.imports = (assembler.globals.iter())
+                .filter(|name| !assembler.symbols.contains_key(*name))
+                .cloned()
+                .collect();
+        }
+        assembler.resolved = true;
+        assembler.pass(&preprocessor.statements)?;
+        Ok(assembler)
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn pass(&mut self, statements: &[Statement]) -> Result<(), AssemblyError> {
+        self.bytes.clear();
+        self.globals.clear();
+        self.data = None;
+        self.relocations.clear();
+        for statement in statements {
+            self.statement(&statement.text)
+                .map_err(|error| statement.location.error(error))?;
+        }
+        Ok(())
+    }
+
+    fn address(&self) -> i64 {
+        i64::try_from(self.bytes.len()).unwrap_or(i64::MAX)
+    }
+
+    /// Section of the next byte.
+    fn section(&self) -> usize {
+        usize::from(self.data.is_some())
+    }
+
+    /// Object made of the bytes before `.data` and of those after it.
+    fn object(self) -> Object {
+        let start = self.data.unwrap_or(self.bytes.len());
+        let mut sections = vec![Section {
+            kind: SectionKind::Code,
+            address: 0,
+            bytes: self.bytes[..start].to_vec(),
+        }];
+        if self.data.is_some() {
+            sections.push(Section {
+                kind: SectionKind::Data,
+                address: u32::try_from(start).unwrap_or(u32::MAX),
+                bytes: self.bytes[start..].to_vec(),
+            });
+        }
+        let mut symbols = (self.labels.iter())
+            .map(|(name, &section)| Symbol {
+                name: name.clone(),
+                address: u32::try_from(self.symbols[name]).unwrap_or(u32::MAX),
+                section: Some(section),
+                global: self.globals.contains(name),
+            })
+            .collect::<Vec<_>>();
+        symbols.sort_by_key(|s| (s.address, !s.global, s.name.clone()));
+        symbols.extend(self.imports.into_iter().map(|name| Symbol {
+            name,
+            address: 0,
+            section: None,
+            global: true,
+        }));
+        Object {
+            sections,
+            symbols,
+            relocations: self.relocations,
+            ..Object::default()
+        }
+    }
+
+    fn statement(&mut self, mut text: &str) -> Result<(), Error> {
+        if let Some((addr, rest)) = text.split_once(char::is_whitespace) {
+            if let Ok(addr) = addr.parse::<usize>() {
+                if addr != self.bytes.len() {
+                    return Err(Error::InvalidMemoryAddress(
+                        u32::try_from(addr).unwrap_or(u32::MAX),
+                    ));
+                }
+                text = rest.trim_start();
+            }
+        }
+        let (label, text) = split_label(text);
+        if let Some(label) = label {
+            self.define(label, self.address())?;
+            self.labels.insert(label.to_owned(), self.section());
+        }
+        if text.is_empty() {
+            return Ok(());
+        }
+        let (mnemonic, operands) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        if mnemonic.starts_with('.') {
+            self.directive(mnemonic, &split_operands(operands))
+        } else {
+            self.instruction(text)
+        }
+    }
+
+    /// Define a symbol, only once as the second pass defines them again.
+    fn define(&mut self, name: &str, value: i64) -> Result<(), Error> {
+        if !is_symbol(name)
+            || register(name).is_some()
+            || (self.symbols.insert(name.to_owned(), value).is_some() && !self.resolved)
+        {
+            return Err(Error::InvalidSyntax);
+        }
+        Ok(())
+    }
+
+    /// Value of `expr`, made of numbers, characters and symbols added or
+    /// subtracted. In the first pass, unknown symbols are worth 0 unless
+    /// the value is `needed`.
+    fn eval(&self, expr: &str, needed: bool) -> Result<i64, Error> {
+        Ok(self.value(expr, needed)?.0)
+    }
+
+    /// Value of `expr`, and its labels.
+    fn value<'a>(&self, expr: &'a str, needed: bool) -> Result<(i64, Labels<'a>), Error> {
+        let mut labels = Vec::new();
+        let (mut total, mut rest) = self.term(expr.trim_start(), needed, 1, &mut labels)?;
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                return Ok((total, labels));
+            }
+            let (sign, r) = match (rest.strip_prefix('+'), rest.strip_prefix('-')) {
+                (Some(r), _) => (1, r),
+                (_, Some(r)) => (-1, r),
+                _ => return Err(Error::InvalidSyntax),
+            };
+            let (value, r) = self.term(r.trim_start(), needed, sign, &mut labels)?;
+            total = (value.checked_mul(sign))
+                .and_then(|v| total.checked_add(v))
+                .ok_or(Error::InvalidSyntax)?;
+            rest = r;
+        }
+    }
+
+    /// Value of the term starting `text`, and the text following it. A
+    /// label is added to `labels` with `sign`.
+    fn term<'a>(
+        &self,
+        text: &'a str,
+        needed: bool,
+        sign: i64,
+        labels: &mut Labels<'a>,
+    ) -> Result<(i64, &'a str), Error> {
+        if let Some(rest) = text.strip_prefix('-') {
+            let (value, rest) = self.term(rest.trim_start(), needed, -sign, labels)?;
+            return Ok((value.checked_neg().ok_or(Error::InvalidSyntax)?, rest));
+        }
+        if let Some(rest) = text.strip_prefix('\'') {
+            let mut chars = rest.chars();
+            let c = match chars.next() {
+                Some('\\') => chars.next().and_then(unescape),
+                c => c,
+            };
+            let rest = chars.as_str().strip_prefix('\'');
+            let (Some(c), Some(rest)) = (c, rest) else {
+                return Err(Error::InvalidSyntax);
+            };
+            return Ok((i64::from(u32::from(c)), rest));
+        }
+        let end = text
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '.')
+            .unwrap_or(text.len());
+        let (token, rest) = text.split_at(end);
+        let value = if token.starts_with(|c: char| c.is_ascii_digit()) {
+            match token.strip_prefix("0x") {
+                Some(hex) => i64::from_str_radix(hex, 16),
+                None => token.parse(),
+            }
+            .map_err(|_| Error::InvalidSyntax)?
+        } else if let Some(&value) = self.symbols.get(token) {
+            if self.labels.contains_key(token) {
+                labels.push((token, sign));
+            }
+            value
+        } else if self.imports.iter().any(|name| name == token) {
+            labels.push((token, sign));
+            0
+        } else if is_symbol(token) && !needed && !self.resolved {
+            0
+        } else {
+            return Err(Error::InvalidSyntax);
+        };
+        Ok((value, rest))
+    }
+
+    /// Label and addend that `expr` refers to when assembling an object, if
+    /// its value depends on where the linker places the sections.
+    fn reference(&self, expr: &str) -> Result<Option<(String, i64)>, Error> {
+        if !self.relocatable || !self.resolved {
+            return Ok(None);
+        }
+        let (value, labels) = self.value(expr, false)?;
+        // Labels of the same section are at constant distances, imported
+        // symbols are each on their own
+        let section = |label| self.labels.get(label).ok_or(label);
+        let mut sections = Vec::new();
+        for &(label, sign) in &labels {
+            match sections.iter_mut().find(|(s, _)| *s == section(label)) {
+                Some((_, count)) => *count += sign,
+                None => sections.push((section(label), sign)),
+            }
+        }
+        sections.retain(|&(_, count)| count != 0);
+        let relocated = match sections[..] {
+            [] => return Ok(None),
+            [(relocated, 1)] => relocated,
+            _ => return Err(Error::InvalidSyntax),
+        };
+        let (label, _) = (labels.iter())
+            .find(|&&(label, sign)| sign > 0 && section(label) == relocated)
+            .ok_or(Error::InvalidSyntax)?;
+        let address = self.symbols.get(*label).copied().unwrap_or_default();
+        Ok(Some((label.to_string(), value - address)))
+    }
+
+    /// Record that the field at `at` holds `reference`.
+    fn relocate(
+        &mut self,
+        at: usize,
+        kind: RelocationKind,
+        (symbol, addend): (String, i64),
+    ) -> Result<(), Error> {
+        let offset = at - self.data.unwrap_or(0);
+        self.relocations.push(Relocation {
+            section: self.section(),
+            offset: u32::try_from(offset).map_err(|_| Error::MemoryOverflow)?,
+            kind,
+            symbol,
+            addend: i32::try_from(addend).map_err(|_| Error::InvalidSyntax)?,
+        });
+        Ok(())
+    }
+
+    fn directive(&mut self, name: &str, operands: &[&str]) -> Result<(), Error> {
+        match (name, operands) {
+            (".equ", [symbol, value]) => match self.eval(value, !self.resolved) {
+                // Constants are not relocated
+                Ok(_) if self.reference(value)?.is_some() => return Err(Error::InvalidSyntax),
+                Ok(value) => self.define(symbol, value)?,
+                // Defined in the second pass, from later symbols
+                Err(_) if !self.resolved => (),
+                Err(e) => return Err(e),
+            },
+            (".byte", values) if !values.is_empty() => {
+                for value in values {
+                    if self.reference(value)?.is_some() {
+                        return Err(Error::InvalidSyntax);
+                    }
+                    let value = self.eval(value, false)?;
+                    let byte = u8::try_from(value)
+                        .or_else(|_| i8::try_from(value).map(i8::cast_unsigned))
+                        .map_err(|_| Error::InvalidSyntax)?;
+                    self.bytes.push(byte);
+                }
+            }
+            (".word", values) if !values.is_empty() => {
+                for value in values {
+                    if let Some(reference) = self.reference(value)? {
+                        self.relocate(self.bytes.len(), RelocationKind::Word, reference)?;
+                    }
+                    let value = self.eval(value, false)?;
+                    let word = u32::try_from(value)
+                        .or_else(|_| i32::try_from(value).map(i32::cast_unsigned))
+                        .map_err(|_| Error::InvalidSyntax)?;
+                    self.bytes.extend(word.to_le_bytes());
+                }
+            }
+            (".ascii", texts) if !texts.is_empty() => {
+                for text in texts {
+                    self.bytes.extend(string(text)?);
+                }
+            }
+            (".space", [size]) => self.pad(self.eval(size, true)?, 0)?,
+            (".space", [size, fill]) => {
+                let fill =
+                    u8::try_from(self.eval(fill, false)?).map_err(|_| Error::InvalidSyntax)?;
+                self.pad(self.eval(size, true)?, fill)?;
+            }
+            (".align", [align]) => {
+                let align = self.eval(align, true)?;
+                if align <= 0 {
+                    return Err(Error::InvalidSyntax);
+                }
+                let address = self.address();
+                self.pad((align - address % align) % align, 0)?;
+            }
+            (".data", []) if self.data.is_none() => {
+                self.pad((4 - self.address() % 4) % 4, 0)?;
+                self.data = Some(self.bytes.len());
+            }
+            (".global", names) if !names.is_empty() => {
+                for &name in names {
+                    // Labels may be defined after being exported
+                    let constant = self.resolved
+                        && self.symbols.contains_key(name)
+                        && !self.labels.contains_key(name);
+                    if !is_symbol(name) || register(name).is_some() || constant {
+                        return Err(Error::InvalidSyntax);
+                    }
+                    self.globals.push(name.to_owned());
+                }
+            }
+            _ => return Err(Error::InvalidSyntax),
+        }
+        Ok(())
+    }
+
+    /// Add `size` bytes set to `fill`.
+    fn pad(&mut self, size: i64, fill: u8) -> Result<(), Error> {
+        let size = usize::try_from(size).map_err(|_| Error::InvalidSyntax)?;
+        if self.bytes.len() + size > MEMORY_SIZE {
+            return Err(Error::MemoryOverflow);
+        }
+        self.bytes.resize(self.bytes.len() + size, fill);
+        Ok(())
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> Result<(), Error> {
+        let mut encoded = [0; Instruction::MAX_SIZE];
+        let len = instruction.encode(&mut encoded)?;
+        self.bytes.extend_from_slice(&encoded[..len]);
+        Ok(())
+    }
+
+    /// Parse an instruction whose immediates are replaced by their value.
+    fn parse(&self, text: &str) -> Result<Instruction, Error> {
+        let tokens = text
+            .split_whitespace()
+            .map(|token| match token.strip_prefix('#') {
+                Some(expr) => Ok(format!("#{}", self.eval(expr, false)?)),
+                None => Ok(token.to_owned()),
+            });
+        tokens.collect::<Result<Vec<_>, Error>>()?.join(" ").parse()
+    }
+
+    /// Emit the instruction `text`, relocating its immediate if needed.
+    fn emit_parsed(&mut self, text: &str) -> Result<(), Error> {
+        let instruction = self.parse(text)?;
+        let immediate = text.split_whitespace().find_map(|t| t.strip_prefix('#'));
+        let at = self.bytes.len();
+        if let Some(reference) = immediate.map(|e| self.reference(e)).transpose()?.flatten() {
+            match instruction {
+                Instruction::LoadImm { .. } => {
+                    self.relocate(at + 2, RelocationKind::Low, reference.clone())?;
+                    self.relocate(at + 3, RelocationKind::High, reference)?;
+                }
+                Instruction::LoadImm32 { .. } => {
+                    self.relocate(at + 2, RelocationKind::Word, reference)?;
+                }
+                _ => return Err(Error::InvalidSyntax),
+            }
+        }
+        self.emit(instruction)
+    }
+
+    fn instruction(&mut self, text: &str) -> Result<(), Error> {
+        let tokens = text.split_whitespace().collect::<Vec<_>>();
+        let (mnemonic, operands) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        match (mnemonic, &tokens[1..]) {
+            ("add", [t, "<-", a, "+", b]) => {
+                let negate = self.parse(&format!("sub r{TMP} <- r1 - {b}"))?;
+                let sub = self.parse(&format!("sub {t} <- {a} - r{TMP}"))?;
+                if matches!(sub, Instruction::Sub { op1: TMP, .. }) {
+                    return Err(Error::InvalidSyntax);
+                }
+                self.emit(negate)?;
+                self.emit(sub)
+            }
+            ("jmp", [target]) => self.jump(target, IP),
+            ("jnz", _) => match split_operands(operands)[..] {
+                [cond, target] => self.jump(target, cond),
+                _ => Err(Error::InvalidSyntax),
+            },
+            ("nop", []) => self.emit(Instruction::Sub {
+                target: 1,
+                op1: 1,
+                op2: 1,
+            }),
+            ("jump_rel", [_, ..]) => self.relative(tokens, 1),
+            ("loadrel", [_, "<-", _]) => self.relative(tokens, 3),
+            _ => self.emit_parsed(text),
+        }
+    }
+
+    /// Jump to `target`, a register or an address, if `cond` is not zero.
+    /// The jump is unconditional when `cond` is IP, which is never zero.
+    fn jump(&mut self, target: &str, cond: &str) -> Result<(), Error> {
+        if let Ok(jump) = format!("move r0 <- {target} if {cond} != 0").parse() {
+            return self.emit(jump);
+        }
+        if cond == IP {
+            return self.emit_parsed(&format!("loadimm r0 <- #{target}"));
+        }
+        let jump = format!("move r0 <- r{TMP} if {cond} != 0").parse()?;
+        if matches!(jump, Instruction::MoveIf { cond: TMP, .. }) {
+            return Err(Error::InvalidSyntax);
+        }
+        self.emit_parsed(&format!("loadimm r{TMP} <- #{target}"))?;
+        self.emit(jump)
+    }
+
+    /// Emit an IP-relative instruction, whose target at `tokens[index]` may
+    /// be written as an address instead of an offset. An address in the
+    /// other section of an object is relocated.
+    fn relative(&mut self, mut tokens: Vec<&str>, index: usize) -> Result<(), Error> {
+        let target = tokens[index];
+        if target.starts_with("ip+") || target.starts_with("ip-") {
+            let instruction = tokens.join(" ").parse()?;
+            return self.emit(instruction);
+        }
+        tokens[index] = "ip+0";
+        let mut instruction = tokens.join(" ").parse::<Instruction>()?;
+        let end = self.address() + i64::from(instruction.size());
+        let offset = self.eval(target, false)? - end;
+        let offset = i32::from(i16::try_from(offset).map_err(|_| Error::InvalidSyntax)?);
+        match &mut instruction {
+            Instruction::JumpRel { offset: o }
+            | Instruction::JumpRelIf { offset: o, .. }
+            | Instruction::LoadRel { offset: o, .. } => *o = offset,
+            _ => return Err(Error::InvalidSyntax),
+        }
+        self.emit(instruction)?;
+        match self.reference(target)? {
+            Some((symbol, addend)) if self.labels.get(&symbol) != Some(&self.section()) => {
+                // The offset is relative to the end of the instruction
+                let at = self.bytes.len() - 2;
+                self.relocate(at, RelocationKind::Relative, (symbol, addend - 2))
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,297 @@
+//! Expansion of includes and macros, which turns the source into the list of
+//! statements to assemble.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use super::{AssemblyError, TMP};
+use crate::machine::register;
+use crate::Error;
+
+/// Deepest nesting of includes and macro expansions, which catches
+/// recursive ones.
+const MAX_DEPTH: usize = 32;
+
+/// Macros available in every source, as written by `generator.py`:
+/// - `push reg` decrements SP (r2) by 4 and stores `reg` at the new SP;
+/// - `pop reg` loads `reg` from the word at SP and increments SP by 4;
+/// - `jsr target` pushes the return address and jumps to `target`.
+///
+/// They use r3 as a scratch register, so their arguments cannot name it.
+const PRELUDE: &str = "
+.macro push reg
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    store [r2] <- \\reg
+.endm
+.macro pop reg
+    loadimm r3 <- #-4
+    sub r2 <- r2 - r3
+    loadimm r3 <- #4
+    sub r3 <- r2 - r3
+    load \\reg <- [r3]
+.endm
+.macro jsr target
+    loadimm r3 <- #4
+    sub r2 <- r2 - r3
+    loadrel r3 <- return_from_\\@
+    store [r2] <- r3
+    jump_rel \\target
+return_from_\\@:
+.endm
+";
+
+/// Where a statement comes from: the line of a macro invocation for the
+/// statements of its expansion.
+#[derive(Clone, Debug)]
+pub(super) struct Location {
+    pub file: Option<Rc<str>>,
+    pub line: usize,
+}
+
+impl Location {
+    pub fn error(&self, error: Error) -> AssemblyError {
+        AssemblyError {
+            file: self.file.as_deref().map(String::from),
+            line: self.line,
+            error,
+        }
+    }
+}
+
+/// Statement without comment nor surrounding whitespace.
+pub(super) struct Statement {
+    pub location: Location,
+    pub text: String,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+    /// Whether the body overwrites r3, which then cannot be an argument
+    scratch: bool,
+}
+
+#[derive(Default)]
+pub(super) struct Preprocessor {
+    macros: HashMap<String, Macro>,
+    /// Macro being defined
+    definition: Option<(String, Macro)>,
+    /// Number of macro expansions, which makes labels unique with `\@`
+    expansions: usize,
+    pub statements: Vec<Statement>,
+}
+
+/// Characters of `text` outside of string and character literals, with
+/// their index.
+fn unquoted(text: &str) -> impl Iterator<Item = (usize, char)> + '_ {
+    let (mut quote, mut escaped) = (None, false);
+    text.char_indices().filter(move |&(_, c)| {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            _ if quote == Some(c) => quote = None,
+            '"' | '\'' if quote.is_none() => quote = Some(c),
+            _ => return quote.is_none(),
+        }
+        false
+    })
+}
+
+/// Remove the comment starting with `;` outside of quotes.
+fn strip_comment(line: &str) -> &str {
+    match unquoted(line).find(|&(_, c)| c == ';') {
+        Some((i, _)) => line[..i].trim(),
+        None => line.trim(),
+    }
+}
+
+/// Split a statement into its optional label and the rest.
+pub(super) fn split_label(text: &str) -> (Option<&str>, &str) {
+    let (first, rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    match first.strip_suffix(':') {
+        Some(label) => (Some(label), rest.trim_start()),
+        None => (None, text),
+    }
+}
+
+/// Split comma-separated operands, keeping quoted strings and characters
+/// whole.
+pub(super) fn split_operands(text: &str) -> Vec<&str> {
+    let mut operands = Vec::new();
+    let mut start = 0;
+    for (i, _) in unquoted(text).filter(|&(_, c)| c == ',') {
+        operands.push(text[start..i].trim());
+        start = i + 1;
+    }
+    if !text.trim().is_empty() {
+        operands.push(text[start..].trim());
+    }
+    operands
+}
+
+impl Preprocessor {
+    pub fn new() -> Self {
+        let mut preprocessor = Self::default();
+        preprocessor
+            .file(PRELUDE, None, Path::new("."), 0)
+            .expect("invalid prelude");
+        for definition in preprocessor.macros.values_mut() {
+            definition.scratch = true;
+        }
+        preprocessor
+    }
+
+    /// Add the statements of `source`, whose includes are relative to
+    /// `dir`.
+    #[allow(clippy::result_large_err)]
+    pub fn file(
+        &mut self,
+        source: &str,
+        file: Option<Rc<str>>,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<(), AssemblyError> {
+        for (index, line) in source.lines().enumerate() {
+            let location = Location {
+                file: file.clone(),
+                line: index + 1,
+            };
+            self.line(strip_comment(line), &location, dir, depth)?;
+        }
+        // Macro definitions end in the file where they start
+        if self.definition.is_some() {
+            let location = Location {
+                file,
+                line: source.lines().count(),
+            };
+            return Err(location.error(Error::InvalidSyntax));
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn line(
+        &mut self,
+        text: &str,
+        location: &Location,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<(), AssemblyError> {
+        let error = || location.error(Error::InvalidSyntax);
+        let (directive, operands) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        if let Some((_, definition)) = &mut self.definition {
+            match directive {
+                ".endm" => {
+                    let (name, definition) = self.definition.take().unwrap();
+                    self.macros.insert(name, definition);
+                }
+                ".macro" => return Err(error()),
+                _ => definition.body.push(text.to_owned()),
+            }
+            return Ok(());
+        }
+        match directive {
+            ".macro" => {
+                let (name, params) = operands
+                    .trim()
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((operands.trim(), ""));
+                if name.is_empty() {
+                    return Err(error());
+                }
+                let params = split_operands(params).into_iter().map(String::from);
+                let definition = Macro {
+                    params: params.collect(),
+                    body: Vec::new(),
+                    scratch: false,
+                };
+                self.definition = Some((name.to_owned(), definition));
+            }
+            ".endm" => return Err(error()),
+            ".include" => {
+                let path = operands
+                    .trim()
+                    .strip_prefix('"')
+                    .and_then(|p| p.strip_suffix('"'))
+                    .ok_or_else(error)?;
+                let path = dir.join(path);
+                let source = fs::read_to_string(&path).map_err(|_| error())?;
+                let file = Rc::from(path.display().to_string());
+                let dir = path.parent().unwrap_or(dir);
+                self.nested(depth, location)?
+                    .file(&source, Some(file), dir, depth + 1)?;
+            }
+            _ => {
+                let (label, statement) = split_label(text);
+                let (name, args) = statement
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((statement, ""));
+                if !self.macros.contains_key(name) {
+                    if !text.is_empty() {
+                        self.statements.push(Statement {
+                            location: location.clone(),
+                            text: text.to_owned(),
+                        });
+                    }
+                    return Ok(());
+                }
+                if let Some(label) = label {
+                    self.statements.push(Statement {
+                        location: location.clone(),
+                        text: format!("{label}:"),
+                    });
+                }
+                self.expand(name, &split_operands(args), location, dir, depth)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn nested(&mut self, depth: usize, location: &Location) -> Result<&mut Self, AssemblyError> {
+        if depth < MAX_DEPTH {
+            Ok(self)
+        } else {
+            Err(location.error(Error::InvalidSyntax))
+        }
+    }
+
+    /// Add the statements of the expansion of the macro `name`.
+    #[allow(clippy::result_large_err)]
+    fn expand(
+        &mut self,
+        name: &str,
+        args: &[&str],
+        location: &Location,
+        dir: &Path,
+        depth: usize,
+    ) -> Result<(), AssemblyError> {
+        let definition = &self.macros[name];
+        let scratch = |arg: &&str| definition.scratch && register(arg) == Some(TMP);
+        if args.len() != definition.params.len() || args.iter().any(scratch) {
+            return Err(location.error(Error::InvalidSyntax));
+        }
+        self.expansions += 1;
+        let unique = self.expansions.to_string();
+        // Longer parameters first, so that `\ab` is not replaced as `\a`
+        let mut params = definition.params.iter().zip(args).collect::<Vec<_>>();
+        params.sort_by_key(|(param, _)| usize::MAX - param.len());
+        let body = (definition.body.iter())
+            .map(|line| {
+                params
+                    .iter()
+                    .fold(line.replace("\\@", &unique), |line, (param, arg)| {
+                        line.replace(&format!("\\{param}"), arg)
+                    })
+            })
+            .collect::<Vec<_>>();
+        let preprocessor = self.nested(depth, location)?;
+        for line in body {
+            preprocessor.line(&line, location, dir, depth + 1)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+//! Line-oriented debugger driving a [`Machine`].
+
+use std::io::{self, BufRead, Write};
+
+use crate::{Error, Machine, SymbolMap};
+
+const HELP: &str = "commands:
+  s, step [N]      execute N instructions (default 1)
+  c, continue      run until the program exits or stops
+  r, regs          show the registers
+  bt, backtrace    show the calls leading to the current instruction
+  q, quit          leave the debugger";
+
+pub struct Debugger {
+    machine: Machine,
+    symbols: SymbolMap,
+    exited: bool,
+}
+
+impl Debugger {
+    /// Create a debugger for `machine`. If given, `symbols` are used to
+    /// display function names.
+    #[must_use]
+    pub fn new(machine: Machine, symbols: Option<SymbolMap>) -> Self {
+        Self {
+            machine,
+            symbols: symbols.unwrap_or_default(),
+            exited: false,
+        }
+    }
+
+    /// Reference onto the debugged machine.
+    #[must_use]
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// Mutable reference onto the debugged machine.
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    /// Read commands from `input` until it is exhausted or a quit command
+    /// is entered. The debugger and the program output go to `out`.
+    ///
+    /// # Errors
+    /// This function returns an error if `input` cannot be read or `out`
+    /// cannot be written to.
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, out: &mut W) -> io::Result<()> {
+        write!(out, "(vm) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            if !self.command(&line?, out)? {
+                break;
+            }
+            write!(out, "(vm) ")?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Execute a single command. Return `false` if the debugger must be
+    /// left.
+    ///
+    /// # Errors
+    /// This function returns an error if `out` cannot be written to.
+    pub fn command<W: Write>(&mut self, command: &str, out: &mut W) -> io::Result<bool> {
+        let mut words = command.split_whitespace();
+        match (words.next(), words.next()) {
+            (None, _) => (),
+            (Some("s" | "step"), count) => match count.map_or(Ok(1), str::parse::<usize>) {
+                Ok(count) => {
+                    for _ in 0..count {
+                        if !self.step(out)? {
+                            return Ok(true);
+                        }
+                    }
+                    self.show_location(out)?;
+                }
+                Err(_) => writeln!(out, "invalid instruction count")?,
+            },
+            (Some("c" | "continue"), _) => while self.step(out)? {},
+            (Some("r" | "regs"), _) => self.show_registers(out)?,
+            (Some("bt" | "backtrace"), _) => {
+                let backtrace = self.machine.backtrace();
+                writeln!(out, "{}", self.symbols.symbolize(&backtrace))?;
+            }
+            (Some("q" | "quit"), _) => return Ok(false),
+            (Some("h" | "help"), _) => writeln!(out, "{HELP}")?,
+            (Some(command), _) => writeln!(out, "unknown command {command}, try help")?,
+        }
+        Ok(true)
+    }
+
+    /// Execute one instruction, return `false` if the execution stopped.
+    fn step<W: Write>(&mut self, out: &mut W) -> io::Result<bool> {
+        if self.exited {
+            writeln!(out, "the program has exited")?;
+            return Ok(false);
+        }
+        match self.machine.step_on(out) {
+            Ok(false) => Ok(true),
+            Ok(true) => {
+                self.exited = true;
+                writeln!(out, "the program has exited")?;
+                Ok(false)
+            }
+            Err(error) => {
+                match error {
+                    Error::Watchpoint(hit) => writeln!(out, "{hit}")?,
+                    Error::AbiViolation(diagnostic) => writeln!(out, "{diagnostic}")?,
+                    error => writeln!(out, "stopped: {error:?}")?,
+                }
+                let backtrace = self.machine.backtrace();
+                writeln!(out, "backtrace: {}", self.symbols.symbolize(&backtrace))?;
+                Ok(false)
+            }
+        }
+    }
+
+    fn show_location<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let ip = self.machine.regs()[0];
+        match self.symbols.lookup(ip) {
+            Some(name) => writeln!(out, "ip = {ip:04} in {name}"),
+            None => writeln!(out, "ip = {ip:04}"),
+        }
+    }
+
+    fn show_registers<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        for (i, chunk) in self.machine.regs().chunks(4).enumerate() {
+            for (j, value) in chunk.iter().enumerate() {
+                let sep = if j == chunk.len() - 1 { "\n" } else { "  " };
+                write!(out, "{:>3} {value:#010x}{sep}", format!("r{}", i * 4 + j))?;
+            }
+        }
+        Ok(())
+    }
+}
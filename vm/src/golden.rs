@@ -0,0 +1,165 @@
+//! Golden-output tests: a program `NAME.bin` is run from its reset state and
+//! its outcome is compared with the one recorded in the sidecar file
+//! `NAME.expected`, which can be blessed (rewritten) from the actual outcome.
+
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{Machine, ObjectError};
+
+/// Number of steps after which a program is considered stuck.
+pub const GOLDEN_MAX_STEPS: usize = 10_000_000;
+
+/// Exit status, final registers and output of a program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Outcome {
+    /// `exit`, `error: ERROR` or `step limit reached`
+    pub status: String,
+    pub registers: Vec<u32>,
+    pub output: Vec<u8>,
+}
+
+impl Outcome {
+    /// Run `program` for at most [`GOLDEN_MAX_STEPS`] steps.
+    #[must_use]
+    pub fn of(program: &[u8]) -> Self {
+        Self::with_step_limit(program, GOLDEN_MAX_STEPS)
+    }
+
+    /// Run `program` for at most `max_steps` steps.
+    #[must_use]
+    pub fn with_step_limit(program: &[u8], max_steps: usize) -> Self {
+        let mut output = Vec::new();
+        let mut machine = match Machine::load_program(program) {
+            Ok(machine) => machine,
+            Err(e) => {
+                let status = match e {
+                    ObjectError::Load(e) => format!("error: {e:?}"),
+                    e => format!("error: {e}"),
+                };
+                return Self {
+                    status,
+                    registers: Vec::new(),
+                    output,
+                };
+            }
+        };
+        let mut status = String::from("step limit reached");
+        for _ in 0..max_steps {
+            match machine.step_on(&mut output) {
+                Ok(false) => (),
+                Ok(true) => {
+                    status = String::from("exit");
+                    break;
+                }
+                Err(e) => {
+                    status = format!("error: {e:?}");
+                    break;
+                }
+            }
+        }
+        Self {
+            status,
+            registers: machine.regs().to_vec(),
+            output,
+        }
+    }
+
+    /// Parse the content of an expectation file: a `status` line, a
+    /// `registers` line, and an `output` line followed by the raw output.
+    #[must_use]
+    pub fn parse(content: &[u8]) -> Option<Self> {
+        let mut parts = content.splitn(4, |&b| b == b'\n');
+        let mut line = || std::str::from_utf8(parts.next()?).ok();
+        let status = line()?.strip_prefix("status ")?.to_owned();
+        let registers = line()?
+            .strip_prefix("registers")?
+            .split_whitespace()
+            .map(|r| r.parse().ok())
+            .collect::<Option<_>>()?;
+        if line()? != "output" {
+            return None;
+        }
+        Some(Self {
+            status,
+            registers,
+            output: parts.next()?.to_vec(),
+        })
+    }
+
+    /// Content of the expectation file for this outcome.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header = format!("status {}\nregisters", self.status);
+        for r in &self.registers {
+            write!(header, " {r}").unwrap();
+        }
+        header.push_str("\noutput\n");
+        let mut content = header.into_bytes();
+        content.extend_from_slice(&self.output);
+        content
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    Fail {
+        expected: Outcome,
+        actual: Outcome,
+    },
+    /// There is no (valid) expectation file
+    Missing(Outcome),
+    /// The expectation file has been written
+    Blessed,
+}
+
+/// Path of the expectation file of `program`.
+#[must_use]
+pub fn expectation_path(program: &Path) -> PathBuf {
+    program.with_extension("expected")
+}
+
+/// Run `program` and compare its outcome with its expectation file, which is
+/// written instead when `bless` is set and the outcome differs.
+///
+/// # Errors
+/// This function returns an error if a file cannot be read or written.
+pub fn check_golden(program: &Path, bless: bool) -> io::Result<Verdict> {
+    let actual = Outcome::of(&fs::read(program)?);
+    let path = expectation_path(program);
+    let expected = match fs::read(&path) {
+        Ok(content) => Outcome::parse(&content),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    if expected.as_ref() == Some(&actual) {
+        return Ok(Verdict::Pass);
+    }
+    if bless {
+        fs::write(path, actual.to_bytes())?;
+        return Ok(Verdict::Blessed);
+    }
+    Ok(match expected {
+        Some(expected) => Verdict::Fail { expected, actual },
+        None => Verdict::Missing(actual),
+    })
+}
+
+/// Programs (`.bin` files) found in `dir`, sorted by name.
+///
+/// # Errors
+/// This function returns an error if the directory cannot be read.
+pub fn discover_programs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut programs = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|e| e == "bin") {
+            programs.push(path);
+        }
+    }
+    programs.sort();
+    Ok(programs)
+}
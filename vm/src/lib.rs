@@ -1,5 +1,43 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+
+#[cfg(feature = "std")]
+mod asm;
+#[cfg(feature = "std")]
+mod debugger;
+#[cfg(feature = "std")]
+mod golden;
+#[cfg(feature = "std")]
+mod link;
 mod machine;
+#[cfg(feature = "std")]
+mod object;
+#[cfg(feature = "std")]
+mod routines;
+#[cfg(feature = "std")]
+mod symbols;
 
+#[cfg(feature = "std")]
+pub use asm::{
+    assemble, assemble_in, assemble_object, disassemble, disassemble_with, AssemblyError,
+    DisassemblyOptions,
+};
+#[cfg(feature = "std")]
+pub use debugger::Debugger;
+#[cfg(feature = "std")]
+pub use golden::{
+    check_golden, discover_programs, expectation_path, Outcome, Verdict, GOLDEN_MAX_STEPS,
+};
+#[cfg(feature = "std")]
+pub use link::{LinkError, LinkMap, Linker, Placement};
 pub use machine::*;
+#[cfg(feature = "std")]
+pub use object::{
+    LineInfo, Object, ObjectError, Relocation, RelocationKind, Section, SectionKind, Symbol,
+    FEATURE_FLOAT, MAGIC, VERSION,
+};
+#[cfg(feature = "std")]
+pub use routines::{routine_library, ROUTINES};
+#[cfg(feature = "std")]
+pub use symbols::SymbolMap;
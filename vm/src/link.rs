@@ -0,0 +1,291 @@
+//! Linker combining relocatable objects into a single object which can be
+//! loaded. Sections are laid out from address 0 by kind: code first, then
+//! data and bss aligned on words. Modules are linked in the order they are
+//! added, and library objects only when they define a symbol referenced by
+//! the linked ones.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::object::section_of;
+use crate::{LineInfo, Object, Section, SectionKind, Symbol, MEMORY_SIZE};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LinkError {
+    NoModule,
+    /// A global symbol is defined by two modules
+    DuplicateSymbol(String),
+    UndefinedSymbol(String),
+    /// The value of the symbol does not fit in the relocated location
+    RelocationOverflow(String),
+    /// The sections need this many bytes
+    MemoryOverflow(usize),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoModule => write!(f, "no module to link"),
+            Self::DuplicateSymbol(name) => write!(f, "duplicate symbol {name}"),
+            Self::UndefinedSymbol(name) => write!(f, "undefined symbol {name}"),
+            Self::RelocationOverflow(name) => write!(f, "relocation of {name} overflows"),
+            Self::MemoryOverflow(size) => {
+                write!(
+                    f,
+                    "{size} bytes do not fit in {MEMORY_SIZE} bytes of memory"
+                )
+            }
+        }
+    }
+}
+
+/// Section of a module placed by the linker.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Placement {
+    pub module: String,
+    pub kind: SectionKind,
+    pub address: u32,
+    pub size: u32,
+}
+
+/// Where the linker has placed sections and global symbols.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LinkMap {
+    pub sections: Vec<Placement>,
+    pub symbols: Vec<(u32, String)>,
+}
+
+impl fmt::Display for LinkMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for p in &self.sections {
+            let kind = match p.kind {
+                SectionKind::Code => "code",
+                SectionKind::Data => "data",
+                SectionKind::Bss => "bss",
+            };
+            writeln!(f, "{:04} {:04} {kind:4} {}", p.address, p.size, p.module)?;
+        }
+        writeln!(f)?;
+        for (address, name) in &self.symbols {
+            writeln!(f, "{address:04} {name}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Linker {
+    modules: Vec<(String, Object)>,
+    libraries: Vec<(String, Object)>,
+}
+
+fn defines(object: &Object, name: &str) -> bool {
+    (object.symbols.iter()).any(|s| s.global && s.section.is_some() && s.name == name)
+}
+
+/// Symbols referenced by `object` and not defined by it.
+fn imports(object: &Object) -> impl Iterator<Item = &str> {
+    let defined =
+        |name: &str| (object.symbols.iter()).any(|s| s.section.is_some() && s.name == name);
+    (object.symbols.iter().map(|s| s.name.as_str()))
+        .chain(object.relocations.iter().map(|r| r.symbol.as_str()))
+        .filter(move |name| !defined(name))
+}
+
+impl Linker {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a module, which is always linked. The entry point, the stack
+    /// pointer and the line information come from the first module.
+    pub fn add_module(&mut self, name: &str, object: Object) {
+        self.modules.push((name.to_owned(), object));
+    }
+
+    /// Add a library object, which is linked only if needed.
+    pub fn add_library(&mut self, name: &str, object: Object) {
+        self.libraries.push((name.to_owned(), object));
+    }
+
+    /// Modules to link: all of them, and the libraries needed to resolve
+    /// their references.
+    fn select(&self) -> Vec<&(String, Object)> {
+        let mut selected = self.modules.iter().collect::<Vec<_>>();
+        let mut pending = self.libraries.iter().collect::<Vec<_>>();
+        loop {
+            let undefined = (selected.iter())
+                .flat_map(|(_, object)| imports(object))
+                .filter(|name| !selected.iter().any(|(_, o)| defines(o, name)))
+                .collect::<HashSet<_>>();
+            let Some(index) =
+                (pending.iter()).position(|(_, o)| undefined.iter().any(|name| defines(o, name)))
+            else {
+                return selected;
+            };
+            selected.push(pending.remove(index));
+        }
+    }
+
+    /// Link the modules into an object without relocations.
+    ///
+    /// # Errors
+    /// This function returns an error if a symbol is undefined or defined
+    /// twice, if a relocated value does not fit or if the sections do not
+    /// fit in memory.
+    pub fn link(&self) -> Result<(Object, LinkMap), LinkError> {
+        let mut layout = Layout::new(self.select())?;
+        let modules = layout.modules.clone();
+        let first = &modules.first().ok_or(LinkError::NoModule)?.1;
+
+        let mut globals = HashMap::new();
+        let mut symbols = Vec::new();
+        for (m, (_, object)) in modules.iter().enumerate() {
+            for symbol in &object.symbols {
+                let Some(address) = layout.symbol_address(m, symbol) else {
+                    continue;
+                };
+                if symbol.global && globals.insert(symbol.name.as_str(), address).is_some() {
+                    return Err(LinkError::DuplicateSymbol(symbol.name.clone()));
+                }
+                symbols.push(Symbol {
+                    address,
+                    section: section_of(&layout.sections, address),
+                    ..symbol.clone()
+                });
+            }
+        }
+
+        for (m, (_, object)) in modules.iter().enumerate() {
+            for (s, section) in object.sections.iter().enumerate() {
+                // Empty sections may lie outside of the output ones
+                if section.kind != SectionKind::Bss && !section.bytes.is_empty() {
+                    let address = layout.addresses[m][s];
+                    layout.bytes_at(address)[..section.bytes.len()].copy_from_slice(&section.bytes);
+                }
+            }
+            for relocation in &object.relocations {
+                let name = &relocation.symbol;
+                let local = (object.symbols.iter())
+                    .find(|s| &s.name == name)
+                    .and_then(|s| layout.symbol_address(m, s));
+                let value = local
+                    .or_else(|| globals.get(name.as_str()).copied())
+                    .ok_or_else(|| LinkError::UndefinedSymbol(name.clone()))?;
+                let place = layout.addresses[m][relocation.section] + relocation.offset;
+                let value = value.wrapping_add_signed(relocation.addend);
+                (relocation.kind)
+                    .apply(layout.bytes_at(place), place, value)
+                    .ok_or_else(|| LinkError::RelocationOverflow(name.clone()))?;
+            }
+        }
+
+        let mut map = LinkMap {
+            sections: layout.placements,
+            symbols: (symbols.iter().filter(|s| s.global))
+                .map(|s| (s.address, s.name.clone()))
+                .collect(),
+        };
+        map.symbols.sort();
+        let relocate = |addr| relocate(first, &layout.addresses[0], addr);
+        let lines = LineInfo {
+            source: first.lines.source.clone(),
+            lines: (first.lines.lines.iter())
+                .map(|&(addr, line)| (relocate(addr), line))
+                .collect(),
+        };
+        let object = Object {
+            features: modules.iter().fold(0, |f, (_, o)| f | o.features),
+            entry: relocate(first.entry),
+            stack: first.stack,
+            sections: layout.sections,
+            symbols,
+            relocations: Vec::new(),
+            lines,
+        };
+        Ok((object, map))
+    }
+}
+
+/// Address of `addr` once the sections of `object` have been moved to
+/// `addresses`.
+fn relocate(object: &Object, addresses: &[u32], addr: u32) -> u32 {
+    match section_of(&object.sections, addr) {
+        Some(s) => addr - object.sections[s].address + addresses[s],
+        None => addr,
+    }
+}
+
+/// Sections of the linked modules, placed in memory.
+struct Layout<'a> {
+    modules: Vec<&'a (String, Object)>,
+    /// Addresses of the sections of every module
+    addresses: Vec<Vec<u32>>,
+    /// Output sections, one of each kind at most
+    sections: Vec<Section>,
+    placements: Vec<Placement>,
+}
+
+impl<'a> Layout<'a> {
+    fn new(modules: Vec<&'a (String, Object)>) -> Result<Self, LinkError> {
+        let mut layout = Self {
+            addresses: (modules.iter())
+                .map(|(_, o)| vec![0; o.sections.len()])
+                .collect(),
+            modules,
+            sections: Vec::new(),
+            placements: Vec::new(),
+        };
+        let mut end = 0usize;
+        for kind in [SectionKind::Code, SectionKind::Data, SectionKind::Bss] {
+            let align = if kind == SectionKind::Code { 1 } else { 4 };
+            end = end.next_multiple_of(align);
+            let start = end;
+            for (m, (name, object)) in layout.modules.iter().enumerate() {
+                for (s, section) in object.sections.iter().enumerate() {
+                    if section.kind != kind {
+                        continue;
+                    }
+                    end = end.next_multiple_of(align);
+                    let address = u32::try_from(end).map_err(|_| LinkError::MemoryOverflow(end))?;
+                    layout.addresses[m][s] = address;
+                    layout.placements.push(Placement {
+                        module: name.clone(),
+                        kind,
+                        address,
+                        size: u32::try_from(section.bytes.len()).unwrap_or(u32::MAX),
+                    });
+                    end = end.saturating_add(section.bytes.len());
+                }
+            }
+            if end > MEMORY_SIZE {
+                return Err(LinkError::MemoryOverflow(end));
+            }
+            if end > start {
+                layout.sections.push(Section {
+                    kind,
+                    address: u32::try_from(start).unwrap_or_default(),
+                    bytes: vec![0; end - start],
+                });
+            }
+        }
+        Ok(layout)
+    }
+
+    /// Address of a symbol defined by module `m`.
+    fn symbol_address(&self, m: usize, symbol: &Symbol) -> Option<u32> {
+        let s = symbol.section?;
+        let offset = symbol
+            .address
+            .wrapping_sub(self.modules[m].1.sections[s].address);
+        Some(offset.wrapping_add(self.addresses[m][s]))
+    }
+
+    /// Output bytes from `addr`, which lies in a section of a module.
+    fn bytes_at(&mut self, addr: u32) -> &mut [u8] {
+        let index = section_of(&self.sections, addr).unwrap_or_default();
+        let section = &mut self.sections[index];
+        &mut section.bytes[(addr - section.address) as usize..]
+    }
+}
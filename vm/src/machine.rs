@@ -1,5 +1,52 @@
 use core::convert::TryFrom;
 
+mod abi;
+mod async_run;
+mod backtrace;
+#[cfg(feature = "std")]
+mod cached;
+mod fault;
+#[cfg(feature = "float")]
+mod float;
+mod framebuffer;
+mod host;
+mod interrupt;
+mod mmu;
+mod privilege;
+mod protect;
+mod shadow;
+mod syntax;
+mod system;
+mod threads;
+mod watch;
+
+use abi::AbiChecker;
+use interrupt::InterruptController;
+use protect::Access;
+use shadow::ShadowMemory;
+#[cfg(feature = "std")]
+pub(crate) use syntax::register;
+
+pub use abi::{AbiDiagnostic, AbiViolation, MAX_CALL_DEPTH};
+pub use async_run::AsyncOutput;
+pub use backtrace::{Backtrace, Fault, MAX_FRAMES};
+#[cfg(feature = "std")]
+pub use cached::CachedMachine;
+pub use fault::{FaultCause, FAULT_CAUSE_REG, FAULT_IP_REG, FAULT_VALUE_REG};
+#[cfg(feature = "float")]
+pub use float::FloatInstruction;
+#[cfg(feature = "std")]
+pub use framebuffer::render_frame;
+pub use framebuffer::{serial_frame, FRAMEBUFFER_SIZE, FRAME_START};
+pub use host::{HostCall, HostHandler, MAX_SERVICES};
+pub use interrupt::{MAX_IRQS, TIMER_IRQ};
+pub use mmu::{PageTable, PAGE_EXECUTE, PAGE_READ, PAGE_SIZE, PAGE_WRITE};
+pub use protect::{Protection, MAX_REGIONS};
+pub use syntax::REGISTER_NAMES;
+pub use system::{Race, Schedule, System, MAX_CORES};
+pub use threads::MAX_THREADS;
+pub use watch::{WatchKind, WatchpointHit, MAX_WATCHPOINTS};
+
 #[cfg(not(feature = "std"))]
 use core::{fmt::Write, result};
 
@@ -13,16 +60,44 @@ pub const MEMORY_SIZE: usize = 4096;
 const NREGS: usize = 16;
 
 const IP: usize = 0;
+// Registers with a dedicated role in the ABI used by `generator.py`
+const ZERO: usize = 1;
+const SP: usize = 2;
 
 type Result<T, E = Error> = result::Result<T, E>;
 
+#[derive(Clone)]
 pub struct Machine {
     memory: [u8; MEMORY_SIZE],
     registers: [u32; NREGS],
+    /// Address of the instruction being executed
+    instruction_ip: u32,
+    watchpoints: [Option<watch::Watchpoint>; MAX_WATCHPOINTS],
+    regions: [Option<protect::Region>; MAX_REGIONS],
+    enforce_wx: bool,
+    abi: Option<AbiChecker>,
+    initialized: ShadowMemory,
+    /// Incremented by memory writes not done by instructions, which may
+    /// overwrite instructions decoded by [`CachedMachine`]
+    generation: u32,
+    check_uninitialized: bool,
+    framebuffer: Option<u32>,
+    services: [Option<(u8, HostHandler)>; MAX_SERVICES],
+    interrupts: InterruptController,
+    fault_handler: Option<u32>,
+    page_table: Option<PageTable>,
+    user_mode: bool,
+    syscall_entry: Option<u32>,
+    /// Identifier of the core running, see [`System`]
+    core_id: u32,
+    threads: threads::Threads,
 }
 
-#[derive(Debug)]
-enum Instruction {
+/// A decoded instruction, built from its binary encoding with
+/// `Instruction::try_from(bytes)` and encoded back with
+/// [`Instruction::encode`]. Its textual form is the one used in `.dis` files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
     MoveIf {
         target: usize,
         source: usize,
@@ -40,6 +115,10 @@ enum Instruction {
         target: usize,
         value: i32,
     },
+    LoadImm32 {
+        target: usize,
+        value: u32,
+    },
     Sub {
         target: usize,
         op1: usize,
@@ -52,9 +131,70 @@ enum Instruction {
         reg: usize,
     },
     Exit,
+    JumpRel {
+        offset: i32,
+    },
+    JumpRelIf {
+        cond: usize,
+        offset: i32,
+    },
+    LoadRel {
+        target: usize,
+        offset: i32,
+    },
+    /// Call the host handler registered for `service`
+    Trap {
+        service: u8,
+    },
+    EnableInterrupts,
+    DisableInterrupts,
+    ReturnFromInterrupt,
+    /// Enter supervisor mode at the system call entry point
+    Syscall,
+    /// Enter user mode at the address held by `reg`
+    ReturnToUser {
+        reg: usize,
+    },
+    /// Atomically replace the word at the address held by `source` with
+    /// `new` if it is equal to `expected`, and set `target` to its previous
+    /// value
+    CompareAndSwap {
+        target: usize,
+        source: usize,
+        expected: usize,
+        new: usize,
+    },
+    /// Atomically add `value` to the word at the address held by `source`,
+    /// and set `target` to its previous value
+    FetchAdd {
+        target: usize,
+        source: usize,
+        value: usize,
+    },
+    /// Set `target` to the identifier of the core running the instruction
+    CoreId {
+        target: usize,
+    },
+    /// Start a thread at the address held by `entry`, and set `target` to
+    /// its identifier
+    Spawn {
+        target: usize,
+        entry: usize,
+    },
+    /// Let the next thread run
+    Yield,
+    /// Wait for the thread whose identifier is held by `reg` to exit
+    Join {
+        reg: usize,
+    },
+    #[cfg(feature = "float")]
+    Float(FloatInstruction),
 }
 
 impl Instruction {
+    /// Size of the largest instruction encoding.
+    pub const MAX_SIZE: usize = 6;
+
     fn to_reg(r: u8) -> Result<usize> {
         match r as usize {
             r if r < NREGS => Ok(r),
@@ -66,14 +206,124 @@ impl Instruction {
         i32::from(i16::from_le_bytes([l, h]))
     }
 
-    fn size(&self) -> usize {
+    fn to_imm32(b0: u8, b1: u8, b2: u8, b3: u8) -> u32 {
+        u32::from_le_bytes([b0, b1, b2, b3])
+    }
+
+    fn from_reg(r: usize) -> Result<u8> {
+        u8::try_from(r)
+            .ok()
+            .filter(|&r| usize::from(r) < NREGS)
+            .ok_or(Error::InvalidRegister(r))
+    }
+
+    fn from_imm(value: i32) -> [u8; 2] {
+        let [l, h, _, _] = value.to_le_bytes();
+        [l, h]
+    }
+
+    /// Size of the instruction encoding in bytes.
+    #[must_use]
+    pub fn size(self) -> u32 {
         match self {
-            Self::MoveIf { .. } | Self::LoadImm { .. } | Self::Sub { .. } => 4,
-            Self::Store { .. } | Self::Load { .. } => 3,
-            Self::Out { .. } | Self::OutNumber { .. } => 2,
-            Self::Exit => 1,
+            Self::LoadImm32 { .. } => 6,
+            Self::CompareAndSwap { .. } => 5,
+            Self::MoveIf { .. }
+            | Self::LoadImm { .. }
+            | Self::Sub { .. }
+            | Self::JumpRelIf { .. }
+            | Self::LoadRel { .. }
+            | Self::FetchAdd { .. } => 4,
+            Self::Store { .. } | Self::Load { .. } | Self::JumpRel { .. } | Self::Spawn { .. } => 3,
+            Self::Out { .. }
+            | Self::OutNumber { .. }
+            | Self::Trap { .. }
+            | Self::ReturnToUser { .. }
+            | Self::CoreId { .. }
+            | Self::Join { .. } => 2,
+            Self::Exit
+            | Self::EnableInterrupts
+            | Self::DisableInterrupts
+            | Self::ReturnFromInterrupt
+            | Self::Syscall
+            | Self::Yield => 1,
+            #[cfg(feature = "float")]
+            Self::Float(f) => f.size(),
         }
     }
+
+    /// Encode the instruction at the beginning of `out`, and return the
+    /// number of bytes written, which is always [`Instruction::size`].
+    /// Immediates and offsets are truncated to their encoded width.
+    ///
+    /// # Errors
+    /// This function returns an error if a register is out of range or if
+    /// `out` is too small.
+    pub fn encode(self, out: &mut [u8]) -> Result<usize> {
+        let size = self.size() as usize;
+        let out = out.get_mut(..size).ok_or(Error::MemoryOverflow)?;
+        let r = Instruction::from_reg;
+        let imm = Instruction::from_imm;
+        match self {
+            Self::MoveIf {
+                target,
+                source,
+                cond,
+            } => out.copy_from_slice(&[1, r(target)?, r(source)?, r(cond)?]),
+            Self::Store { target, source } => out.copy_from_slice(&[2, r(target)?, r(source)?]),
+            Self::Load { target, source } => out.copy_from_slice(&[3, r(target)?, r(source)?]),
+            Self::LoadImm { target, value } => {
+                let [l, h] = imm(value);
+                out.copy_from_slice(&[4, r(target)?, l, h]);
+            }
+            Self::Sub { target, op1, op2 } => {
+                out.copy_from_slice(&[5, r(target)?, r(op1)?, r(op2)?]);
+            }
+            Self::Out { reg } => out.copy_from_slice(&[6, r(reg)?]),
+            Self::Exit => out[0] = 7,
+            Self::OutNumber { reg } => out.copy_from_slice(&[8, r(reg)?]),
+            #[cfg(feature = "float")]
+            Self::Float(f) => f.encode(out)?,
+            Self::LoadImm32 { target, value } => {
+                let [b0, b1, b2, b3] = value.to_le_bytes();
+                out.copy_from_slice(&[17, r(target)?, b0, b1, b2, b3]);
+            }
+            Self::JumpRel { offset } => {
+                let [l, h] = imm(offset);
+                out.copy_from_slice(&[18, l, h]);
+            }
+            Self::JumpRelIf { cond, offset } => {
+                let [l, h] = imm(offset);
+                out.copy_from_slice(&[19, r(cond)?, l, h]);
+            }
+            Self::LoadRel { target, offset } => {
+                let [l, h] = imm(offset);
+                out.copy_from_slice(&[20, r(target)?, l, h]);
+            }
+            Self::Trap { service } => out.copy_from_slice(&[21, service]),
+            Self::EnableInterrupts => out[0] = 22,
+            Self::DisableInterrupts => out[0] = 23,
+            Self::ReturnFromInterrupt => out[0] = 24,
+            Self::Syscall => out[0] = 25,
+            Self::ReturnToUser { reg } => out.copy_from_slice(&[26, r(reg)?]),
+            Self::CompareAndSwap {
+                target,
+                source,
+                expected,
+                new,
+            } => out.copy_from_slice(&[27, r(target)?, r(source)?, r(expected)?, r(new)?]),
+            Self::FetchAdd {
+                target,
+                source,
+                value,
+            } => out.copy_from_slice(&[28, r(target)?, r(source)?, r(value)?]),
+            Self::CoreId { target } => out.copy_from_slice(&[29, r(target)?]),
+            Self::Spawn { target, entry } => out.copy_from_slice(&[30, r(target)?, r(entry)?]),
+            Self::Yield => out[0] = 31,
+            Self::Join { reg } => out.copy_from_slice(&[32, r(reg)?]),
+        }
+        Ok(size)
+    }
 }
 
 impl TryFrom<&[u8]> for Instruction {
@@ -111,13 +361,62 @@ impl TryFrom<&[u8]> for Instruction {
             8 => Self::OutNumber {
                 reg: Instruction::to_reg(byte(1)?)?,
             },
+            #[cfg(feature = "float")]
+            o @ FloatInstruction::FIRST_OPCODE..=FloatInstruction::LAST_OPCODE => {
+                Self::Float(FloatInstruction::decode(o, byte)?)
+            }
+            17 => Self::LoadImm32 {
+                target: Instruction::to_reg(byte(1)?)?,
+                value: Instruction::to_imm32(byte(2)?, byte(3)?, byte(4)?, byte(5)?),
+            },
+            18 => Self::JumpRel {
+                offset: Instruction::to_imm(byte(1)?, byte(2)?),
+            },
+            19 => Self::JumpRelIf {
+                cond: Instruction::to_reg(byte(1)?)?,
+                offset: Instruction::to_imm(byte(2)?, byte(3)?),
+            },
+            20 => Self::LoadRel {
+                target: Instruction::to_reg(byte(1)?)?,
+                offset: Instruction::to_imm(byte(2)?, byte(3)?),
+            },
+            21 => Self::Trap { service: byte(1)? },
+            22 => Self::EnableInterrupts,
+            23 => Self::DisableInterrupts,
+            24 => Self::ReturnFromInterrupt,
+            25 => Self::Syscall,
+            26 => Self::ReturnToUser {
+                reg: Instruction::to_reg(byte(1)?)?,
+            },
+            27 => Self::CompareAndSwap {
+                target: Instruction::to_reg(byte(1)?)?,
+                source: Instruction::to_reg(byte(2)?)?,
+                expected: Instruction::to_reg(byte(3)?)?,
+                new: Instruction::to_reg(byte(4)?)?,
+            },
+            28 => Self::FetchAdd {
+                target: Instruction::to_reg(byte(1)?)?,
+                source: Instruction::to_reg(byte(2)?)?,
+                value: Instruction::to_reg(byte(3)?)?,
+            },
+            29 => Self::CoreId {
+                target: Instruction::to_reg(byte(1)?)?,
+            },
+            30 => Self::Spawn {
+                target: Instruction::to_reg(byte(1)?)?,
+                entry: Instruction::to_reg(byte(2)?)?,
+            },
+            31 => Self::Yield,
+            32 => Self::Join {
+                reg: Instruction::to_reg(byte(1)?)?,
+            },
             o => return Err(Error::UnknownOpcode(o)),
         };
         Ok(op)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum Error {
     /// Attempt to create a machine with too large a memory
     MemoryOverflow,
@@ -126,6 +425,69 @@ pub enum Error {
     InvalidMemoryAddress(u32),
     ReadPastMemoryEnd,
     OutputError,
+    /// A watchpoint has been triggered by the last executed instruction
+    Watchpoint(WatchpointHit),
+    TooManyWatchpoints,
+    /// Attempt to write into a read-only region
+    WriteViolation(u32),
+    /// Attempt to execute code from a non-executable region
+    ExecuteViolation(u32),
+    /// Attempt to access a guard region
+    GuardViolation(u32),
+    TooManyRegions,
+    /// The calling convention checker found a violation in the last
+    /// executed instruction
+    AbiViolation(AbiDiagnostic),
+    /// The instruction at `ip` has read the uninitialized byte at `addr`
+    UninitializedRead {
+        ip: u32,
+        addr: u32,
+    },
+    /// Text which is not a valid instruction
+    InvalidSyntax,
+    /// No handler is registered for this service
+    UnknownService(u8),
+    TooManyServices,
+    InvalidInterrupt(u8),
+    /// The `reti` instruction at this address is not run by an interrupt
+    /// handler
+    NotInInterrupt(u32),
+    /// The last executed instruction has written to the framebuffer
+    /// present register
+    FramePresented,
+    /// The virtual address is not mapped with the required permission
+    PageFault(u32),
+    /// The instruction at this address is not allowed in user mode
+    PrivilegedInstruction(u32),
+    /// The memory at this address is reserved to supervisor mode
+    PrivilegedMemory(u32),
+    /// The `syscall` instruction at this address has no entry point
+    NoSyscallEntry(u32),
+    /// No core with this number, or invalid number of cores
+    InvalidCore(usize),
+    /// The `spawn` instruction at this address has been executed before
+    /// thread stacks are set up
+    ThreadsDisabled(u32),
+    TooManyThreads,
+    /// No thread with this identifier
+    InvalidThread(u32),
+    /// All the threads which have not exited are waiting for each other
+    Deadlock,
+}
+
+impl Error {
+    /// Whether this error reports an event after the instruction has been
+    /// completely executed, in which case the execution can be resumed.
+    #[must_use]
+    pub fn is_resumable(&self) -> bool {
+        matches!(
+            self,
+            Error::Watchpoint(_)
+                | Error::AbiViolation(_)
+                | Error::UninitializedRead { .. }
+                | Error::FramePresented
+        )
+    }
 }
 
 impl Machine {
@@ -141,13 +503,63 @@ impl Machine {
         let mut machine = Self {
             memory: [0; MEMORY_SIZE],
             registers: [0; NREGS],
+            instruction_ip: 0,
+            watchpoints: Default::default(),
+            regions: Default::default(),
+            enforce_wx: false,
+            abi: None,
+            initialized: ShadowMemory::new(),
+            generation: 0,
+            check_uninitialized: false,
+            framebuffer: None,
+            services: Default::default(),
+            interrupts: InterruptController::default(),
+            fault_handler: None,
+            page_table: None,
+            user_mode: false,
+            syscall_entry: None,
+            core_id: 0,
+            threads: threads::Threads::default(),
         };
         machine.memory[..memory.len()].copy_from_slice(memory);
+        machine.initialized.mark(0..memory.len());
         Ok(machine)
     }
 
+    /// Copy `image` into the machine memory starting at address `base`.
+    /// If `entry` is given, it is interpreted as an offset into `image`
+    /// and IP is set to point to it.
+    ///
+    /// Combined with the IP-relative instructions, this lets several
+    /// position-independent modules share the same memory.
+    ///
+    /// # Errors
+    /// This function returns an error if `image` does not fit in memory
+    /// at `base`, or if `entry` lies outside of `image`.
+    pub fn load_at(&mut self, base: u32, image: &[u8], entry: Option<u32>) -> Result<()> {
+        let start = Self::get_memory_address(base)?;
+        let end = start + image.len();
+        if end > MEMORY_SIZE {
+            return Err(Error::MemoryOverflow);
+        }
+        if let Some(entry) = entry {
+            if usize::try_from(entry).map_or(true, |entry| entry >= image.len()) {
+                return Err(Error::InvalidMemoryAddress(base.wrapping_add(entry)));
+            }
+            self.registers[IP] = base + entry;
+        }
+        self.memory[start..end].copy_from_slice(image);
+        self.initialized.mark(start..end);
+        self.generation = self.generation.wrapping_add(1);
+        Ok(())
+    }
+
     /// Run until the program terminates or until an error happens.
     /// If output instructions are run, they print on `fd`.
+    ///
+    /// # Errors
+    /// This function returns the first error encountered while executing
+    /// an instruction.
     pub fn run_on<T: Write>(&mut self, fd: &mut T) -> Result<()> {
         while !self.step_on(fd)? {}
         Ok(())
@@ -155,6 +567,9 @@ impl Machine {
 
     /// Run until the program terminates or until an error happens.
     /// If output instructions are run, they print on standard output.
+    ///
+    /// # Errors
+    /// See [`run_on`](Machine::run_on).
     #[cfg(feature = "std")]
     pub fn run(&mut self) -> Result<()> {
         self.run_on(&mut io::stdout().lock())
@@ -167,20 +582,45 @@ impl Machine {
     ///
     /// If output instructions are run, they print on `fd`.
     /// If an error happens at either of those steps, an error is
-    /// returned.
+    /// returned, unless it is a fault sent to the handler set with
+    /// [`Machine::set_fault_handler`].
     ///
     /// In case of success, `true` is returned if the program is
     /// terminated (upon encountering an exit instruction), or
     /// `false` if the execution must continue.
+    ///
+    /// # Errors
+    /// This function returns an error if the instruction cannot be decoded
+    /// or executed.
     pub fn step_on<T: Write>(&mut self, fd: &mut T) -> Result<bool> {
-        let ip = self.registers[IP] as usize;
-        let instruction = Instruction::try_from(self.memory.get(ip..).unwrap_or_default())?;
-        self.registers[IP] += instruction.size() as u32;
-        self.execute_instruction(instruction, fd)
+        self.deliver_interrupt()?;
+        self.instruction_ip = self.registers[IP];
+        let result = self
+            .fetch(self.registers[IP])
+            .and_then(|instruction| self.execute_decoded(instruction, fd));
+        self.trap_fault(result)
+    }
+
+    /// Execute `instruction`, which has been decoded from `instruction_ip`.
+    fn execute_decoded<T: Write>(&mut self, instruction: Instruction, fd: &mut T) -> Result<bool> {
+        self.check_access(self.instruction_ip, instruction.size(), Access::Execute)?;
+        self.check_privilege(instruction)?;
+        self.tick_timer();
+        self.registers[IP] += instruction.size();
+        let zero = self.registers[ZERO];
+        let result = self.execute_instruction(instruction, fd);
+        // Those stop the execution once the instruction is complete
+        if result.as_ref().err().is_none_or(Error::is_resumable) {
+            self.check_abi_after(instruction, zero)?;
+        }
+        result
     }
 
     /// Similar to [`step_on`](Machine::step_on).
     /// If output instructions are run, they print on standard output.
+    ///
+    /// # Errors
+    /// See [`step_on`](Machine::step_on).
     #[cfg(feature = "std")]
     pub fn step(&mut self) -> Result<bool> {
         self.step_on(&mut io::stdout().lock())
@@ -193,11 +633,16 @@ impl Machine {
     }
 
     /// Sets a register to the given value.
+    ///
+    /// # Errors
+    /// This function returns an error if `reg` is not a valid register.
     pub fn set_reg(&mut self, reg: usize, value: u32) -> Result<()> {
-        match self.registers.get_mut(reg) {
-            Some(r) => Ok(*r = value),
-            None => Err(Error::InvalidRegister(reg)),
-        }
+        let r = self
+            .registers
+            .get_mut(reg)
+            .ok_or(Error::InvalidRegister(reg))?;
+        *r = value;
+        Ok(())
     }
 
     /// Reference onto the machine current memory.
@@ -222,41 +667,144 @@ impl Machine {
                 }
             }
             Instruction::Load { target, source } => {
-                self.registers[target] = self.get_memory_u32(self.registers[source])?;
+                let addr = self.registers[source];
+                self.check_access(addr, 4, Access::Read)?;
+                self.check_user_access(addr, Access::Read)?;
+                let value = self.get_memory_u32(addr)?;
+                self.registers[target] = value;
+                self.check_initialized(addr)?;
+                self.check_watchpoints(WatchKind::Read, addr, value, value)?;
             }
             Instruction::Store { target, source } => {
-                self.store_memory(self.registers[target], self.registers[source])?;
+                let (addr, value) = (self.registers[target], self.registers[source]);
+                self.check_access(addr, 4, Access::Write)?;
+                self.check_user_access(addr, Access::Write)?;
+                let old = self.get_memory_u32(addr)?;
+                self.store_word(addr, old, value)?;
             }
+            Instruction::CompareAndSwap {
+                target,
+                source,
+                expected,
+                new,
+            } => {
+                let (expected, new) = (self.registers[expected], self.registers[new]);
+                self.atomic_update(target, self.registers[source], |old| {
+                    (old == expected).then_some(new)
+                })?;
+            }
+            Instruction::FetchAdd {
+                target,
+                source,
+                value,
+            } => {
+                let value = self.registers[value];
+                self.atomic_update(target, self.registers[source], |old| {
+                    Some(old.wrapping_add(value))
+                })?;
+            }
+            Instruction::CoreId { target } => self.registers[target] = self.core_id,
+            Instruction::Spawn { target, entry } => {
+                self.spawn_thread(target, self.registers[entry])?;
+            }
+            Instruction::Yield => self.yield_thread()?,
+            Instruction::Join { reg } => self.join_thread(self.registers[reg])?,
             Instruction::LoadImm { target, value } => {
-                self.registers[target] = value as u32;
+                self.registers[target] = value.cast_unsigned();
+            }
+            Instruction::LoadImm32 { target, value } => {
+                self.registers[target] = value;
             }
             Instruction::Sub { target, op1, op2 } => {
                 self.registers[target] = self.registers[op1].wrapping_sub(self.registers[op2]);
             }
             Instruction::Out { reg } => {
-                write!(fd, "{}", self.registers[reg] as u8 as char)
+                write!(fd, "{}", char::from(self.registers[reg].to_le_bytes()[0]))
                     .map_err(|_| Error::OutputError)?;
             }
-            Instruction::Exit => return Ok(true),
+            Instruction::Exit => return self.exit_thread(),
+            Instruction::JumpRel { offset } => {
+                self.registers[IP] = self.registers[IP].wrapping_add_signed(offset);
+            }
+            Instruction::JumpRelIf { cond, offset } => {
+                if self.registers[cond] != 0 {
+                    self.registers[IP] = self.registers[IP].wrapping_add_signed(offset);
+                }
+            }
+            Instruction::LoadRel { target, offset } => {
+                self.registers[target] = self.registers[IP].wrapping_add_signed(offset);
+            }
             Instruction::OutNumber { reg } => {
-                write!(fd, "{}", self.registers[reg] as i32).map_err(|_| Error::OutputError)?;
+                write!(fd, "{}", self.registers[reg].cast_signed())
+                    .map_err(|_| Error::OutputError)?;
             }
+            Instruction::Trap { service } => return self.host_call(service),
+            Instruction::EnableInterrupts => self.set_interrupts_enabled(true),
+            Instruction::DisableInterrupts => self.set_interrupts_enabled(false),
+            Instruction::ReturnFromInterrupt => self.return_from_interrupt()?,
+            Instruction::Syscall => self.syscall()?,
+            Instruction::ReturnToUser { reg } => self.return_to_user(self.registers[reg]),
+            #[cfg(feature = "float")]
+            Instruction::Float(f) => f.execute(&mut self.registers, fd)?,
         }
         Ok(false)
     }
 
-    fn get_memory_address(addr: u32) -> Result<usize> {
-        if addr < MEMORY_SIZE as u32 {
-            Ok(addr as usize)
-        } else {
-            Err(Error::InvalidMemoryAddress(addr))
+    /// Store `value`, replacing `old`, at `addr` whose access has been
+    /// checked, then report device and watchpoint events.
+    fn store_word(&mut self, addr: u32, old: u32, value: u32) -> Result<()> {
+        let physical = self.store_memory(addr, value)?;
+        self.check_timer_write(physical);
+        self.check_watchpoints(WatchKind::Write, addr, old, value)?;
+        self.check_present(physical)
+    }
+
+    /// Read the word at `addr`, store `update(old)` if it is not `None` and
+    /// set `target` to the old value. The execution of an instruction being
+    /// never interrupted, this is atomic with respect to the other cores.
+    fn atomic_update(
+        &mut self,
+        target: usize,
+        addr: u32,
+        update: impl FnOnce(u32) -> Option<u32>,
+    ) -> Result<()> {
+        self.check_access(addr, 4, Access::Write)?;
+        self.check_user_access(addr, Access::Write)?;
+        let old = self.get_memory_u32(addr)?;
+        let result = match update(old) {
+            Some(value) => self.store_word(addr, old, value),
+            None => Ok(()),
+        };
+        if result.as_ref().err().is_none_or(Error::is_resumable) {
+            self.registers[target] = old;
         }
+        result
     }
 
-    fn get_memory(&self, addr: u32) -> Result<u8> {
+    fn get_memory_address(addr: u32) -> Result<usize> {
+        usize::try_from(addr)
+            .ok()
+            .filter(|&addr| addr < MEMORY_SIZE)
+            .ok_or(Error::InvalidMemoryAddress(addr))
+    }
+
+    fn get_physical(&self, addr: u32) -> Result<u8> {
         Ok(self.memory[Self::get_memory_address(addr)?])
     }
 
+    fn get_physical_u32(&self, addr: u32) -> Result<u32> {
+        Ok(u32::from_le_bytes([
+            self.get_physical(addr)?,
+            self.get_physical(addr + 1)?,
+            self.get_physical(addr + 2)?,
+            self.get_physical(addr + 3)?,
+        ]))
+    }
+
+    fn get_memory(&self, addr: u32) -> Result<u8> {
+        self.get_physical(self.translate(addr, Access::Read)?)
+    }
+
     fn get_memory_u32(&self, addr: u32) -> Result<u32> {
         Ok(u32::from_le_bytes([
             self.get_memory(addr)?,
@@ -266,11 +814,19 @@ impl Machine {
         ]))
     }
 
-    fn store_memory(&mut self, addr: u32, value: u32) -> Result<()> {
-        let bytes = value.to_le_bytes();
-        for i in 0..4 {
-            self.memory[Self::get_memory_address(addr + i)?] = bytes[i as usize];
+    /// Store `value` at the virtual address `addr`, and return the physical
+    /// address it has been stored at. Nothing is stored if a byte cannot be
+    /// written.
+    fn store_memory(&mut self, addr: u32, value: u32) -> Result<u32> {
+        let mut physical = [0; 4];
+        for (i, p) in (0..).zip(&mut physical) {
+            let vaddr = addr.wrapping_add(i);
+            *p = Self::get_memory_address(self.translate(vaddr, Access::Write)?)?;
         }
-        Ok(())
+        for (&addr, byte) in physical.iter().zip(value.to_le_bytes()) {
+            self.memory[addr] = byte;
+            self.initialized.mark(addr..addr + 1);
+        }
+        self.translate(addr, Access::Write)
     }
 }
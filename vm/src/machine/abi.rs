@@ -0,0 +1,201 @@
+//! Runtime checker for the calling convention used by `generator.py`:
+//!   - r1 always holds zero;
+//!   - a function is called by pushing the return address then jumping,
+//!     and returns by popping the return address into IP;
+//!   - the stack pointer (r2) and the callee-saved registers r4-r7 are
+//!     identical before the call and after the return.
+//!
+//! r3 is used as a scratch register by the call and return sequences, so
+//! it is not checked even though it is part of the callee-saved set.
+
+use core::fmt;
+
+use super::{Backtrace, Error, Instruction, Machine, Result, IP, SP, ZERO};
+
+/// Maximum call depth tracked by the checker. Deeper calls are not checked.
+pub const MAX_CALL_DEPTH: usize = 64;
+
+const CALLEE_SAVED: [usize; 4] = [4, 5, 6, 7];
+
+/// Calling convention violation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbiViolation {
+    /// The zero register (r1) has been modified
+    ZeroRegisterModified { value: u32 },
+    /// A callee-saved register has a different value when returning
+    CalleeSavedNotRestored {
+        reg: usize,
+        expected: u32,
+        actual: u32,
+    },
+    /// The stack pointer when returning does not match the one of the caller
+    UnbalancedStack { expected: u32, actual: u32 },
+    /// Return to an address which has not been pushed by a call
+    UnknownReturnAddress { addr: u32 },
+}
+
+/// Calling convention violation, along with where it happened.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AbiDiagnostic {
+    /// Address of the offending instruction
+    pub ip: u32,
+    pub violation: AbiViolation,
+    /// Active calls when the offending instruction was executed
+    pub backtrace: Backtrace,
+}
+
+impl fmt::Display for AbiDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "abi violation at {:04}: ", self.ip)?;
+        match self.violation {
+            AbiViolation::ZeroRegisterModified { value } => {
+                write!(f, "r{ZERO} modified (now {value:#010x})")?;
+            }
+            AbiViolation::CalleeSavedNotRestored {
+                reg,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "callee-saved r{reg} not restored (expected {expected:#010x}, got {actual:#010x})"
+            )?,
+            AbiViolation::UnbalancedStack { expected, actual } => write!(
+                f,
+                "unbalanced stack on return (expected r{SP} = {expected}, got {actual})"
+            )?,
+            AbiViolation::UnknownReturnAddress { addr } => {
+                write!(f, "return to {addr:04} which has not been pushed by a call")?;
+            }
+        }
+        write!(f, "\n  backtrace: {}", self.backtrace)
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Frame {
+    return_addr: u32,
+    /// Stack pointer once the return address has been pushed
+    sp: u32,
+    saved: [u32; CALLEE_SAVED.len()],
+}
+
+#[derive(Clone)]
+pub(super) struct AbiChecker {
+    frames: [Frame; MAX_CALL_DEPTH],
+    depth: usize,
+    /// Calls made while `frames` was full
+    untracked: usize,
+}
+
+impl AbiChecker {
+    pub(super) fn new() -> Self {
+        Self {
+            frames: [Frame::default(); MAX_CALL_DEPTH],
+            depth: 0,
+            untracked: 0,
+        }
+    }
+
+    fn frames(&self) -> impl Iterator<Item = &Frame> {
+        self.frames[..self.depth].iter().rev()
+    }
+}
+
+impl Machine {
+    /// Enable or disable the calling convention checks. When enabled,
+    /// a violation stops the execution with [`Error::AbiViolation`]
+    /// once the offending instruction has been executed.
+    ///
+    /// Enabling the checks while calls are in progress will report their
+    /// returns as violations.
+    pub fn check_abi(&mut self, enable: bool) {
+        self.abi = enable.then(AbiChecker::new);
+    }
+
+    /// Active calls tracked by the calling convention checker, starting
+    /// with the current IP, or `None` if the checks are disabled.
+    #[must_use]
+    pub fn abi_backtrace(&self) -> Option<Backtrace> {
+        self.abi_backtrace_at(self.registers[IP])
+    }
+
+    fn abi_backtrace_at(&self, ip: u32) -> Option<Backtrace> {
+        let abi = self.abi.as_ref()?;
+        let mut backtrace = Backtrace::new(ip);
+        for frame in abi.frames() {
+            backtrace.push(frame.return_addr);
+        }
+        Some(backtrace)
+    }
+
+    /// Update the calling convention checker after `instruction`, located at
+    /// `instruction_ip`, has been executed. `zero` is the value of r1 before
+    /// the execution.
+    pub(super) fn check_abi_after(&mut self, instruction: Instruction, zero: u32) -> Result<()> {
+        let Some(backtrace) = self.abi_backtrace_at(self.instruction_ip) else {
+            return Ok(());
+        };
+        let violation = self.update_abi(instruction, zero);
+        match violation {
+            Some(violation) => Err(Error::AbiViolation(AbiDiagnostic {
+                ip: self.instruction_ip,
+                violation,
+                backtrace,
+            })),
+            None => Ok(()),
+        }
+    }
+
+    fn update_abi(&mut self, instruction: Instruction, zero: u32) -> Option<AbiViolation> {
+        let fallthrough = self.instruction_ip + instruction.size();
+        let (ip, sp) = (self.registers[IP], self.registers[SP]);
+        let saved = CALLEE_SAVED.map(|r| self.registers[r]);
+        let top = self.get_memory_u32(sp).ok();
+        let abi = self.abi.as_mut()?;
+        if let Instruction::Load { target: IP, .. } = instruction {
+            if abi.untracked > 0 {
+                abi.untracked -= 1;
+                return None;
+            }
+            // Returning to an outer frame discards the inner ones.
+            let Some(index) = abi.frames[..abi.depth]
+                .iter()
+                .rposition(|f| f.return_addr == ip)
+            else {
+                return Some(AbiViolation::UnknownReturnAddress { addr: ip });
+            };
+            abi.depth = index;
+            let frame = abi.frames[index];
+            let expected = frame.sp.wrapping_add(4);
+            if sp != expected {
+                return Some(AbiViolation::UnbalancedStack {
+                    expected,
+                    actual: sp,
+                });
+            }
+            if let Some(i) = (0..saved.len()).find(|&i| saved[i] != frame.saved[i]) {
+                return Some(AbiViolation::CalleeSavedNotRestored {
+                    reg: CALLEE_SAVED[i],
+                    expected: frame.saved[i],
+                    actual: saved[i],
+                });
+            }
+        } else if ip != fallthrough && top == Some(fallthrough) {
+            // Jump with the address of the next instruction on top of the
+            // stack: this is a call.
+            if abi.depth < MAX_CALL_DEPTH {
+                abi.frames[abi.depth] = Frame {
+                    return_addr: fallthrough,
+                    sp,
+                    saved,
+                };
+                abi.depth += 1;
+            } else {
+                abi.untracked += 1;
+            }
+        }
+        (self.registers[ZERO] != zero).then_some(AbiViolation::ZeroRegisterModified {
+            value: self.registers[ZERO],
+        })
+    }
+}
@@ -0,0 +1,110 @@
+//! Cooperative runner for async hosts such as embassy executors: the machine
+//! executes a batch of instructions, then yields to the executor. Output is
+//! sent to an [`AsyncOutput`] sink after every instruction.
+//!
+//! Dropping the future returned by [`Machine::run_async`] cancels the run.
+//! Every instruction is either fully executed or not started, so the machine
+//! can be resumed later, but output still waiting to be written by the sink
+//! when cancelling is lost.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use super::{Machine, Result, Write};
+
+/// Largest output of a single instruction, a float printed in full.
+const MAX_OUTPUT: usize = 64;
+
+/// Asynchronous destination for the output of a program.
+pub trait AsyncOutput {
+    /// Write all of `bytes`.
+    fn write_all(&mut self, bytes: &[u8]) -> impl Future<Output = Result<()>>;
+}
+
+/// Output buffer for a single instruction.
+struct StepOutput {
+    buffer: [u8; MAX_OUTPUT],
+    len: usize,
+}
+
+impl StepOutput {
+    fn push(&mut self, bytes: &[u8]) -> Option<()> {
+        let end = self.len.checked_add(bytes.len())?;
+        self.buffer.get_mut(self.len..end)?.copy_from_slice(bytes);
+        self.len = end;
+        Some(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Write for StepOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.push(buf).ok_or(std::io::ErrorKind::WriteZero)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl Write for StepOutput {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.push(s.as_bytes()).ok_or(core::fmt::Error)
+    }
+}
+
+/// Future returning `Pending` once, after asking to be polled again.
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+impl Machine {
+    /// Run until the program terminates or until an error happens, yielding
+    /// to the executor every `batch` instructions (at least one).
+    ///
+    /// # Errors
+    /// This function returns the first error encountered while executing an
+    /// instruction or writing its output.
+    pub async fn run_async<O: AsyncOutput>(&mut self, out: &mut O, batch: usize) -> Result<()> {
+        loop {
+            for _ in 0..batch.max(1) {
+                let mut output = StepOutput {
+                    buffer: [0; MAX_OUTPUT],
+                    len: 0,
+                };
+                let done = self.step_on(&mut output)?;
+                if output.len > 0 {
+                    out.write_all(&output.buffer[..output.len]).await?;
+                }
+                if done {
+                    return Ok(());
+                }
+            }
+            YieldNow(false).await;
+        }
+    }
+}
+
+// Convenience sink collecting the output, mostly useful for tests.
+#[cfg(feature = "std")]
+impl AsyncOutput for std::vec::Vec<u8> {
+    async fn write_all(&mut self, bytes: &[u8]) -> Result<()> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+}
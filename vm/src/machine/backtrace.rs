@@ -0,0 +1,146 @@
+//! Call chains, from the innermost frame to the outermost one.
+//!
+//! Calls push their return address on the stack before jumping to the
+//! called function, so a backtrace can be reconstructed by looking for
+//! words located above SP (r2) which point right after a jump.
+
+use core::{fmt, iter};
+
+use super::{Error, Instruction, Machine, Result, Write, IP, MEMORY_SIZE, PAGE_SIZE, SP};
+
+/// Maximum number of frames kept in a backtrace.
+pub const MAX_FRAMES: usize = 16;
+
+/// A backtrace holds the address being executed in the innermost frame,
+/// followed by the return addresses of the enclosing frames.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Backtrace {
+    frames: [u32; MAX_FRAMES],
+    len: usize,
+    truncated: bool,
+}
+
+impl Backtrace {
+    pub(super) fn new(ip: u32) -> Self {
+        let mut backtrace = Self {
+            frames: [0; MAX_FRAMES],
+            len: 0,
+            truncated: false,
+        };
+        backtrace.push(ip);
+        backtrace
+    }
+
+    /// Add an outer frame, or mark the backtrace as truncated if it is full.
+    pub(super) fn push(&mut self, addr: u32) {
+        if self.len < MAX_FRAMES {
+            self.frames[self.len] = addr;
+            self.len += 1;
+        } else {
+            self.truncated = true;
+        }
+    }
+
+    /// Frame addresses, innermost first.
+    #[must_use]
+    pub fn frames(&self) -> &[u32] {
+        &self.frames[..self.len]
+    }
+
+    /// `true` if outer frames did not fit in the backtrace.
+    #[must_use]
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+}
+
+impl fmt::Debug for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.frames()).finish()
+    }
+}
+
+impl fmt::Display for Backtrace {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, addr) in self.frames().iter().enumerate() {
+            if i > 0 {
+                write!(f, " <- ")?;
+            }
+            write!(f, "{addr:04}")?;
+        }
+        if self.truncated {
+            write!(f, " <- ...")?;
+        }
+        Ok(())
+    }
+}
+
+/// Error which stopped the execution, along with the backtrace of the
+/// machine at the time.
+#[derive(Debug)]
+pub struct Fault {
+    pub error: Error,
+    pub backtrace: Backtrace,
+}
+
+impl Machine {
+    /// Reconstruct the call chain leading to the current IP.
+    #[must_use]
+    pub fn backtrace(&self) -> Backtrace {
+        self.backtrace_from(self.registers[IP])
+    }
+
+    fn backtrace_from(&self, ip: u32) -> Backtrace {
+        let mut backtrace = Backtrace::new(ip);
+        let sp = self.registers[SP];
+        // The stack may live anywhere in the virtual address space
+        let end = self.page_table.map_or(MEMORY_SIZE, |table| {
+            table.pages as usize * PAGE_SIZE as usize
+        });
+        let stack = iter::successors(Some(sp), |addr| addr.checked_add(4))
+            .take_while(|&addr| addr as usize + 4 <= end);
+        for word in stack.filter_map(|addr| self.get_memory_u32(addr).ok()) {
+            if self.is_return_address(word) {
+                backtrace.push(word);
+            }
+        }
+        backtrace
+    }
+
+    /// Check whether `addr` immediately follows an instruction setting IP,
+    /// reading the code through the MMU.
+    /// Saved registers may look like return addresses, so when the target
+    /// of the jump is known it must hold a valid instruction as well.
+    fn is_return_address(&self, addr: u32) -> bool {
+        [3, 4, 6].into_iter().any(|size| {
+            let Some(Ok(instruction)) = addr.checked_sub(size).map(|start| self.fetch(start))
+            else {
+                return false;
+            };
+            let target = match instruction {
+                _ if instruction.size() != size => return false,
+                Instruction::MoveIf { target: IP, .. } | Instruction::JumpRelIf { .. } => {
+                    return true;
+                }
+                Instruction::LoadImm { target: IP, value } => value.cast_unsigned(),
+                Instruction::LoadImm32 { target: IP, value } => value,
+                Instruction::JumpRel { offset } => addr.wrapping_add_signed(offset),
+                _ => return false,
+            };
+            self.fetch(target).is_ok()
+        })
+    }
+
+    /// Similar to [`run_on`](Machine::run_on), but attach the backtrace
+    /// leading to the instruction which caused an error.
+    ///
+    /// # Errors
+    /// See [`run_on`](Machine::run_on).
+    #[allow(clippy::result_large_err)]
+    pub fn run_traced_on<T: Write>(&mut self, fd: &mut T) -> Result<(), Fault> {
+        self.run_on(fd).map_err(|error| Fault {
+            error,
+            backtrace: self.backtrace_from(self.instruction_ip),
+        })
+    }
+}
@@ -0,0 +1,102 @@
+//! Alternative execution engine keeping decoded instructions in a cache
+//! instead of decoding them again every time they are executed. Stores
+//! invalidate the cached instructions they overlap, and other writes such as
+//! the ones of host calls flush the whole cache, so self-modifying code
+//! behaves as with [`Machine::step_on`]. Nothing is cached while paging is
+//! enabled.
+
+use super::{Instruction, Machine, Result, Write, IP, MEMORY_SIZE};
+
+pub struct CachedMachine {
+    machine: Machine,
+    cache: Vec<Option<Instruction>>,
+    /// Memory generation of the machine the cache is valid for
+    generation: u32,
+}
+
+impl CachedMachine {
+    /// Wrap `machine`, whose state is kept.
+    #[must_use]
+    pub fn new(machine: Machine) -> Self {
+        Self {
+            generation: machine.generation,
+            machine,
+            cache: vec![None; MEMORY_SIZE],
+        }
+    }
+
+    /// Reference onto the underlying machine.
+    #[must_use]
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// Return the underlying machine.
+    #[must_use]
+    pub fn into_machine(self) -> Machine {
+        self.machine
+    }
+
+    /// Sets a register to the given value.
+    ///
+    /// # Errors
+    /// See [`Machine::set_reg`].
+    pub fn set_reg(&mut self, reg: usize, value: u32) -> Result<()> {
+        self.machine.set_reg(reg, value)
+    }
+
+    /// Same as [`Machine::step_on`].
+    ///
+    /// # Errors
+    /// See [`Machine::step_on`].
+    pub fn step_on<T: Write>(&mut self, fd: &mut T) -> Result<bool> {
+        let machine = &mut self.machine;
+        // The cache is indexed by address, which would require flushing it
+        // whenever the page table changes
+        if machine.page_table().is_some() {
+            return machine.step_on(fd);
+        }
+        if self.generation != machine.generation {
+            self.generation = machine.generation;
+            self.cache.fill(None);
+        }
+        machine.deliver_interrupt()?;
+        machine.instruction_ip = machine.registers[IP];
+        let ip = machine.registers[IP] as usize;
+        let instruction = if let Some(instruction) = self.cache.get(ip).copied().flatten() {
+            instruction
+        } else {
+            match Instruction::try_from(machine.memory.get(ip..).unwrap_or_default()) {
+                Ok(instruction) => {
+                    self.cache[ip] = Some(instruction);
+                    instruction
+                }
+                Err(e) => return machine.trap_fault(Err(e)),
+            }
+        };
+        let store = match instruction {
+            Instruction::Store { target: addr, .. }
+            | Instruction::CompareAndSwap { source: addr, .. }
+            | Instruction::FetchAdd { source: addr, .. } => Some(machine.registers[addr] as usize),
+            _ => None,
+        };
+        let result = machine.execute_decoded(instruction, fd);
+        if let Some(addr) = store {
+            let start = addr.saturating_sub(Instruction::MAX_SIZE - 1);
+            let end = addr.saturating_add(4).min(MEMORY_SIZE);
+            for entry in self.cache.get_mut(start..end).unwrap_or_default() {
+                *entry = None;
+            }
+        }
+        machine.trap_fault(result)
+    }
+
+    /// Same as [`Machine::run_on`].
+    ///
+    /// # Errors
+    /// See [`Machine::run_on`].
+    pub fn run_on<T: Write>(&mut self, fd: &mut T) -> Result<()> {
+        while !self.step_on(fd)? {}
+        Ok(())
+    }
+}
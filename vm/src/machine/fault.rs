@@ -0,0 +1,91 @@
+//! Exception mode: instead of stopping the machine, faults transfer control
+//! to a handler written in VM code, which can report them, recover, or
+//! emulate missing instructions.
+//!
+//! When entering the handler, `FAULT_IP_REG` holds the address of the
+//! faulting instruction, `FAULT_CAUSE_REG` the [`FaultCause`] code and
+//! `FAULT_VALUE_REG` the opcode, register number or memory address at
+//! fault. Those registers are overwritten without being saved. The handler
+//! resumes the program by jumping to the address of its choice. The handler
+//! runs in supervisor mode.
+
+use super::{Error, Machine, Result, IP, MEMORY_SIZE};
+
+pub const FAULT_IP_REG: usize = 13;
+pub const FAULT_CAUSE_REG: usize = 14;
+pub const FAULT_VALUE_REG: usize = 15;
+
+/// Faults which can be handled by VM code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FaultCause {
+    /// The value is the opcode
+    UnknownOpcode = 1,
+    /// The value is the register number
+    InvalidRegister = 2,
+    /// The value is the address, which is the end of memory when an
+    /// instruction is truncated
+    InvalidMemoryAddress = 3,
+    /// The value is the virtual address
+    PageFault = 4,
+    /// The value is the address of the instruction
+    PrivilegedInstruction = 5,
+    /// The value is the address
+    PrivilegedMemory = 6,
+}
+
+impl FaultCause {
+    /// Cause and value corresponding to `error`, if it can be handled.
+    #[must_use]
+    pub fn of(error: &Error) -> Option<(Self, u32)> {
+        match *error {
+            Error::UnknownOpcode(opcode) => Some((Self::UnknownOpcode, u32::from(opcode))),
+            Error::InvalidRegister(reg) => Some((
+                Self::InvalidRegister,
+                u32::try_from(reg).unwrap_or(u32::MAX),
+            )),
+            Error::InvalidMemoryAddress(addr) => Some((Self::InvalidMemoryAddress, addr)),
+            Error::PageFault(addr) => Some((Self::PageFault, addr)),
+            Error::PrivilegedInstruction(addr) => Some((Self::PrivilegedInstruction, addr)),
+            Error::PrivilegedMemory(addr) => Some((Self::PrivilegedMemory, addr)),
+            Error::ReadPastMemoryEnd => Some((
+                Self::InvalidMemoryAddress,
+                u32::try_from(MEMORY_SIZE).unwrap_or(u32::MAX),
+            )),
+            _ => None,
+        }
+    }
+
+    /// Code stored in `FAULT_CAUSE_REG`.
+    #[must_use]
+    pub fn code(self) -> u32 {
+        self as u32
+    }
+}
+
+impl Machine {
+    /// Jump to `handler` on faults instead of returning an error, or go back
+    /// to returning errors.
+    pub fn set_fault_handler(&mut self, handler: Option<u32>) {
+        self.fault_handler = handler;
+    }
+
+    /// Enter the fault handler if `result` is a fault which can be handled.
+    /// A fault on the first instruction of the handler is returned as is, to
+    /// avoid looping forever.
+    pub(super) fn trap_fault(&mut self, result: Result<bool>) -> Result<bool> {
+        let Err(error) = &result else {
+            return result;
+        };
+        match (self.fault_handler, FaultCause::of(error)) {
+            (Some(handler), Some((cause, value))) if self.instruction_ip != handler => {
+                self.registers[FAULT_IP_REG] = self.instruction_ip;
+                self.registers[FAULT_CAUSE_REG] = cause.code();
+                self.registers[FAULT_VALUE_REG] = value;
+                self.registers[IP] = handler;
+                self.user_mode = false;
+                Ok(false)
+            }
+            _ => result,
+        }
+    }
+}
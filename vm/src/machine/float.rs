@@ -0,0 +1,159 @@
+//! Optional IEEE-754 single-precision extension. Registers keep holding
+//! `u32` values, the instructions below reinterpret them as `f32` bit
+//! patterns.
+
+use super::{Error, Instruction, Result, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FloatInstruction {
+    Add {
+        target: usize,
+        op1: usize,
+        op2: usize,
+    },
+    Sub {
+        target: usize,
+        op1: usize,
+        op2: usize,
+    },
+    Mul {
+        target: usize,
+        op1: usize,
+        op2: usize,
+    },
+    Div {
+        target: usize,
+        op1: usize,
+        op2: usize,
+    },
+    /// Store -1, 0 or 1 into `target` depending on whether `op1` is lower,
+    /// equal or greater than `op2`, or 2 if they are unordered (one of them
+    /// is a NaN).
+    Cmp {
+        target: usize,
+        op1: usize,
+        op2: usize,
+    },
+    FromInt {
+        target: usize,
+        source: usize,
+    },
+    ToInt {
+        target: usize,
+        source: usize,
+    },
+    Out {
+        reg: usize,
+    },
+}
+
+impl FloatInstruction {
+    pub(super) const FIRST_OPCODE: u8 = 9;
+    pub(super) const LAST_OPCODE: u8 = 16;
+
+    pub(super) fn decode(op: u8, byte: impl Fn(usize) -> Result<u8>) -> Result<Self> {
+        let reg = |index| Instruction::to_reg(byte(index)?);
+        let instruction = match op {
+            9 => Self::Add {
+                target: reg(1)?,
+                op1: reg(2)?,
+                op2: reg(3)?,
+            },
+            10 => Self::Sub {
+                target: reg(1)?,
+                op1: reg(2)?,
+                op2: reg(3)?,
+            },
+            11 => Self::Mul {
+                target: reg(1)?,
+                op1: reg(2)?,
+                op2: reg(3)?,
+            },
+            12 => Self::Div {
+                target: reg(1)?,
+                op1: reg(2)?,
+                op2: reg(3)?,
+            },
+            13 => Self::Cmp {
+                target: reg(1)?,
+                op1: reg(2)?,
+                op2: reg(3)?,
+            },
+            14 => Self::FromInt {
+                target: reg(1)?,
+                source: reg(2)?,
+            },
+            15 => Self::ToInt {
+                target: reg(1)?,
+                source: reg(2)?,
+            },
+            16 => Self::Out { reg: reg(1)? },
+            o => return Err(Error::UnknownOpcode(o)),
+        };
+        Ok(instruction)
+    }
+
+    pub(super) fn size(self) -> u32 {
+        match self {
+            Self::Add { .. }
+            | Self::Sub { .. }
+            | Self::Mul { .. }
+            | Self::Div { .. }
+            | Self::Cmp { .. } => 4,
+            Self::FromInt { .. } | Self::ToInt { .. } => 3,
+            Self::Out { .. } => 2,
+        }
+    }
+
+    pub(super) fn encode(self, out: &mut [u8]) -> Result<()> {
+        let r = Instruction::from_reg;
+        match self {
+            Self::Add { target, op1, op2 } => {
+                out.copy_from_slice(&[9, r(target)?, r(op1)?, r(op2)?]);
+            }
+            Self::Sub { target, op1, op2 } => {
+                out.copy_from_slice(&[10, r(target)?, r(op1)?, r(op2)?]);
+            }
+            Self::Mul { target, op1, op2 } => {
+                out.copy_from_slice(&[11, r(target)?, r(op1)?, r(op2)?]);
+            }
+            Self::Div { target, op1, op2 } => {
+                out.copy_from_slice(&[12, r(target)?, r(op1)?, r(op2)?]);
+            }
+            Self::Cmp { target, op1, op2 } => {
+                out.copy_from_slice(&[13, r(target)?, r(op1)?, r(op2)?]);
+            }
+            Self::FromInt { target, source } => out.copy_from_slice(&[14, r(target)?, r(source)?]),
+            Self::ToInt { target, source } => out.copy_from_slice(&[15, r(target)?, r(source)?]),
+            Self::Out { reg } => out.copy_from_slice(&[16, r(reg)?]),
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    pub(super) fn execute<T: Write>(self, registers: &mut [u32], fd: &mut T) -> Result<()> {
+        let f = |r: usize| f32::from_bits(registers[r]);
+        match self {
+            Self::Add { target, op1, op2 } => registers[target] = (f(op1) + f(op2)).to_bits(),
+            Self::Sub { target, op1, op2 } => registers[target] = (f(op1) - f(op2)).to_bits(),
+            Self::Mul { target, op1, op2 } => registers[target] = (f(op1) * f(op2)).to_bits(),
+            Self::Div { target, op1, op2 } => registers[target] = (f(op1) / f(op2)).to_bits(),
+            Self::Cmp { target, op1, op2 } => {
+                registers[target] = match f(op1).partial_cmp(&f(op2)) {
+                    Some(ordering) => (ordering as i32).cast_unsigned(),
+                    None => 2,
+                };
+            }
+            Self::FromInt { target, source } => {
+                registers[target] = (registers[source].cast_signed() as f32).to_bits();
+            }
+            Self::ToInt { target, source } => {
+                registers[target] = (f(source) as i32).cast_unsigned();
+            }
+            Self::Out { reg } => {
+                write!(fd, "{}", f(reg)).map_err(|_| Error::OutputError)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+//! Framebuffer peripheral for the 8x8 RGB LED matrix driven by
+//! `tp_led_matrix`. The framebuffer is a range of `FRAMEBUFFER_SIZE` bytes
+//! of the machine memory with the same layout as `tp_led_matrix::Image`:
+//! pixels in row-major order, each made of its red, green and blue bytes, so
+//! that `image.as_mut().copy_from_slice(framebuffer)` displays it.
+//!
+//! The word following the framebuffer is the present register: storing any
+//! value there stops the execution with [`Error::FramePresented`] once the
+//! instruction is complete, letting the host pick the frame up before
+//! resuming.
+
+use super::{Error, Machine, Result, MEMORY_SIZE};
+
+/// Size of the framebuffer, 8 rows of 8 pixels of 3 bytes.
+pub const FRAMEBUFFER_SIZE: usize = 192;
+
+/// Marker starting a frame in the serial protocol of `tp_led_matrix`.
+pub const FRAME_START: u8 = 0xff;
+
+impl Machine {
+    /// Map the framebuffer at `base`, and its present register right after.
+    ///
+    /// # Errors
+    /// This function returns an error if the framebuffer and its present
+    /// register do not fit in memory.
+    pub fn map_framebuffer(&mut self, base: u32) -> Result<()> {
+        if base as usize + FRAMEBUFFER_SIZE + 4 > MEMORY_SIZE {
+            return Err(Error::InvalidMemoryAddress(base));
+        }
+        self.framebuffer = Some(base);
+        Ok(())
+    }
+
+    /// Remove the framebuffer mapping. The memory keeps its content.
+    pub fn unmap_framebuffer(&mut self) {
+        self.framebuffer = None;
+    }
+
+    /// Current content of the framebuffer if it is mapped.
+    #[must_use]
+    pub fn framebuffer(&self) -> Option<&[u8; FRAMEBUFFER_SIZE]> {
+        let base = self.framebuffer? as usize;
+        self.memory[base..base + FRAMEBUFFER_SIZE].try_into().ok()
+    }
+
+    /// Check whether the word stored at `addr` touches the present register.
+    pub(super) fn check_present(&self, addr: u32) -> Result<()> {
+        match self.framebuffer {
+            Some(base) if (base as usize + FRAMEBUFFER_SIZE).abs_diff(addr as usize) < 4 => {
+                Err(Error::FramePresented)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Encode a frame for the serial protocol of `tp_led_matrix`: a
+/// [`FRAME_START`] marker followed by the pixels, where the marker value is
+/// replaced by the closest value.
+#[must_use]
+pub fn serial_frame(framebuffer: &[u8; FRAMEBUFFER_SIZE]) -> [u8; FRAMEBUFFER_SIZE + 1] {
+    let mut frame = [FRAME_START; FRAMEBUFFER_SIZE + 1];
+    for (dest, &b) in frame[1..].iter_mut().zip(framebuffer) {
+        *dest = b.min(FRAME_START - 1);
+    }
+    frame
+}
+
+/// Render a frame as text for host-side tests: one line per row, each pixel
+/// being written as `rrggbb` in hexadecimal.
+#[cfg(feature = "std")]
+#[must_use]
+pub fn render_frame(framebuffer: &[u8; FRAMEBUFFER_SIZE]) -> String {
+    use std::fmt::Write;
+
+    let mut text = String::new();
+    for row in framebuffer.chunks(24) {
+        let pixels = row
+            .chunks(3)
+            .map(|p| format!("{:02x}{:02x}{:02x}", p[0], p[1], p[2]))
+            .collect::<Vec<_>>();
+        writeln!(text, "{}", pixels.join(" ")).unwrap();
+    }
+    text
+}
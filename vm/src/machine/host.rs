@@ -0,0 +1,183 @@
+//! Host calls: the `trap` instruction calls the Rust handler registered for
+//! its service number, giving VM code access to host facilities such as
+//! files, clocks or logs. Handlers are plain function pointers so that no
+//! allocation is needed.
+
+use core::ops::Range;
+
+use super::{protect::Access, Error, Machine, Result};
+
+/// Maximum number of services which can be registered at the same time.
+pub const MAX_SERVICES: usize = 16;
+
+/// Handler of a service. It returns `true` to stop the machine as if the
+/// program had exited.
+pub type HostHandler = fn(&mut HostCall) -> Result<bool>;
+
+/// Access to the machine given to a handler during a host call.
+pub struct HostCall<'a> {
+    machine: &'a mut Machine,
+    service: u8,
+}
+
+impl HostCall<'_> {
+    /// Service number of the `trap` instruction.
+    #[must_use]
+    pub fn service(&self) -> u8 {
+        self.service
+    }
+
+    /// Address of the `trap` instruction. The IP register already points
+    /// after it.
+    #[must_use]
+    pub fn ip(&self) -> u32 {
+        self.machine.instruction_ip
+    }
+
+    /// Value of a register.
+    ///
+    /// # Errors
+    /// This function returns an error if the register does not exist.
+    pub fn reg(&self, reg: usize) -> Result<u32> {
+        self.machine
+            .registers
+            .get(reg)
+            .copied()
+            .ok_or(Error::InvalidRegister(reg))
+    }
+
+    /// Set a register.
+    ///
+    /// # Errors
+    /// This function returns an error if the register does not exist.
+    pub fn set_reg(&mut self, reg: usize, value: u32) -> Result<()> {
+        self.machine.set_reg(reg, value)
+    }
+
+    /// Physical address of the byte at the virtual address `vaddr`.
+    fn physical(&self, vaddr: u32, access: Access) -> Result<usize> {
+        Machine::get_memory_address(self.machine.translate(vaddr, access)?)
+    }
+
+    /// Virtual addresses of the `len` bytes at `addr`, once checked that
+    /// they can all be accessed by the program.
+    fn checked_range(&self, addr: u32, len: usize, access: Access) -> Result<Range<u32>> {
+        let end = u32::try_from(len)
+            .ok()
+            .and_then(|len| addr.checked_add(len))
+            .ok_or(Error::InvalidMemoryAddress(addr))?;
+        self.machine.check_access(addr, end - addr, access)?;
+        for vaddr in addr..end {
+            // Report the range rather than the byte exceeding the memory
+            self.physical(vaddr, access).map_err(|e| match e {
+                Error::InvalidMemoryAddress(_) => Error::InvalidMemoryAddress(addr),
+                e => e,
+            })?;
+        }
+        Ok(addr..end)
+    }
+
+    /// Memory range starting at `addr`, which must not span pages mapped
+    /// to distinct frames.
+    ///
+    /// # Errors
+    /// This function returns an error if the range exceeds the memory or
+    /// cannot be read by the program.
+    pub fn read(&self, addr: u32, len: usize) -> Result<&[u8]> {
+        if len == 0 {
+            return Ok(&[]);
+        }
+        let range = self.checked_range(addr, len, Access::Read)?;
+        let start = self.physical(addr, Access::Read)?;
+        for (i, vaddr) in (0..).zip(range) {
+            if self.physical(vaddr, Access::Read)? != start + i {
+                return Err(Error::PageFault(vaddr));
+            }
+        }
+        Ok(&self.machine.memory[start..start + len])
+    }
+
+    /// Copy `bytes` into memory at `addr`. Nothing is written if a byte
+    /// cannot be.
+    ///
+    /// # Errors
+    /// This function returns an error if the range exceeds the memory or
+    /// cannot be written by the program.
+    pub fn write(&mut self, addr: u32, bytes: &[u8]) -> Result<()> {
+        let range = self.checked_range(addr, bytes.len(), Access::Write)?;
+        for (vaddr, &byte) in range.zip(bytes) {
+            let paddr = self.physical(vaddr, Access::Write)?;
+            self.machine.memory[paddr] = byte;
+            self.machine.initialized.mark(paddr..paddr + 1);
+        }
+        self.machine.generation = self.machine.generation.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Word at `addr`.
+    ///
+    /// # Errors
+    /// This function returns an error if the word exceeds the memory or
+    /// cannot be read by the program.
+    pub fn read_u32(&self, addr: u32) -> Result<u32> {
+        self.machine.check_access(addr, 4, Access::Read)?;
+        self.machine.get_memory_u32(addr)
+    }
+
+    /// Store a word at `addr`.
+    ///
+    /// # Errors
+    /// This function returns an error if the word exceeds the memory or
+    /// cannot be written by the program.
+    pub fn write_u32(&mut self, addr: u32, value: u32) -> Result<()> {
+        self.write(addr, &value.to_le_bytes())
+    }
+}
+
+impl Machine {
+    /// Call `handler` when a `trap` instruction with the given service number
+    /// is executed, replacing any previous handler for this service.
+    ///
+    /// # Errors
+    /// This function returns an error if `MAX_SERVICES` other services are
+    /// already registered.
+    pub fn register_service(&mut self, service: u8, handler: HostHandler) -> Result<()> {
+        let slot = match self
+            .services
+            .iter()
+            .position(|s| s.is_some_and(|(n, _)| n == service))
+        {
+            Some(index) => &mut self.services[index],
+            None => self
+                .services
+                .iter_mut()
+                .find(|s| s.is_none())
+                .ok_or(Error::TooManyServices)?,
+        };
+        *slot = Some((service, handler));
+        Ok(())
+    }
+
+    /// Remove the handler of a service. Return `true` if there was one.
+    pub fn unregister_service(&mut self, service: u8) -> bool {
+        let slot = self
+            .services
+            .iter_mut()
+            .find(|s| s.is_some_and(|(n, _)| n == service));
+        slot.map(Option::take).is_some()
+    }
+
+    pub(super) fn host_call(&mut self, service: u8) -> Result<bool> {
+        let (_, handler) = self
+            .services
+            .iter()
+            .flatten()
+            .find(|(n, _)| *n == service)
+            .copied()
+            .ok_or(Error::UnknownService(service))?;
+        handler(&mut HostCall {
+            machine: self,
+            service,
+        })
+    }
+}
@@ -0,0 +1,177 @@
+//! Interrupt controller and timer device.
+//!
+//! Interrupt lines are numbered from 0 to `MAX_IRQS - 1`. When interrupts
+//! are enabled and a line is pending, the controller saves the IP and the
+//! interrupt enable flag, disables interrupts and jumps to the handler whose
+//! address is the word at `vector_table + 4 * line` before the next
+//! instruction. The `reti` instruction restores the saved state. Handlers
+//! are not nested, and must save the registers they use.
+//!
+//! The timer is a memory-mapped device made of a single word, its period. A
+//! non-zero period raises [`TIMER_IRQ`] every `period` executed
+//! instructions, counted from the moment the period is written, so that the
+//! execution is deterministic.
+
+use core::ops::Range;
+
+use super::{Error, Machine, Result, IP, MEMORY_SIZE};
+
+/// Number of interrupt lines.
+pub const MAX_IRQS: u8 = 8;
+
+/// Interrupt line used by the timer.
+pub const TIMER_IRQ: u8 = 0;
+
+#[derive(Clone, Copy)]
+struct Saved {
+    ip: u32,
+    enabled: bool,
+    user_mode: bool,
+}
+
+#[derive(Clone, Copy)]
+struct Timer {
+    base: u32,
+    /// Instructions to execute before raising the interrupt, 0 when stopped
+    remaining: u32,
+}
+
+/// Interrupt state of a core.
+#[derive(Clone, Copy, Default)]
+pub(super) struct CoreInterrupts {
+    enabled: bool,
+    /// One bit per pending line
+    pending: u8,
+    saved: Option<Saved>,
+}
+
+#[derive(Clone, Default)]
+pub(super) struct InterruptController {
+    vector_table: Option<u32>,
+    timer: Option<Timer>,
+    /// State of the running core, see [`System`](super::System)
+    pub core: CoreInterrupts,
+}
+
+impl Machine {
+    /// Set the address of the vector table, or disable interrupt delivery.
+    pub fn set_vector_table(&mut self, addr: Option<u32>) {
+        self.interrupts.vector_table = addr;
+    }
+
+    /// Mark an interrupt line as pending.
+    ///
+    /// # Errors
+    /// This function returns an error if the line does not exist.
+    pub fn raise_interrupt(&mut self, line: u8) -> Result<()> {
+        if line >= MAX_IRQS {
+            return Err(Error::InvalidInterrupt(line));
+        }
+        self.interrupts.core.pending |= 1 << line;
+        Ok(())
+    }
+
+    /// Check whether interrupts are enabled, as done by the `ei` instruction.
+    #[must_use]
+    pub fn interrupts_enabled(&self) -> bool {
+        self.interrupts.core.enabled
+    }
+
+    /// Check whether an interrupt handler is running.
+    #[must_use]
+    pub fn in_interrupt(&self) -> bool {
+        self.interrupts.core.saved.is_some()
+    }
+
+    /// Map the timer period register at `base`. The timer is stopped until
+    /// a period is written.
+    ///
+    /// # Errors
+    /// This function returns an error if the register does not fit in
+    /// memory.
+    pub fn map_timer(&mut self, base: u32) -> Result<()> {
+        if base as usize + 4 > MEMORY_SIZE {
+            return Err(Error::InvalidMemoryAddress(base));
+        }
+        self.interrupts.timer = Some(Timer { base, remaining: 0 });
+        Ok(())
+    }
+
+    pub(super) fn set_interrupts_enabled(&mut self, enabled: bool) {
+        self.interrupts.core.enabled = enabled;
+    }
+
+    pub(super) fn return_from_interrupt(&mut self) -> Result<()> {
+        let saved = self
+            .interrupts
+            .core
+            .saved
+            .take()
+            .ok_or(Error::NotInInterrupt(self.instruction_ip))?;
+        self.registers[IP] = saved.ip;
+        self.interrupts.core.enabled = saved.enabled;
+        self.user_mode = saved.user_mode;
+        Ok(())
+    }
+
+    /// Jump to the handler of the lowest pending line if interrupts can be
+    /// delivered.
+    pub(super) fn deliver_interrupt(&mut self) -> Result<()> {
+        let Some(vector_table) = self.interrupts.vector_table else {
+            return Ok(());
+        };
+        let core = self.interrupts.core;
+        if !core.enabled || core.pending == 0 || core.saved.is_some() {
+            return Ok(());
+        }
+        let line = core.pending.trailing_zeros();
+        let handler = self.get_physical_u32(vector_table.wrapping_add(4 * line))?;
+        self.interrupts.core.pending &= !(1 << line);
+        self.interrupts.core.saved = Some(Saved {
+            ip: self.registers[IP],
+            enabled: true,
+            user_mode: self.user_mode,
+        });
+        self.interrupts.core.enabled = false;
+        self.user_mode = false;
+        self.registers[IP] = handler;
+        Ok(())
+    }
+
+    /// Physical memory holding the vector table and the timer period.
+    pub(super) fn interrupt_ranges(&self) -> [Option<Range<usize>>; 2] {
+        let interrupts = &self.interrupts;
+        [
+            interrupts
+                .vector_table
+                .map(|base| base as usize..base as usize + 4 * usize::from(MAX_IRQS)),
+            interrupts
+                .timer
+                .map(|t| t.base as usize..t.base as usize + 4),
+        ]
+    }
+
+    /// Restart the timer if the word stored at `addr` touches its period.
+    pub(super) fn check_timer_write(&mut self, addr: u32) {
+        if let Some(timer) = self.interrupts.timer {
+            if timer.base.abs_diff(addr) < 4 {
+                let remaining = self.get_physical_u32(timer.base).unwrap_or_default();
+                self.interrupts.timer = Some(Timer { remaining, ..timer });
+            }
+        }
+    }
+
+    /// Count an executed instruction.
+    pub(super) fn tick_timer(&mut self) {
+        let Some(timer) = self.interrupts.timer.filter(|t| t.remaining > 0) else {
+            return;
+        };
+        let remaining = if timer.remaining == 1 {
+            self.interrupts.core.pending |= 1 << TIMER_IRQ;
+            self.get_physical_u32(timer.base).unwrap_or_default()
+        } else {
+            timer.remaining - 1
+        };
+        self.interrupts.timer = Some(Timer { remaining, ..timer });
+    }
+}
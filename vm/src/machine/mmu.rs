@@ -0,0 +1,130 @@
+//! Optional MMU translating the virtual addresses used by instructions into
+//! physical memory addresses.
+//!
+//! The page table is an array of `pages` words in physical memory, the entry
+//! at index `n` describing the virtual page starting at `n * PAGE_SIZE`. An
+//! entry holds the physical address of the page, which is aligned on
+//! `PAGE_SIZE`, combined with its `PAGE_*` permission bits. An entry without
+//! any permission is not mapped.
+//!
+//! Loads, stores, instruction fetches and host calls are translated. The
+//! loader, the devices and the vector table keep using physical addresses.
+
+use core::ops::Range;
+
+use super::{protect::Access, Error, Instruction, Machine, Result, MEMORY_SIZE};
+
+/// Size of a page, in bytes.
+pub const PAGE_SIZE: u32 = 256;
+
+pub const PAGE_READ: u32 = 1;
+pub const PAGE_WRITE: u32 = 2;
+pub const PAGE_EXECUTE: u32 = 4;
+
+/// Location of the page table, as set in the page-table base register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageTable {
+    /// Physical address of the first entry
+    pub base: u32,
+    /// Number of entries, virtual addresses beyond them are not mapped
+    pub pages: u32,
+}
+
+impl Machine {
+    /// Enable address translation using `table`, or disable it.
+    ///
+    /// # Errors
+    /// This function returns an error if the table does not fit in memory.
+    pub fn set_page_table(&mut self, table: Option<PageTable>) -> Result<()> {
+        if let Some(PageTable { base, pages }) = table {
+            let end = pages.checked_mul(4).and_then(|len| base.checked_add(len));
+            if end.is_none_or(|end| end as usize > MEMORY_SIZE) {
+                return Err(Error::InvalidMemoryAddress(base));
+            }
+        }
+        self.page_table = table;
+        Ok(())
+    }
+
+    /// Current page table, if address translation is enabled.
+    #[must_use]
+    pub fn page_table(&self) -> Option<PageTable> {
+        self.page_table
+    }
+
+    /// Write the page table entry mapping the virtual page containing
+    /// `vaddr` to the physical page at `paddr`, with the given `PAGE_*`
+    /// permissions. Use no permission to unmap the page.
+    ///
+    /// # Errors
+    /// This function returns an error if the virtual page is not covered by
+    /// the page table or if `paddr` is not aligned on a page.
+    pub fn map_page(&mut self, vaddr: u32, paddr: u32, permissions: u32) -> Result<()> {
+        if !paddr.is_multiple_of(PAGE_SIZE) || permissions >= PAGE_SIZE {
+            return Err(Error::InvalidMemoryAddress(paddr));
+        }
+        let entry = self.page_table_entry(vaddr)?;
+        self.load_at(entry, &(paddr | permissions).to_le_bytes(), None)
+    }
+
+    /// Physical memory holding the page table.
+    pub(super) fn page_table_range(&self) -> Option<Range<usize>> {
+        let PageTable { base, pages } = self.page_table?;
+        Some(base as usize..base as usize + 4 * pages as usize)
+    }
+
+    /// Physical address of the page table entry for `vaddr`.
+    fn page_table_entry(&self, vaddr: u32) -> Result<u32> {
+        match self.page_table {
+            Some(PageTable { base, pages }) if vaddr / PAGE_SIZE < pages => {
+                Ok(base + 4 * (vaddr / PAGE_SIZE))
+            }
+            _ => Err(Error::PageFault(vaddr)),
+        }
+    }
+
+    /// Physical address of `vaddr`, checking that `access` is allowed.
+    pub(super) fn translate(&self, vaddr: u32, access: Access) -> Result<u32> {
+        if self.page_table.is_none() {
+            return Ok(vaddr);
+        }
+        let entry = self.get_physical_u32(self.page_table_entry(vaddr)?)?;
+        let permission = match access {
+            Access::Read => PAGE_READ,
+            Access::Write => PAGE_WRITE,
+            Access::Execute => PAGE_EXECUTE,
+        };
+        if entry & permission == 0 {
+            return Err(Error::PageFault(vaddr));
+        }
+        Ok((entry & !(PAGE_SIZE - 1)) | (vaddr % PAGE_SIZE))
+    }
+
+    /// Decode the instruction at `ip`, which may span several pages.
+    pub(super) fn fetch(&self, ip: u32) -> Result<Instruction> {
+        if self.page_table.is_none() {
+            return Instruction::try_from(self.memory.get(ip as usize..).unwrap_or_default());
+        }
+        let mut bytes = [0; Instruction::MAX_SIZE];
+        let mut len = 0;
+        let mut fault = None;
+        for (i, byte) in (0..).zip(&mut bytes) {
+            match self
+                .translate(ip.wrapping_add(i), Access::Execute)
+                .and_then(|paddr| self.get_physical(paddr))
+            {
+                Ok(b) => *byte = b,
+                Err(e) => {
+                    fault = Some(e);
+                    break;
+                }
+            }
+            len += 1;
+        }
+        // A truncated instruction is reported as the fault which truncated it
+        Instruction::try_from(&bytes[..len]).map_err(|e| match (e, fault) {
+            (Error::ReadPastMemoryEnd, Some(fault)) => fault,
+            (e, _) => e,
+        })
+    }
+}
@@ -0,0 +1,101 @@
+//! Supervisor and user privilege levels.
+//!
+//! The machine starts in supervisor mode, where everything is allowed. In
+//! user mode, the `trap`, `ei`, `di`, `reti` and `sysret` instructions are
+//! refused, as well as loads and stores touching the framebuffer, the timer,
+//! the vector table or the page table.
+//!
+//! The `syscall` instruction enters supervisor mode at the system call entry
+//! point, with the address of the next instruction in `FAULT_IP_REG`, and
+//! `sysret rX` enters user mode at the address held by `rX`. Delivering an
+//! interrupt or entering the fault handler also switches to supervisor mode,
+//! so that a kernel written in VM code keeps control over user programs.
+
+use core::ops::Range;
+
+use super::{
+    fault::FAULT_IP_REG, protect::Access, Error, Instruction, Machine, Result, FRAMEBUFFER_SIZE, IP,
+};
+
+impl Machine {
+    /// Switch between user and supervisor mode.
+    pub fn set_user_mode(&mut self, user: bool) {
+        self.user_mode = user;
+    }
+
+    /// Check whether the machine runs in user mode.
+    #[must_use]
+    pub fn user_mode(&self) -> bool {
+        self.user_mode
+    }
+
+    /// Set the address where the `syscall` instruction jumps, or make it
+    /// fail.
+    pub fn set_syscall_entry(&mut self, entry: Option<u32>) {
+        self.syscall_entry = entry;
+    }
+
+    /// Check that `instruction` can be executed in the current mode.
+    pub(super) fn check_privilege(&self, instruction: Instruction) -> Result<()> {
+        let privileged = matches!(
+            instruction,
+            Instruction::Trap { .. }
+                | Instruction::EnableInterrupts
+                | Instruction::DisableInterrupts
+                | Instruction::ReturnFromInterrupt
+                | Instruction::ReturnToUser { .. }
+        );
+        if privileged && self.user_mode {
+            return Err(Error::PrivilegedInstruction(self.instruction_ip));
+        }
+        Ok(())
+    }
+
+    /// Check that the word at `addr` can be accessed in the current mode.
+    /// Translation faults are left to the access itself.
+    pub(super) fn check_user_access(&self, addr: u32, access: Access) -> Result<()> {
+        if !self.user_mode {
+            return Ok(());
+        }
+        let ranges = self.supervisor_ranges();
+        for vaddr in (0..4).map(|i| addr.wrapping_add(i)) {
+            if let Ok(paddr) = self.translate(vaddr, access) {
+                if ranges
+                    .iter()
+                    .flatten()
+                    .any(|r| r.contains(&(paddr as usize)))
+                {
+                    return Err(Error::PrivilegedMemory(addr));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Physical memory reserved to supervisor mode.
+    fn supervisor_ranges(&self) -> [Option<Range<usize>>; 4] {
+        let [vector_table, timer] = self.interrupt_ranges();
+        [
+            self.framebuffer
+                .map(|base| base as usize..base as usize + FRAMEBUFFER_SIZE + 4),
+            vector_table,
+            timer,
+            self.page_table_range(),
+        ]
+    }
+
+    pub(super) fn syscall(&mut self) -> Result<()> {
+        let entry = self
+            .syscall_entry
+            .ok_or(Error::NoSyscallEntry(self.instruction_ip))?;
+        self.registers[FAULT_IP_REG] = self.registers[IP];
+        self.registers[IP] = entry;
+        self.user_mode = false;
+        Ok(())
+    }
+
+    pub(super) fn return_to_user(&mut self, addr: u32) {
+        self.registers[IP] = addr;
+        self.user_mode = true;
+    }
+}
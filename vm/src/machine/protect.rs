@@ -0,0 +1,93 @@
+//! Optional protection map restricting how memory ranges can be accessed.
+
+use core::ops::Range;
+
+use super::{Error, Machine, Result};
+
+/// Maximum number of protected regions.
+pub const MAX_REGIONS: usize = 8;
+
+/// Protection applied to a memory region. Memory outside of any region
+/// can be read, written and executed, unless W^X is enforced.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protection {
+    /// Readable and executable, writes are refused (code)
+    ReadOnly,
+    /// Readable and writable, execution is refused (data, stack)
+    NoExecute,
+    /// Any access is refused (e.g., between the stack and the code)
+    Guard,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Clone, Debug)]
+pub(super) struct Region {
+    range: Range<u32>,
+    protection: Protection,
+}
+
+impl Machine {
+    /// Apply `protection` to `range`. When regions overlap, the most
+    /// recently added one applies.
+    ///
+    /// # Errors
+    /// This function returns an error if `MAX_REGIONS` regions are already
+    /// defined.
+    pub fn protect(&mut self, range: Range<u32>, protection: Protection) -> Result<()> {
+        let slot = self
+            .regions
+            .iter_mut()
+            .find(|r| r.is_none())
+            .ok_or(Error::TooManyRegions)?;
+        *slot = Some(Region { range, protection });
+        Ok(())
+    }
+
+    /// Remove all protected regions.
+    pub fn clear_protection(&mut self) {
+        self.regions = Default::default();
+    }
+
+    /// Enforce W^X: memory outside of any region can no longer be executed,
+    /// so that code has to be mapped [`ReadOnly`](Protection::ReadOnly)
+    /// and no address can be both written and executed.
+    pub fn enforce_wx(&mut self, enforce: bool) {
+        self.enforce_wx = enforce;
+    }
+
+    /// Protection of the byte at `addr`, if any.
+    fn protection(&self, addr: u32) -> Option<Protection> {
+        let region = self.regions.iter().rev().flatten();
+        region
+            .filter(|r| r.range.contains(&addr))
+            .map(|r| r.protection)
+            .next()
+            .or(self.enforce_wx.then_some(Protection::NoExecute))
+    }
+
+    /// Check that the `len` bytes starting at `addr` can be accessed.
+    pub(super) fn check_access(&self, addr: u32, len: u32, access: Access) -> Result<()> {
+        if !self.enforce_wx && self.regions.iter().all(Option::is_none) {
+            return Ok(());
+        }
+        for addr in (0..len).map(|i| addr.wrapping_add(i)) {
+            match (self.protection(addr), access) {
+                (Some(Protection::Guard), _) => return Err(Error::GuardViolation(addr)),
+                (Some(Protection::ReadOnly), Access::Write) => {
+                    return Err(Error::WriteViolation(addr));
+                }
+                (Some(Protection::NoExecute), Access::Execute) => {
+                    return Err(Error::ExecuteViolation(addr));
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+//! Shadow memory recording which bytes have been initialized, either by
+//! the loaded image or by a store, in order to detect reads of
+//! uninitialized memory.
+
+use core::ops::Range;
+
+use super::{protect::Access, Error, Machine, Result, MEMORY_SIZE};
+
+#[derive(Clone)]
+pub(super) struct ShadowMemory([u32; MEMORY_SIZE / 32]);
+
+impl ShadowMemory {
+    pub(super) fn new() -> Self {
+        Self([0; MEMORY_SIZE / 32])
+    }
+
+    pub(super) fn mark(&mut self, range: Range<usize>) {
+        for addr in range {
+            self.0[addr / 32] |= 1 << (addr % 32);
+        }
+    }
+
+    fn is_initialized(&self, addr: usize) -> bool {
+        self.0[addr / 32] & (1 << (addr % 32)) != 0
+    }
+}
+
+impl Machine {
+    /// Enable or disable the detection of uninitialized memory reads. When
+    /// enabled, a load reading at least one byte which has neither been
+    /// loaded as part of the program image nor been stored stops the
+    /// execution with [`Error::UninitializedRead`] once the load has been
+    /// executed.
+    pub fn check_uninitialized(&mut self, enable: bool) {
+        self.check_uninitialized = enable;
+    }
+
+    /// Check that the word at `addr`, which has just been read by the
+    /// current instruction, is fully initialized. Its bytes are translated
+    /// one by one as the word may cross a page boundary.
+    pub(super) fn check_initialized(&self, addr: u32) -> Result<()> {
+        if !self.check_uninitialized {
+            return Ok(());
+        }
+        for vaddr in (0..4).map(|i| addr.wrapping_add(i)) {
+            let paddr = Self::get_memory_address(self.translate(vaddr, Access::Read)?)?;
+            if !self.initialized.is_initialized(paddr) {
+                return Err(Error::UninitializedRead {
+                    ip: self.instruction_ip,
+                    addr: vaddr,
+                });
+            }
+        }
+        Ok(())
+    }
+}
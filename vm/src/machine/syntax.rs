@@ -0,0 +1,266 @@
+//! Textual form of instructions, as found in the `.dis` files written by
+//! `generator.py`, e.g. `move r0 <- r9 if r8 != 0` or `loadimm r3 <- #1`.
+//! Registers may also be written with their names in the calling
+//! convention, e.g. `move ip <- t1 if t0 != 0`.
+
+use core::fmt;
+use core::str::FromStr;
+
+#[cfg(feature = "float")]
+use super::FloatInstruction;
+use super::{Error, Instruction, Result, NREGS};
+
+/// Largest number of whitespace-separated tokens in an instruction.
+const MAX_TOKENS: usize = 8;
+
+/// Names of the registers in the calling convention: the instruction
+/// pointer, the zero register, the stack pointer, the scratch register,
+/// the callee-saved registers, and the caller-saved ones, of which r10 to
+/// r12 hold arguments and results.
+pub const REGISTER_NAMES: [&str; NREGS] = [
+    "ip", "zero", "sp", "tmp", "s0", "s1", "s2", "s3", "t0", "t1", "a0", "a1", "a2", "a3", "a4",
+    "a5",
+];
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::MoveIf {
+                target,
+                source,
+                cond,
+            } => write!(f, "move r{target} <- r{source} if r{cond} != 0"),
+            Self::Store { target, source } => write!(f, "store [r{target}] <- r{source}"),
+            Self::Load { target, source } => write!(f, "load r{target} <- [r{source}]"),
+            Self::LoadImm { target, value } => write!(f, "loadimm r{target} <- #{value}"),
+            Self::LoadImm32 { target, value } => {
+                write!(f, "loadimm32 r{target} <- #{}", value.cast_signed())
+            }
+            Self::Sub { target, op1, op2 } => write!(f, "sub r{target} <- r{op1} - r{op2}"),
+            Self::Out { reg } => write!(f, "out r{reg}"),
+            Self::Exit => write!(f, "exit"),
+            Self::OutNumber { reg } => write!(f, "out_number r{reg}"),
+            Self::JumpRel { offset } => write!(f, "jump_rel ip{offset:+}"),
+            Self::JumpRelIf { cond, offset } => write!(f, "jump_rel ip{offset:+} if r{cond} != 0"),
+            Self::LoadRel { target, offset } => write!(f, "loadrel r{target} <- ip{offset:+}"),
+            Self::Trap { service } => write!(f, "trap #{service}"),
+            Self::EnableInterrupts => write!(f, "ei"),
+            Self::DisableInterrupts => write!(f, "di"),
+            Self::ReturnFromInterrupt => write!(f, "reti"),
+            Self::Syscall => write!(f, "syscall"),
+            Self::ReturnToUser { reg } => write!(f, "sysret r{reg}"),
+            Self::CompareAndSwap {
+                target,
+                source,
+                expected,
+                new,
+            } => write!(f, "cas r{target} <- [r{source}] r{expected} r{new}"),
+            Self::FetchAdd {
+                target,
+                source,
+                value,
+            } => write!(f, "fetch_add r{target} <- [r{source}] + r{value}"),
+            Self::CoreId { target } => write!(f, "coreid r{target}"),
+            Self::Spawn { target, entry } => write!(f, "spawn r{target} <- r{entry}"),
+            Self::Yield => write!(f, "yield"),
+            Self::Join { reg } => write!(f, "join r{reg}"),
+            #[cfg(feature = "float")]
+            Self::Float(instruction) => instruction.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "float")]
+impl fmt::Display for FloatInstruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::Add { target, op1, op2 } => write!(f, "fadd r{target} <- r{op1} + r{op2}"),
+            Self::Sub { target, op1, op2 } => write!(f, "fsub r{target} <- r{op1} - r{op2}"),
+            Self::Mul { target, op1, op2 } => write!(f, "fmul r{target} <- r{op1} * r{op2}"),
+            Self::Div { target, op1, op2 } => write!(f, "fdiv r{target} <- r{op1} / r{op2}"),
+            Self::Cmp { target, op1, op2 } => write!(f, "fcmp r{target} <- r{op1} <=> r{op2}"),
+            Self::FromInt { target, source } => write!(f, "itof r{target} <- r{source}"),
+            Self::ToInt { target, source } => write!(f, "ftoi r{target} <- r{source}"),
+            Self::Out { reg } => write!(f, "out_float r{reg}"),
+        }
+    }
+}
+
+/// Register written as `rN` or with its name.
+pub(crate) fn register(token: &str) -> Option<usize> {
+    token
+        .strip_prefix('r')
+        .and_then(|r| r.parse().ok())
+        .filter(|&r| r < NREGS)
+        .or_else(|| REGISTER_NAMES.iter().position(|&name| name == token))
+}
+
+fn reg(token: &str) -> Result<usize> {
+    register(token).ok_or(Error::InvalidSyntax)
+}
+
+fn indirect(token: &str) -> Result<usize> {
+    reg(token
+        .strip_prefix('[')
+        .and_then(|t| t.strip_suffix(']'))
+        .ok_or(Error::InvalidSyntax)?)
+}
+
+fn imm(token: &str) -> Result<i32> {
+    token
+        .strip_prefix('#')
+        .and_then(|v| v.parse::<i16>().ok())
+        .map(i32::from)
+        .ok_or(Error::InvalidSyntax)
+}
+
+/// Parse a 32 bit immediate, written either as a signed or unsigned number.
+fn imm32(token: &str) -> Result<u32> {
+    let value: i64 = token
+        .strip_prefix('#')
+        .and_then(|v| v.parse().ok())
+        .ok_or(Error::InvalidSyntax)?;
+    u32::try_from(value)
+        .or_else(|_| i32::try_from(value).map(i32::cast_unsigned))
+        .map_err(|_| Error::InvalidSyntax)
+}
+
+fn offset(token: &str) -> Result<i32> {
+    token
+        .strip_prefix("ip")
+        .filter(|o| o.starts_with(['+', '-']))
+        .and_then(|o| o.parse::<i16>().ok())
+        .map(i32::from)
+        .ok_or(Error::InvalidSyntax)
+}
+
+#[cfg(feature = "float")]
+fn parse_float(tokens: &[&str]) -> Result<FloatInstruction> {
+    let instruction = match *tokens {
+        ["fadd", t, "<-", a, "+", b] => FloatInstruction::Add {
+            target: reg(t)?,
+            op1: reg(a)?,
+            op2: reg(b)?,
+        },
+        ["fsub", t, "<-", a, "-", b] => FloatInstruction::Sub {
+            target: reg(t)?,
+            op1: reg(a)?,
+            op2: reg(b)?,
+        },
+        ["fmul", t, "<-", a, "*", b] => FloatInstruction::Mul {
+            target: reg(t)?,
+            op1: reg(a)?,
+            op2: reg(b)?,
+        },
+        ["fdiv", t, "<-", a, "/", b] => FloatInstruction::Div {
+            target: reg(t)?,
+            op1: reg(a)?,
+            op2: reg(b)?,
+        },
+        ["fcmp", t, "<-", a, "<=>", b] => FloatInstruction::Cmp {
+            target: reg(t)?,
+            op1: reg(a)?,
+            op2: reg(b)?,
+        },
+        ["itof", t, "<-", s] => FloatInstruction::FromInt {
+            target: reg(t)?,
+            source: reg(s)?,
+        },
+        ["ftoi", t, "<-", s] => FloatInstruction::ToInt {
+            target: reg(t)?,
+            source: reg(s)?,
+        },
+        ["out_float", r] => FloatInstruction::Out { reg: reg(r)? },
+        _ => return Err(Error::InvalidSyntax),
+    };
+    Ok(instruction)
+}
+
+impl FromStr for Instruction {
+    type Err = Error;
+
+    /// Parse an instruction written as by its `Display` implementation.
+    /// Tokens must be separated by whitespace.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut tokens = [""; MAX_TOKENS];
+        let mut len = 0;
+        for token in s.split_whitespace() {
+            *tokens.get_mut(len).ok_or(Error::InvalidSyntax)? = token;
+            len += 1;
+        }
+        let instruction = match tokens[..len] {
+            ["move", t, "<-", s, "if", c, "!=", "0"] => Self::MoveIf {
+                target: reg(t)?,
+                source: reg(s)?,
+                cond: reg(c)?,
+            },
+            ["store", t, "<-", s] => Self::Store {
+                target: indirect(t)?,
+                source: reg(s)?,
+            },
+            ["load", t, "<-", s] => Self::Load {
+                target: reg(t)?,
+                source: indirect(s)?,
+            },
+            ["loadimm", t, "<-", v] => Self::LoadImm {
+                target: reg(t)?,
+                value: imm(v)?,
+            },
+            ["loadimm32", t, "<-", v] => Self::LoadImm32 {
+                target: reg(t)?,
+                value: imm32(v)?,
+            },
+            ["sub", t, "<-", a, "-", b] => Self::Sub {
+                target: reg(t)?,
+                op1: reg(a)?,
+                op2: reg(b)?,
+            },
+            ["out", r] => Self::Out { reg: reg(r)? },
+            ["exit"] => Self::Exit,
+            ["ei"] => Self::EnableInterrupts,
+            ["di"] => Self::DisableInterrupts,
+            ["reti"] => Self::ReturnFromInterrupt,
+            ["syscall"] => Self::Syscall,
+            ["sysret", r] => Self::ReturnToUser { reg: reg(r)? },
+            ["cas", t, "<-", s, e, n] => Self::CompareAndSwap {
+                target: reg(t)?,
+                source: indirect(s)?,
+                expected: reg(e)?,
+                new: reg(n)?,
+            },
+            ["fetch_add", t, "<-", s, "+", v] => Self::FetchAdd {
+                target: reg(t)?,
+                source: indirect(s)?,
+                value: reg(v)?,
+            },
+            ["coreid", t] => Self::CoreId { target: reg(t)? },
+            ["spawn", t, "<-", e] => Self::Spawn {
+                target: reg(t)?,
+                entry: reg(e)?,
+            },
+            ["yield"] => Self::Yield,
+            ["join", r] => Self::Join { reg: reg(r)? },
+            ["out_number", r] => Self::OutNumber { reg: reg(r)? },
+            ["jump_rel", o] => Self::JumpRel { offset: offset(o)? },
+            ["jump_rel", o, "if", c, "!=", "0"] => Self::JumpRelIf {
+                cond: reg(c)?,
+                offset: offset(o)?,
+            },
+            ["loadrel", t, "<-", o] => Self::LoadRel {
+                target: reg(t)?,
+                offset: offset(o)?,
+            },
+            ["trap", s] => Self::Trap {
+                service: s
+                    .strip_prefix('#')
+                    .and_then(|s| s.parse().ok())
+                    .ok_or(Error::InvalidSyntax)?,
+            },
+            #[cfg(feature = "float")]
+            ref tokens => Self::Float(parse_float(tokens)?),
+            #[cfg(not(feature = "float"))]
+            _ => return Err(Error::InvalidSyntax),
+        };
+        Ok(instruction)
+    }
+}
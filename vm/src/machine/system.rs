@@ -0,0 +1,288 @@
+//! Multi-core system: several cores, each with its own registers, privilege
+//! level, interrupt state, ABI checker and green threads, share the memory
+//! and the devices of a single machine. A deterministic scheduler
+//! runs one instruction of one core at a time, so every instruction, and in
+//! particular `cas` and `fetch_add`, is atomic. The `coreid` instruction
+//! tells cores apart.
+
+#[cfg(feature = "std")]
+use core::ops::Range;
+
+#[cfg(feature = "std")]
+use super::MEMORY_SIZE;
+use super::{
+    abi::AbiChecker, interrupt::CoreInterrupts, threads::Threads, Error, Machine, Result, Write,
+    NREGS,
+};
+
+/// Maximum number of cores in a system.
+pub const MAX_CORES: usize = 8;
+
+/// Order in which cores execute instructions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Schedule {
+    /// One instruction of every running core in turn
+    RoundRobin,
+    /// One instruction of a running core picked by a pseudo-random
+    /// generator initialized with this seed
+    Random(u64),
+}
+
+/// Two random schedules of the same system ending differently, which
+/// reveals a race. Both can be replayed with [`Schedule::Random`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Race {
+    pub reference: u64,
+    pub seed: u64,
+}
+
+/// State of a core, swapped into the shared machine while the core executes
+/// an instruction.
+#[derive(Clone)]
+struct CoreState {
+    registers: [u32; NREGS],
+    instruction_ip: u32,
+    user_mode: bool,
+    interrupts: CoreInterrupts,
+    abi: Option<AbiChecker>,
+    threads: Threads,
+}
+
+impl CoreState {
+    fn swap(&mut self, machine: &mut Machine) {
+        core::mem::swap(&mut self.registers, &mut machine.registers);
+        core::mem::swap(&mut self.instruction_ip, &mut machine.instruction_ip);
+        core::mem::swap(&mut self.user_mode, &mut machine.user_mode);
+        core::mem::swap(&mut self.interrupts, &mut machine.interrupts.core);
+        core::mem::swap(&mut self.abi, &mut machine.abi);
+        core::mem::swap(&mut self.threads, &mut machine.threads);
+    }
+}
+
+#[derive(Clone)]
+pub struct System {
+    machine: Machine,
+    states: [CoreState; MAX_CORES],
+    exited: [bool; MAX_CORES],
+    cores: usize,
+    schedule: Schedule,
+    /// State of the xorshift generator
+    random: u64,
+    current: usize,
+}
+
+impl System {
+    /// Create a system of `cores` cores sharing the memory and devices of
+    /// `machine`. All cores start with the registers, privilege level,
+    /// interrupt state, ABI checker and threads of `machine`.
+    ///
+    /// # Errors
+    /// This function returns an error if there are no cores or more than
+    /// `MAX_CORES`.
+    pub fn new(machine: Machine, cores: usize) -> Result<Self> {
+        if cores == 0 || cores > MAX_CORES {
+            return Err(Error::InvalidCore(cores));
+        }
+        let state = CoreState {
+            registers: machine.registers,
+            instruction_ip: machine.instruction_ip,
+            user_mode: machine.user_mode,
+            interrupts: machine.interrupts.core,
+            abi: machine.abi.clone(),
+            threads: machine.threads.clone(),
+        };
+        Ok(Self {
+            states: core::array::from_fn(|_| state.clone()),
+            machine,
+            exited: [false; MAX_CORES],
+            cores,
+            schedule: Schedule::RoundRobin,
+            random: 0,
+            current: cores - 1,
+        })
+    }
+
+    /// Shared machine. Its registers and other per-core state are not those
+    /// of any core.
+    #[must_use]
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// Shared machine, to configure its memory and devices.
+    pub fn machine_mut(&mut self) -> &mut Machine {
+        &mut self.machine
+    }
+
+    /// Number of cores.
+    #[must_use]
+    pub fn cores(&self) -> usize {
+        self.cores
+    }
+
+    /// Core which has executed the last instruction.
+    #[must_use]
+    pub fn current_core(&self) -> usize {
+        self.current
+    }
+
+    fn check_core(&self, core: usize) -> Result<usize> {
+        if core < self.cores {
+            Ok(core)
+        } else {
+            Err(Error::InvalidCore(core))
+        }
+    }
+
+    /// Registers of a core.
+    ///
+    /// # Errors
+    /// This function returns an error if the core does not exist.
+    pub fn regs(&self, core: usize) -> Result<&[u32]> {
+        Ok(&self.states[self.check_core(core)?].registers)
+    }
+
+    /// Set a register of a core.
+    ///
+    /// # Errors
+    /// This function returns an error if the core or the register does not
+    /// exist.
+    pub fn set_reg(&mut self, core: usize, reg: usize, value: u32) -> Result<()> {
+        let registers = &mut self.states[self.check_core(core)?].registers;
+        *registers.get_mut(reg).ok_or(Error::InvalidRegister(reg))? = value;
+        Ok(())
+    }
+
+    /// Check whether a core has executed an exit instruction.
+    ///
+    /// # Errors
+    /// This function returns an error if the core does not exist.
+    pub fn has_exited(&self, core: usize) -> Result<bool> {
+        Ok(self.exited[self.check_core(core)?])
+    }
+
+    /// Check whether a core runs in user mode.
+    ///
+    /// # Errors
+    /// This function returns an error if the core does not exist.
+    pub fn user_mode(&self, core: usize) -> Result<bool> {
+        Ok(self.states[self.check_core(core)?].user_mode)
+    }
+
+    /// Switch a core between user and supervisor mode.
+    ///
+    /// # Errors
+    /// This function returns an error if the core does not exist.
+    pub fn set_user_mode(&mut self, core: usize, user: bool) -> Result<()> {
+        self.on_core(core, |machine| machine.set_user_mode(user))
+    }
+
+    /// Mark an interrupt line as pending on a core.
+    ///
+    /// # Errors
+    /// This function returns an error if the core or the line does not
+    /// exist.
+    pub fn raise_interrupt(&mut self, core: usize, line: u8) -> Result<()> {
+        self.on_core(core, |machine| machine.raise_interrupt(line))?
+    }
+
+    /// Call `f` on the shared machine holding the state of `core`.
+    fn on_core<R>(&mut self, core: usize, f: impl FnOnce(&mut Machine) -> R) -> Result<R> {
+        let state = &mut self.states[self.check_core(core)?];
+        self.machine.core_id = u32::try_from(core).unwrap_or(u32::MAX);
+        state.swap(&mut self.machine);
+        let result = f(&mut self.machine);
+        state.swap(&mut self.machine);
+        Ok(result)
+    }
+
+    /// Change the schedule, restarting its pseudo-random sequence if any.
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.schedule = schedule;
+        if let Schedule::Random(seed) = schedule {
+            // splitmix64, so that close seeds give unrelated sequences and
+            // the xorshift state is never zero
+            let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            self.random = (z ^ (z >> 31)) | 1;
+        }
+    }
+
+    /// Pick the core running the next instruction among the running ones.
+    fn next_core(&mut self) -> Option<usize> {
+        let running = self.exited[..self.cores].iter().filter(|&&e| !e).count();
+        if running == 0 {
+            return None;
+        }
+        match self.schedule {
+            Schedule::RoundRobin => (1..=self.cores)
+                .map(|i| (self.current + i) % self.cores)
+                .find(|&core| !self.exited[core]),
+            Schedule::Random(_) => {
+                self.random ^= self.random << 13;
+                self.random ^= self.random >> 7;
+                self.random ^= self.random << 17;
+                #[allow(clippy::cast_possible_truncation)]
+                let n = (self.random % running as u64) as usize;
+                (0..self.cores).filter(|&core| !self.exited[core]).nth(n)
+            }
+        }
+    }
+
+    /// Execute one instruction of the core chosen by the schedule, as done
+    /// by [`Machine::step_on`]. Return `true` once all cores have exited.
+    ///
+    /// # Errors
+    /// This function returns the error of the instruction, whose core is
+    /// given by [`System::current_core`].
+    pub fn step_on<T: Write>(&mut self, fd: &mut T) -> Result<bool> {
+        let Some(core) = self.next_core() else {
+            return Ok(true);
+        };
+        self.current = core;
+        if self.on_core(core, |machine| machine.step_on(fd))?? {
+            self.exited[core] = true;
+        }
+        Ok(self.exited[..self.cores].iter().all(|&e| e))
+    }
+
+    /// Run until all cores have exited or until an error happens.
+    ///
+    /// # Errors
+    /// See [`System::step_on`].
+    pub fn run_on<T: Write>(&mut self, fd: &mut T) -> Result<()> {
+        while !self.step_on(fd)? {}
+        Ok(())
+    }
+
+    /// Run copies of this system with the random schedules given by `seeds`,
+    /// each for at most `max_steps` instructions, and return the first one
+    /// whose output, memory or error differ from the first schedule.
+    /// Registers are not compared, as they often legitimately depend on the
+    /// schedule, e.g., the value returned by `fetch_add`.
+    #[cfg(feature = "std")]
+    #[must_use]
+    pub fn find_race(&self, seeds: Range<u64>, max_steps: usize) -> Option<Race> {
+        type Outcome = (Result<bool>, Vec<u8>, [u8; MEMORY_SIZE]);
+        let run = |seed| -> Outcome {
+            let mut system = self.clone();
+            system.set_schedule(Schedule::Random(seed));
+            let mut output = Vec::new();
+            let mut status = Ok(false);
+            for _ in 0..max_steps {
+                status = system.step_on(&mut output);
+                if status != Ok(false) {
+                    break;
+                }
+            }
+            (status, output, system.machine.memory)
+        };
+        let reference = seeds.start;
+        let expected = run(reference);
+        seeds
+            .skip(1)
+            .find(|&seed| run(seed) != expected)
+            .map(|seed| Race { reference, seed })
+    }
+}
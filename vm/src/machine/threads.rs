@@ -0,0 +1,181 @@
+//! Cooperative green threads multiplexed over the registers of a machine.
+//!
+//! `spawn rT <- rE` starts a thread at the address held by `rE` with a copy
+//! of the registers of its parent, once `rT` has been set to the identifier
+//! of the new thread, and a stack pointer at the top of its own stack
+//! region. `yield` switches to the next ready thread in a round-robin way,
+//! and `join rT` waits until the thread whose identifier is in `rT` has
+//! executed `exit`. The initial thread has identifier 0 and keeps its stack.
+//!
+//! Once threads are used, `exit` only terminates the current thread, and the
+//! machine stops when all threads have exited. If no thread can run because
+//! they are all waiting for each other, [`Error::Deadlock`] is returned.
+
+use super::{Error, Machine, Result, IP, NREGS, SP};
+
+/// Maximum number of threads alive at the same time, including the initial
+/// one. The identifier of a joined thread can be reused.
+pub const MAX_THREADS: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ready,
+    /// Waiting for this thread to exit
+    Joining(usize),
+    Exited,
+}
+
+#[derive(Clone, Copy)]
+struct Thread {
+    registers: [u32; NREGS],
+    state: State,
+}
+
+#[derive(Clone, Default)]
+pub(super) struct Threads {
+    /// Top of the stack of thread 1, and size of each stack
+    stacks: Option<(u32, u32)>,
+    /// Empty until a thread is spawned
+    slots: [Option<Thread>; MAX_THREADS],
+    current: usize,
+}
+
+impl Threads {
+    /// Whether the thread in `slot` can be resumed.
+    fn can_run(&self, slot: usize) -> bool {
+        match self.slots[slot] {
+            Some(Thread {
+                state: State::Ready,
+                ..
+            }) => true,
+            // The join is executed again and fails if the thread is gone
+            Some(Thread {
+                state: State::Joining(thread),
+                ..
+            }) => self.slots[thread].is_none_or(|t| t.state == State::Exited),
+            _ => false,
+        }
+    }
+}
+
+impl Machine {
+    /// Allow threads to be spawned. Thread `n` gets the stack whose top is
+    /// `top - (n - 1) * size`.
+    ///
+    /// # Errors
+    /// This function returns an error if the stacks of all the threads do
+    /// not fit in memory.
+    pub fn enable_threads(&mut self, top: u32, size: u32) -> Result<()> {
+        let stacks = u32::try_from(MAX_THREADS - 1).unwrap_or(u32::MAX);
+        if Self::get_memory_address(top.wrapping_sub(1)).is_err()
+            || size.checked_mul(stacks).is_none_or(|len| len > top)
+        {
+            return Err(Error::InvalidMemoryAddress(top));
+        }
+        self.threads.stacks = Some((top, size));
+        Ok(())
+    }
+
+    /// Identifier of the running thread.
+    #[must_use]
+    pub fn current_thread(&self) -> u32 {
+        u32::try_from(self.threads.current).unwrap_or(u32::MAX)
+    }
+
+    pub(super) fn spawn_thread(&mut self, target: usize, entry: u32) -> Result<()> {
+        let threads = &mut self.threads;
+        let (top, size) = threads
+            .stacks
+            .ok_or(Error::ThreadsDisabled(self.instruction_ip))?;
+        let slot = (1..MAX_THREADS)
+            .find(|&slot| threads.slots[slot].is_none())
+            .ok_or(Error::TooManyThreads)?;
+        if threads.slots[0].is_none() {
+            threads.slots[0] = Some(Thread {
+                registers: self.registers,
+                state: State::Ready,
+            });
+        }
+        self.registers[target] = u32::try_from(slot).unwrap_or(u32::MAX);
+        let mut registers = self.registers;
+        registers[IP] = entry;
+        registers[SP] = top - size * (registers[target] - 1);
+        threads.slots[slot] = Some(Thread {
+            registers,
+            state: State::Ready,
+        });
+        Ok(())
+    }
+
+    pub(super) fn yield_thread(&mut self) -> Result<()> {
+        if self.threads.slots[0].is_none() {
+            return Ok(());
+        }
+        self.switch_thread()
+    }
+
+    pub(super) fn join_thread(&mut self, thread: u32) -> Result<()> {
+        let threads = &mut self.threads;
+        let slot = usize::try_from(thread)
+            .ok()
+            .filter(|&slot| slot < MAX_THREADS && threads.slots[slot].is_some())
+            .ok_or(Error::InvalidThread(thread))?;
+        if slot == threads.current {
+            return Err(Error::Deadlock);
+        }
+        if threads.slots[slot].is_some_and(|t| t.state == State::Exited) {
+            threads.slots[slot] = None;
+            return Ok(());
+        }
+        // Execute the join again once the thread has exited
+        self.registers[IP] = self.instruction_ip;
+        self.set_thread_state(State::Joining(slot));
+        self.switch_thread()
+    }
+
+    /// Terminate the current thread, and return `true` if it was the last
+    /// one.
+    pub(super) fn exit_thread(&mut self) -> Result<bool> {
+        if self.threads.slots[0].is_none() {
+            return Ok(true);
+        }
+        self.set_thread_state(State::Exited);
+        let threads = &self.threads;
+        if threads
+            .slots
+            .iter()
+            .flatten()
+            .all(|t| t.state == State::Exited)
+        {
+            return Ok(true);
+        }
+        self.switch_thread()?;
+        Ok(false)
+    }
+
+    fn set_thread_state(&mut self, state: State) {
+        if let Some(thread) = &mut self.threads.slots[self.threads.current] {
+            thread.state = state;
+        }
+    }
+
+    /// Save the registers of the current thread and resume the next one
+    /// which can run, possibly the current one.
+    fn switch_thread(&mut self) -> Result<()> {
+        let threads = &mut self.threads;
+        let current = threads.current;
+        if let Some(thread) = &mut threads.slots[current] {
+            thread.registers = self.registers;
+        }
+        let next = (1..=MAX_THREADS)
+            .map(|i| (current + i) % MAX_THREADS)
+            .find(|&slot| threads.can_run(slot))
+            .ok_or(Error::Deadlock)?;
+        threads.current = next;
+        if let Some(thread) = &mut threads.slots[next] {
+            thread.state = State::Ready;
+            self.registers = thread.registers;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,137 @@
+//! Watchpoints stopping the execution when a memory range is accessed.
+
+use alloc::borrow::Cow;
+use core::fmt;
+use core::ops::Range;
+
+use super::{Error, Machine, Result};
+
+/// Maximum number of watchpoints which can be set at the same time.
+pub const MAX_WATCHPOINTS: usize = 8;
+
+/// Kind of access a watchpoint reacts to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    /// Either a read or a write
+    Access,
+}
+
+impl WatchKind {
+    fn matches(self, access: WatchKind) -> bool {
+        self == WatchKind::Access || self == access
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct Watchpoint {
+    range: Range<u32>,
+    kind: WatchKind,
+    label: Cow<'static, str>,
+}
+
+/// Description of the memory access which triggered a watchpoint.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchpointHit {
+    /// Label given when the watchpoint was set
+    pub label: Cow<'static, str>,
+    /// Actual access, either [`WatchKind::Read`] or [`WatchKind::Write`]
+    pub access: WatchKind,
+    /// Address of the instruction doing the access
+    pub ip: u32,
+    /// Address of the accessed word
+    pub addr: u32,
+    /// Word value before the access
+    pub old: u32,
+    /// Word value after the access, identical to `old` for a read
+    pub new: u32,
+}
+
+impl fmt::Display for WatchpointHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let access = match self.access {
+            WatchKind::Write => "write to",
+            _ => "read from",
+        };
+        write!(
+            f,
+            "watchpoint {}: {access} {:04} at ip {:04}: {:#010x} -> {:#010x}",
+            self.label, self.addr, self.ip, self.old, self.new
+        )
+    }
+}
+
+impl Machine {
+    /// Stop the execution with [`Error::Watchpoint`] whenever an access of
+    /// the given `kind` touches a byte of `range`. The instruction doing the
+    /// access is executed completely, so the execution can be resumed
+    /// afterwards.
+    ///
+    /// # Errors
+    /// This function returns an error if `MAX_WATCHPOINTS` watchpoints are
+    /// already set.
+    pub fn add_watchpoint(
+        &mut self,
+        kind: WatchKind,
+        range: Range<u32>,
+        label: impl Into<Cow<'static, str>>,
+    ) -> Result<()> {
+        let slot = self
+            .watchpoints
+            .iter_mut()
+            .find(|w| w.is_none())
+            .ok_or(Error::TooManyWatchpoints)?;
+        *slot = Some(Watchpoint {
+            range,
+            kind,
+            label: label.into(),
+        });
+        Ok(())
+    }
+
+    /// Remove all the watchpoints with the given label. Return `true` if
+    /// at least one was found.
+    pub fn remove_watchpoint(&mut self, label: &str) -> bool {
+        let mut found = false;
+        for slot in &mut self.watchpoints {
+            if slot.as_ref().is_some_and(|w| w.label == label) {
+                *slot = None;
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Remove all the watchpoints.
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints = Default::default();
+    }
+
+    /// Check an access to the word at `addr` by the current instruction.
+    pub(super) fn check_watchpoints(
+        &self,
+        access: WatchKind,
+        addr: u32,
+        old: u32,
+        new: u32,
+    ) -> Result<()> {
+        let end = addr.saturating_add(4);
+        let hit = self
+            .watchpoints
+            .iter()
+            .flatten()
+            .find(|w| w.kind.matches(access) && w.range.start < end && addr < w.range.end);
+        match hit {
+            Some(w) => Err(Error::Watchpoint(WatchpointHit {
+                label: w.label.clone(),
+                access,
+                ip: self.instruction_ip,
+                addr,
+                old,
+                new,
+            })),
+            None => Ok(()),
+        }
+    }
+}
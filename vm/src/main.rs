@@ -1,6 +1,409 @@
-fn main() -> Result<(), interpreter::Error> {
-    let filename = std::env::args().nth(1).unwrap();
-    let buffer = std::fs::read(filename).unwrap();
-    let mut machine = interpreter::Machine::new(&buffer).unwrap();
-    machine.run()
+use interpreter::{
+    assemble_in, check_golden, disassemble_with, discover_programs, expectation_path, render_frame,
+    serial_frame, Debugger, DisassemblyOptions, Error, Fault, Linker, Machine, Object, ObjectError,
+    Outcome, Protection, SymbolMap, Verdict, WatchKind,
+};
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: vm [OPTIONS] FILE
+       vm test [--bless] [PATH...]
+       vm link [-o OUTPUT] [--map FILE] [-l LIBRARY]... OBJECT...
+       vm asm [-o OUTPUT] SOURCE
+       vm dis [--aliases] [--idioms] FILE
+
+Options:
+  --watch-read RANGE[=LABEL]     stop on reads from RANGE
+  --watch-write RANGE[=LABEL]    stop on writes to RANGE
+  --watch RANGE[=LABEL]          stop on any access to RANGE
+  --protect RANGE=ro|nx|guard    make RANGE read-only, non-executable or a guard
+  --wx                           refuse to execute memory outside read-only ranges
+  --abi                          check the calling convention
+  --uninit                       detect reads of uninitialized memory
+  --symbols FILE                 read function names from FILE (defaults to
+                                 the program name with a .sym extension)
+  --debug                        start an interactive debugger
+  --framebuffer ADDR             map the LED matrix framebuffer at ADDR
+  --frames FILE                  write presented frames to FILE using the
+                                 serial protocol of the LED matrix instead of
+                                 displaying them on standard error
+
+RANGE is either ADDR or START..END.
+
+FILE is either a raw memory image, loaded at address 0, or an object file
+which provides the entry point, the stack pointer, symbols and source lines.
+
+The test command runs the given programs, or the .bin files of the given
+directories (examples by default), and compares their output, registers and
+exit status with their .expected files. --bless rewrites the files which
+do not match.
+
+The link command links object files into OUTPUT (a.obj by default), which
+is a raw memory image if its extension is .bin, along with the libraries
+which define symbols they need. --map writes where sections and symbols
+have been placed into FILE.
+
+The asm command assembles SOURCE into the raw memory image OUTPUT (a.bin by
+default). Included files are searched from the directory of SOURCE.
+
+The dis command disassembles FILE. --aliases writes registers with their
+names in the calling convention, such as sp, and --idioms writes jumps and
+no-ops as the pseudo-instructions of the assembler, such as jmp.
+
+Watchpoint hits, calling convention violations and uninitialized reads are
+reported on standard error and execution resumes. Other errors stop the
+program and are reported along with a backtrace.";
+
+#[derive(Default)]
+#[allow(clippy::struct_excessive_bools)]
+struct Options {
+    filename: String,
+    watchpoints: Vec<(WatchKind, Range<u32>, String)>,
+    regions: Vec<(Range<u32>, Protection)>,
+    enforce_wx: bool,
+    check_abi: bool,
+    check_uninitialized: bool,
+    symbols: Option<String>,
+    debug: bool,
+    framebuffer: Option<u32>,
+    frames: Option<String>,
+}
+
+fn parse_range(range: &str) -> Option<Range<u32>> {
+    if let Some((start, end)) = range.split_once("..") {
+        let range = start.parse().ok()?..end.parse().ok()?;
+        // An empty range would never be accessed
+        (!range.is_empty()).then_some(range)
+    } else {
+        let addr: u32 = range.parse().ok()?;
+        Some(addr..addr.checked_add(1)?)
+    }
+}
+
+fn parse_watchpoint(spec: &str) -> Option<(Range<u32>, String)> {
+    let (range, label) = spec.split_once('=').unwrap_or((spec, spec));
+    Some((parse_range(range)?, label.to_owned()))
+}
+
+fn parse_region(spec: &str) -> Option<(Range<u32>, Protection)> {
+    let (range, protection) = spec.split_once('=')?;
+    let protection = match protection {
+        "ro" => Protection::ReadOnly,
+        "nx" => Protection::NoExecute,
+        "guard" => Protection::Guard,
+        _ => return None,
+    };
+    Some((parse_range(range)?, protection))
+}
+
+fn parse_args() -> Option<Options> {
+    let mut args = std::env::args().skip(1);
+    let mut options = Options::default();
+    let mut filename = None;
+    while let Some(arg) = args.next() {
+        let kind = match arg.as_str() {
+            "--watch-read" => WatchKind::Read,
+            "--watch-write" => WatchKind::Write,
+            "--watch" => WatchKind::Access,
+            "--protect" => {
+                options.regions.push(parse_region(&args.next()?)?);
+                continue;
+            }
+            "--wx" => {
+                options.enforce_wx = true;
+                continue;
+            }
+            "--abi" => {
+                options.check_abi = true;
+                continue;
+            }
+            "--uninit" => {
+                options.check_uninitialized = true;
+                continue;
+            }
+            "--symbols" => {
+                options.symbols = Some(args.next()?);
+                continue;
+            }
+            "--debug" => {
+                options.debug = true;
+                continue;
+            }
+            "--framebuffer" => {
+                options.framebuffer = Some(args.next()?.parse().ok()?);
+                continue;
+            }
+            "--frames" => {
+                options.frames = Some(args.next()?);
+                continue;
+            }
+            _ if filename.is_none() && !arg.starts_with('-') => {
+                filename = Some(arg);
+                continue;
+            }
+            _ => return None,
+        };
+        let (range, label) = parse_watchpoint(&args.next()?)?;
+        options.watchpoints.push((kind, range, label));
+    }
+    options.filename = filename?;
+    Some(options)
+}
+
+fn describe_difference(expected: &Outcome, actual: &Outcome) -> String {
+    let mut differences = Vec::new();
+    if expected.status != actual.status {
+        differences.push(format!(
+            "status {} instead of {}",
+            actual.status, expected.status
+        ));
+    }
+    if expected.registers != actual.registers {
+        differences.push(format!(
+            "registers {:?} instead of {:?}",
+            actual.registers, expected.registers
+        ));
+    }
+    if expected.output != actual.output {
+        let common = expected
+            .output
+            .iter()
+            .zip(&actual.output)
+            .take_while(|(e, a)| e == a)
+            .count();
+        differences.push(format!("output differs from byte {common}"));
+    }
+    differences.join(", ")
+}
+
+fn run_tests(args: &[String]) -> io::Result<ExitCode> {
+    let bless = args.iter().any(|arg| arg == "--bless");
+    let mut paths = args
+        .iter()
+        .filter(|arg| *arg != "--bless")
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    if paths.is_empty() {
+        paths.push(PathBuf::from("examples"));
+    }
+    let mut programs = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            programs.extend(discover_programs(&path)?);
+        } else {
+            programs.push(path);
+        }
+    }
+    let mut failures = 0;
+    for program in &programs {
+        let name = program.display();
+        match check_golden(program, bless) {
+            Err(e) => {
+                println!("FAIL {name}: {e}");
+                failures += 1;
+            }
+            Ok(Verdict::Pass) => println!("PASS {name}"),
+            Ok(Verdict::Blessed) => println!("BLESS {name}"),
+            Ok(Verdict::Fail { expected, actual }) => {
+                println!("FAIL {name}: {}", describe_difference(&expected, &actual));
+                failures += 1;
+            }
+            Ok(Verdict::Missing(_)) => {
+                println!("FAIL {name}: no {}", expectation_path(program).display());
+                failures += 1;
+            }
+        }
+    }
+    println!("{} passed, {failures} failed", programs.len() - failures);
+    Ok(if failures == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+fn run_link(args: &[String]) -> Result<(), String> {
+    let read = |path: &str| {
+        let bytes = std::fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+        Object::parse(&bytes).map_err(|e| format!("{path}: {e}"))
+    };
+    let mut linker = Linker::new();
+    let mut output = String::from("a.obj");
+    let mut map = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().cloned().ok_or_else(|| USAGE.to_owned());
+        match arg.as_str() {
+            "-o" => output = value()?,
+            "--map" => map = Some(value()?),
+            "-l" => {
+                let path = value()?;
+                linker.add_library(&path, read(&path)?);
+            }
+            path => linker.add_module(path, read(path)?),
+        }
+    }
+    let (object, link_map) = linker.link().map_err(|e| e.to_string())?;
+    let bytes = if Path::new(&output).extension().is_some_and(|e| e == "bin") {
+        if object.entry != 0 {
+            return Err(format!("entry point {} is not 0", object.entry));
+        }
+        object.to_image()
+    } else {
+        object.to_bytes()
+    };
+    std::fs::write(&output, bytes).map_err(|e| format!("{output}: {e}"))?;
+    if let Some(map) = map {
+        std::fs::write(&map, link_map.to_string()).map_err(|e| format!("{map}: {e}"))?;
+    }
+    Ok(())
+}
+
+fn run_asm(args: &[String]) -> Result<(), String> {
+    let (output, source) = match args {
+        [source] => ("a.bin", source),
+        [o, output, source] if o == "-o" => (output.as_str(), source),
+        _ => return Err(USAGE.to_owned()),
+    };
+    let text = std::fs::read_to_string(source).map_err(|e| format!("{source}: {e}"))?;
+    let dir = Path::new(source).parent().unwrap_or(Path::new("."));
+    let bytes = assemble_in(&text, dir).map_err(|e| match e.file {
+        Some(_) => e.to_string(),
+        None => format!("{source}:{e}"),
+    })?;
+    std::fs::write(output, bytes).map_err(|e| format!("{output}: {e}"))
+}
+
+fn run_dis(args: &[String]) -> Result<(), String> {
+    let mut options = DisassemblyOptions::default();
+    let mut path = None;
+    for arg in args {
+        match arg.as_str() {
+            "--aliases" => options.aliases = true,
+            "--idioms" => options.idioms = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => return Err(USAGE.to_owned()),
+        }
+    }
+    let path = path.ok_or_else(|| USAGE.to_owned())?;
+    let mut bytes = std::fs::read(path).map_err(|e| format!("{path}: {e}"))?;
+    if Object::is_object(&bytes) {
+        bytes = Object::parse(&bytes)
+            .map_err(|e| format!("{path}: {e}"))?
+            .to_image();
+    }
+    print!("{}", disassemble_with(&bytes, options));
+    Ok(())
+}
+
+/// Load a raw memory image or an object file, which is also returned.
+fn load(buffer: &[u8]) -> Result<(Machine, Option<Object>), ObjectError> {
+    if !Object::is_object(buffer) {
+        return Ok((Machine::new(buffer)?, None));
+    }
+    let object = Object::parse(buffer)?;
+    let mut machine = Machine::new(&[])?;
+    machine.load_object(&object)?;
+    Ok((machine, Some(object)))
+}
+
+fn main() -> Result<ExitCode, Error> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let result = match args.first().map(String::as_str) {
+        Some("test") => Some(run_tests(&args[1..]).map_err(|e| e.to_string())),
+        Some("link") => Some(run_link(&args[1..]).map(|()| ExitCode::SUCCESS)),
+        Some("asm") => Some(run_asm(&args[1..]).map(|()| ExitCode::SUCCESS)),
+        Some("dis") => Some(run_dis(&args[1..]).map(|()| ExitCode::SUCCESS)),
+        _ => None,
+    };
+    if let Some(result) = result {
+        return Ok(result.unwrap_or_else(|e| {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }));
+    }
+    let Some(options) = parse_args() else {
+        eprintln!("{USAGE}");
+        return Ok(ExitCode::from(2));
+    };
+    let buffer = std::fs::read(&options.filename).unwrap();
+    let symbols = options.symbols.map_or_else(
+        || Path::new(&options.filename).with_extension("sym"),
+        Into::into,
+    );
+    let (mut machine, object) = match load(&buffer) {
+        Ok(loaded) => loaded,
+        Err(ObjectError::Load(e)) => return Err(e),
+        Err(e) => {
+            eprintln!("error: {e}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+    let symbols = std::fs::read_to_string(symbols)
+        .ok()
+        .and_then(|s| SymbolMap::parse(&s))
+        .or_else(|| object.as_ref().map(Object::symbol_map));
+    for (kind, range, label) in options.watchpoints {
+        machine.add_watchpoint(kind, range, label)?;
+    }
+    for (range, protection) in options.regions {
+        machine.protect(range, protection)?;
+    }
+    machine.enforce_wx(options.enforce_wx);
+    machine.check_abi(options.check_abi);
+    machine.check_uninitialized(options.check_uninitialized);
+    if let Some(base) = options.framebuffer {
+        machine.map_framebuffer(base)?;
+    }
+    let mut frames = match options.frames {
+        Some(path) => Some(File::create(path).map_err(|_| Error::OutputError)?),
+        None => None,
+    };
+    if options.debug {
+        let mut debugger = Debugger::new(machine, symbols);
+        debugger
+            .repl(io::stdin().lock(), &mut io::stdout().lock())
+            .map_err(|_| Error::OutputError)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    loop {
+        match machine.run_traced_on(&mut io::stdout().lock()) {
+            Ok(()) => return Ok(ExitCode::SUCCESS),
+            Err(Fault { error, backtrace }) => match error {
+                Error::Watchpoint(hit) => eprintln!("{hit}"),
+                Error::FramePresented => {
+                    let framebuffer = machine.framebuffer().unwrap();
+                    match &mut frames {
+                        Some(frames) => frames
+                            .write_all(&serial_frame(framebuffer))
+                            .map_err(|_| Error::OutputError)?,
+                        None => eprintln!("{}", render_frame(framebuffer)),
+                    }
+                }
+                Error::AbiViolation(diagnostic) => eprintln!("{diagnostic}"),
+                Error::UninitializedRead { ip, addr } => {
+                    eprintln!("uninitialized read of {addr:04} at ip {ip:04}");
+                }
+                error => {
+                    // The innermost frame is the faulting instruction
+                    let line = object.as_ref().and_then(|object| {
+                        let line = object.line_of(*backtrace.frames().first()?)?;
+                        Some(format!(" ({}:{line})", object.lines.source))
+                    });
+                    let backtrace = match &symbols {
+                        Some(symbols) => symbols.symbolize(&backtrace),
+                        None => backtrace.to_string(),
+                    };
+                    eprintln!(
+                        "error: {error:?}{}\nbacktrace: {backtrace}",
+                        line.unwrap_or_default()
+                    );
+                    return Ok(ExitCode::FAILURE);
+                }
+            },
+        }
+    }
 }
@@ -0,0 +1,576 @@
+//! Object files: unlike raw `.bin` memory images, they tell code from data,
+//! and carry the entry point, the initial stack pointer, symbols and debug
+//! line information. Relocatable objects also reference symbols defined by
+//! other modules, and are combined by the [linker](crate::Linker).
+//!
+//! All integers are little-endian, strings are UTF-8 and prefixed by their
+//! length as a `u16`. The layout is:
+//!   - the header: `MAGIC`, the format version (`u16`), the ISA features
+//!     (`u16`, `FEATURE_*` flags), the entry point (`u32`) and the initial
+//!     stack pointer (`u32`);
+//!   - the sections: their count (`u32`), then for each one its kind (`u8`,
+//!     0 for code, 1 for data, 2 for bss), its address (`u32`), its size
+//!     (`u32`) and its content unless it is a bss section;
+//!   - the symbols: their count (`u32`), then for each one its address
+//!     (`u32`), the index of its section (`u32`, `u32::MAX` if undefined),
+//!     whether it is global (`u8`) and its name;
+//!   - the relocations: their count (`u32`), then for each one the index of
+//!     its section (`u32`), its offset in the section (`u32`), its kind
+//!     (`u8`, in the order of [`RelocationKind`]), its addend (`i32`) and
+//!     the name of its symbol;
+//!   - the line information: the name of the source, the count of entries
+//!     (`u32`), then for each one an address (`u32`) and the line of the
+//!     source (`u32`) starting at this address.
+//!
+//! Version 1 has neither section indices, global flags nor relocations: all
+//! its symbols are global and defined by the section containing them.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::{Error, Machine, Protection, SymbolMap, MEMORY_SIZE};
+
+pub const MAGIC: [u8; 4] = *b"VMOB";
+
+/// Version written by [`Object::to_bytes`].
+pub const VERSION: u16 = 2;
+
+/// The program uses floating point instructions.
+pub const FEATURE_FLOAT: u16 = 1;
+
+/// Features supported by this build of the interpreter.
+const SUPPORTED_FEATURES: u16 = if cfg!(feature = "float") {
+    FEATURE_FLOAT
+} else {
+    0
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SectionKind {
+    Code,
+    Data,
+    /// Zero-initialized data, whose content is not stored
+    Bss,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Section {
+    pub kind: SectionKind,
+    pub address: u32,
+    /// Content of the section, zeroes for a bss section
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u32,
+    /// Index of the section defining the symbol, `None` if it is defined
+    /// by another module
+    pub section: Option<usize>,
+    /// Whether other modules can reference the symbol. Only global symbols
+    /// appear in backtraces.
+    pub global: bool,
+}
+
+/// How the value of a symbol, plus an addend, is written into a section.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RelocationKind {
+    /// Low byte of a `loadimm` immediate
+    Low,
+    /// High byte of a `loadimm` immediate
+    High,
+    /// 32-bit word, e.g., a `loadimm32` immediate
+    Word,
+    /// 16-bit offset from the relocated location, as used by IP-relative
+    /// instructions with an addend of -2 since their offset is relative to
+    /// the end of the instruction
+    Relative,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Relocation {
+    pub section: usize,
+    /// Offset from the start of the section
+    pub offset: u32,
+    pub kind: RelocationKind,
+    pub symbol: String,
+    pub addend: i32,
+}
+
+impl RelocationKind {
+    const ALL: [Self; 4] = [Self::Low, Self::High, Self::Word, Self::Relative];
+
+    /// Number of bytes written.
+    #[must_use]
+    pub fn size(self) -> usize {
+        match self {
+            Self::Low | Self::High => 1,
+            Self::Word => 4,
+            Self::Relative => 2,
+        }
+    }
+
+    /// Write `value` into `bytes`, which start at address `place`. Return
+    /// `None` if the value does not fit.
+    pub(crate) fn apply(self, bytes: &mut [u8], place: u32, value: u32) -> Option<()> {
+        // Values written into 16 bits are sign-extended when executed
+        #[allow(clippy::cast_possible_truncation)]
+        let short = |value: u32| {
+            let short = value as u16;
+            (u32::from(short) == value || i32::from(short.cast_signed()).cast_unsigned() == value)
+                .then_some(short.to_le_bytes())
+        };
+        match self {
+            Self::Low => bytes[0] = short(value)?[0],
+            Self::High => bytes[0] = short(value)?[1],
+            Self::Word => bytes[..4].copy_from_slice(&value.to_le_bytes()),
+            Self::Relative => {
+                let offset = i16::try_from(value.wrapping_sub(place).cast_signed()).ok()?;
+                bytes[..2].copy_from_slice(&offset.to_le_bytes());
+            }
+        }
+        Some(())
+    }
+}
+
+/// Source lines of the instructions.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LineInfo {
+    pub source: String,
+    /// Address and line, sorted by address
+    pub lines: Vec<(u32, u32)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Object {
+    pub features: u16,
+    pub entry: u32,
+    pub stack: u32,
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Symbol>,
+    pub relocations: Vec<Relocation>,
+    pub lines: LineInfo,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ObjectError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    /// Features required by the object which are not supported
+    UnsupportedFeatures(u16),
+    Truncated,
+    InvalidSectionKind(u8),
+    InvalidSectionIndex(u32),
+    InvalidRelocationKind(u8),
+    /// The relocation at this offset does not fit in its section
+    InvalidRelocationOffset(u32),
+    InvalidString,
+    /// The object has to be linked before being loaded
+    UndefinedSymbol(String),
+    /// A relocation against this symbol has not been applied, the object
+    /// has to be linked before being loaded
+    UnresolvedRelocation(String),
+    /// Error while loading the object into a machine
+    Load(Error),
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not an object file"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported version {version}"),
+            Self::UnsupportedFeatures(features) => {
+                write!(f, "unsupported ISA features {features:#06x}")
+            }
+            Self::Truncated => write!(f, "truncated object file"),
+            Self::InvalidSectionKind(kind) => write!(f, "invalid section kind {kind}"),
+            Self::InvalidSectionIndex(index) => write!(f, "invalid section index {index}"),
+            Self::InvalidRelocationKind(kind) => write!(f, "invalid relocation kind {kind}"),
+            Self::InvalidRelocationOffset(offset) => {
+                write!(f, "relocation at offset {offset} outside of its section")
+            }
+            Self::InvalidString => write!(f, "invalid string"),
+            Self::UndefinedSymbol(name) => write!(f, "undefined symbol {name}"),
+            Self::UnresolvedRelocation(name) => {
+                write!(f, "unresolved relocation against {name}")
+            }
+            Self::Load(error) => write!(f, "cannot load object: {error:?}"),
+        }
+    }
+}
+
+impl From<Error> for ObjectError {
+    fn from(error: Error) -> Self {
+        Self::Load(error)
+    }
+}
+
+fn check_section_index(sections: &[Section], index: u32) -> Result<usize, ObjectError> {
+    usize::try_from(index)
+        .ok()
+        .filter(|&i| i < sections.len())
+        .ok_or(ObjectError::InvalidSectionIndex(index))
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], ObjectError> {
+        if self.0.len() < len {
+            return Err(ObjectError::Truncated);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ObjectError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ObjectError> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, ObjectError> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, ObjectError> {
+        Ok(i32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, ObjectError> {
+        let len = self.u16()?;
+        let bytes = self.bytes(usize::from(len))?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| ObjectError::InvalidString)
+    }
+
+    fn section(&mut self) -> Result<Section, ObjectError> {
+        let kind = match self.u8()? {
+            0 => SectionKind::Code,
+            1 => SectionKind::Data,
+            2 => SectionKind::Bss,
+            kind => return Err(ObjectError::InvalidSectionKind(kind)),
+        };
+        let address = self.u32()?;
+        let size = self.u32()? as usize;
+        let bytes = match kind {
+            // Do not allocate more than the memory for a bss section
+            SectionKind::Bss => vec![0; size.min(MEMORY_SIZE + 1)],
+            _ => self.bytes(size)?.to_vec(),
+        };
+        Ok(Section {
+            kind,
+            address,
+            bytes,
+        })
+    }
+
+    fn section_index(&mut self, sections: &[Section]) -> Result<usize, ObjectError> {
+        let index = self.u32()?;
+        check_section_index(sections, index)
+    }
+
+    fn symbol(&mut self, version: u16, sections: &[Section]) -> Result<Symbol, ObjectError> {
+        let address = self.u32()?;
+        let (section, global) = if version == 1 {
+            (section_of(sections, address), true)
+        } else {
+            let section = match self.u32()? {
+                u32::MAX => None,
+                index => Some(check_section_index(sections, index)?),
+            };
+            (section, self.u8()? != 0)
+        };
+        Ok(Symbol {
+            name: self.string()?,
+            address,
+            section,
+            global,
+        })
+    }
+
+    fn relocation(&mut self, sections: &[Section]) -> Result<Relocation, ObjectError> {
+        let section = self.section_index(sections)?;
+        let offset = self.u32()?;
+        let kind = self.u8()?;
+        let kind = *RelocationKind::ALL
+            .get(usize::from(kind))
+            .ok_or(ObjectError::InvalidRelocationKind(kind))?;
+        let addend = self.i32()?;
+        let s = &sections[section];
+        if s.kind == SectionKind::Bss
+            || (offset as usize).saturating_add(kind.size()) > s.bytes.len()
+        {
+            return Err(ObjectError::InvalidRelocationOffset(offset));
+        }
+        Ok(Relocation {
+            section,
+            offset,
+            kind,
+            symbol: self.string()?,
+            addend,
+        })
+    }
+
+    /// Read a count of items, which cannot exceed the remaining bytes.
+    fn count(&mut self) -> Result<usize, ObjectError> {
+        let count = self.u32()? as usize;
+        if count > self.0.len() {
+            return Err(ObjectError::Truncated);
+        }
+        Ok(count)
+    }
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    // Longer strings are truncated
+    let bytes = &s.as_bytes()[..s.len().min(usize::from(u16::MAX))];
+    out.extend(u16::try_from(bytes.len()).unwrap().to_le_bytes());
+    out.extend(bytes);
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    out.extend(u32::try_from(len).unwrap_or(u32::MAX).to_le_bytes());
+}
+
+impl Object {
+    /// Check whether `bytes` start like an object file rather than a raw
+    /// memory image.
+    #[must_use]
+    pub fn is_object(bytes: &[u8]) -> bool {
+        bytes.starts_with(&MAGIC)
+    }
+
+    /// Parse an object file.
+    ///
+    /// # Errors
+    /// This function returns an error if the object is malformed or
+    /// requires features not supported by this build.
+    pub fn parse(bytes: &[u8]) -> Result<Self, ObjectError> {
+        let mut r = Reader(bytes);
+        if r.bytes(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+            return Err(ObjectError::BadMagic);
+        }
+        let version = r.u16()?;
+        if version == 0 || version > VERSION {
+            return Err(ObjectError::UnsupportedVersion(version));
+        }
+        let features = r.u16()?;
+        if features & !SUPPORTED_FEATURES != 0 {
+            return Err(ObjectError::UnsupportedFeatures(
+                features & !SUPPORTED_FEATURES,
+            ));
+        }
+        let (entry, stack) = (r.u32()?, r.u32()?);
+        let sections = (0..r.count()?)
+            .map(|_| r.section())
+            .collect::<Result<Vec<_>, _>>()?;
+        let symbols = (0..r.count()?)
+            .map(|_| r.symbol(version, &sections))
+            .collect::<Result<_, _>>()?;
+        let relocations = if version == 1 {
+            Vec::new()
+        } else {
+            (0..r.count()?)
+                .map(|_| r.relocation(&sections))
+                .collect::<Result<_, _>>()?
+        };
+        let source = r.string()?;
+        let mut lines = (0..r.count()?)
+            .map(|_| Ok((r.u32()?, r.u32()?)))
+            .collect::<Result<Vec<_>, ObjectError>>()?;
+        lines.sort_unstable();
+        Ok(Self {
+            features,
+            entry,
+            stack,
+            sections,
+            symbols,
+            relocations,
+            lines: LineInfo { source, lines },
+        })
+    }
+
+    /// Encode the object.
+    #[must_use]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend(VERSION.to_le_bytes());
+        out.extend(self.features.to_le_bytes());
+        out.extend(self.entry.to_le_bytes());
+        out.extend(self.stack.to_le_bytes());
+        write_len(&mut out, self.sections.len());
+        for section in &self.sections {
+            out.push(match section.kind {
+                SectionKind::Code => 0,
+                SectionKind::Data => 1,
+                SectionKind::Bss => 2,
+            });
+            out.extend(section.address.to_le_bytes());
+            write_len(&mut out, section.bytes.len());
+            if section.kind != SectionKind::Bss {
+                out.extend(&section.bytes);
+            }
+        }
+        write_len(&mut out, self.symbols.len());
+        for symbol in &self.symbols {
+            out.extend(symbol.address.to_le_bytes());
+            let section = symbol
+                .section
+                .map_or(u32::MAX, |index| u32::try_from(index).unwrap_or(u32::MAX));
+            out.extend(section.to_le_bytes());
+            out.push(u8::from(symbol.global));
+            write_string(&mut out, &symbol.name);
+        }
+        write_len(&mut out, self.relocations.len());
+        for relocation in &self.relocations {
+            write_len(&mut out, relocation.section);
+            out.extend(relocation.offset.to_le_bytes());
+            let kind = RelocationKind::ALL
+                .iter()
+                .position(|&k| k == relocation.kind);
+            out.push(u8::try_from(kind.unwrap_or_default()).unwrap_or_default());
+            out.extend(relocation.addend.to_le_bytes());
+            write_string(&mut out, &relocation.symbol);
+        }
+        write_string(&mut out, &self.lines.source);
+        write_len(&mut out, self.lines.lines.len());
+        for (address, line) in &self.lines.lines {
+            out.extend(address.to_le_bytes());
+            out.extend(line.to_le_bytes());
+        }
+        out
+    }
+
+    /// Global symbols of the object, for backtraces and the debugger.
+    #[must_use]
+    pub fn symbol_map(&self) -> SymbolMap {
+        let mut map = SymbolMap::default();
+        for symbol in self.symbols.iter().filter(|s| s.global) {
+            if symbol.section.is_some() {
+                map.insert(symbol.address, &symbol.name);
+            }
+        }
+        map
+    }
+
+    /// Raw memory image of the object, starting at address 0 and ending
+    /// with its last initialized section.
+    #[must_use]
+    pub fn to_image(&self) -> Vec<u8> {
+        let mut image = Vec::new();
+        for section in self.sections.iter().filter(|s| s.kind != SectionKind::Bss) {
+            let start = section.address as usize;
+            let end = start + section.bytes.len();
+            if image.len() < end {
+                image.resize(end, 0);
+            }
+            image[start..end].copy_from_slice(&section.bytes);
+        }
+        image
+    }
+
+    /// Source line of the instruction at `addr`.
+    #[must_use]
+    pub fn line_of(&self, addr: u32) -> Option<u32> {
+        let lines = &self.lines.lines;
+        let index = lines.partition_point(|&(a, _)| a <= addr);
+        index.checked_sub(1).map(|index| lines[index].1)
+    }
+}
+
+/// Index of the section containing `addr`, or ending at `addr` for labels
+/// placed after the last byte of a section.
+pub(crate) fn section_of(sections: &[Section], addr: u32) -> Option<usize> {
+    let addr = u64::from(addr);
+    let end = |s: &Section| u64::from(s.address) + s.bytes.len() as u64;
+    (sections.iter())
+        .position(|s| u64::from(s.address) <= addr && addr < end(s))
+        .or_else(|| sections.iter().position(|s| addr == end(s)))
+}
+
+/// Check whether the field patched by `relocation` already holds the value
+/// computed from the current address of its symbol.
+fn is_applied(object: &Object, relocation: &Relocation) -> bool {
+    let symbol = (object.symbols.iter()).find(|s| s.name == relocation.symbol);
+    let (Some(symbol), Some(section)) = (symbol, object.sections.get(relocation.section)) else {
+        return false;
+    };
+    let start = relocation.offset as usize;
+    let Some(field) = section.bytes.get(start..start + relocation.kind.size()) else {
+        return false;
+    };
+    let mut expected = field.to_vec();
+    let place = section.address.wrapping_add(relocation.offset);
+    let value = symbol.address.wrapping_add_signed(relocation.addend);
+    relocation.kind.apply(&mut expected, place, value).is_some() && expected == field
+}
+
+impl Machine {
+    /// Load the sections of `object`, then set IP to its entry point and SP
+    /// to its initial stack pointer. Code sections are made read-only and
+    /// the other ones non-executable, see [`Machine::protect`]. Adjacent
+    /// sections with the same protection share a region.
+    ///
+    /// # Errors
+    /// This function returns an error if the object references undefined
+    /// symbols or has relocations which have not been applied, if a
+    /// section does not fit in memory or if there are too many regions to
+    /// protect.
+    pub fn load_object(&mut self, object: &Object) -> Result<(), ObjectError> {
+        let defined =
+            |name: &str| (object.symbols.iter()).any(|s| s.name == name && s.section.is_some());
+        let undefined = (object.symbols.iter().map(|s| &s.name))
+            .chain(object.relocations.iter().map(|r| &r.symbol))
+            .find(|name| !defined(name));
+        if let Some(name) = undefined {
+            return Err(ObjectError::UndefinedSymbol(name.clone()));
+        }
+        if let Some(relocation) = (object.relocations.iter()).find(|r| !is_applied(object, r)) {
+            return Err(ObjectError::UnresolvedRelocation(relocation.symbol.clone()));
+        }
+        let mut sections = object.sections.iter().collect::<Vec<_>>();
+        sections.sort_by_key(|s| s.address);
+        let mut regions: Vec<(Range<u32>, Protection)> = Vec::new();
+        for section in sections {
+            self.load_at(section.address, &section.bytes, None)?;
+            let end = u32::try_from(section.bytes.len())
+                .ok()
+                .and_then(|len| section.address.checked_add(len))
+                .ok_or(Error::MemoryOverflow)?;
+            let protection = match section.kind {
+                SectionKind::Code => Protection::ReadOnly,
+                SectionKind::Data | SectionKind::Bss => Protection::NoExecute,
+            };
+            match regions.last_mut() {
+                Some((range, p)) if range.end == section.address && *p == protection => {
+                    range.end = end;
+                }
+                _ => regions.push((section.address..end, protection)),
+            }
+        }
+        for (range, protection) in regions {
+            self.protect(range, protection)?;
+        }
+        self.set_reg(0, object.entry)?;
+        Ok(self.set_reg(2, object.stack)?)
+    }
+
+    /// Create a machine running `program`, which is either an object file
+    /// or a raw memory image loaded at address 0 and starting there.
+    ///
+    /// # Errors
+    /// This function returns an error if the program is a malformed object
+    /// or does not fit in memory.
+    pub fn load_program(program: &[u8]) -> Result<Self, ObjectError> {
+        if !Object::is_object(program) {
+            return Ok(Self::new(program)?);
+        }
+        let mut machine = Self::new(&[])?;
+        machine.load_object(&Object::parse(program)?)?;
+        Ok(machine)
+    }
+}
@@ -0,0 +1,57 @@
+//! Library of routines following the calling convention checked by
+//! [`Machine::check_abi`](crate::Machine::check_abi): arguments are passed in
+//! r10, r11 and r12, results are returned in r11 and r12, and r8-r15 may be
+//! modified. The routines only use IP-relative jumps and addresses, so the
+//! library can be loaded anywhere, or linked with programs referencing them.
+//!
+//! | Routine          | Arguments                           | Results                  |
+//! |------------------|-------------------------------------|--------------------------|
+//! | `print`          | r10: address, r11: length           |                          |
+//! | `print_unsigned` | r10: number                         |                          |
+//! | `print_signed`   | r10: number                         |                          |
+//! | `mult`           | r10, r11: factors                   | r11: product             |
+//! | `divmod`         | r10: dividend, r11: divisor         | r11: quotient, r12: rest |
+//! | `sdivmod`        | r10: dividend, r11: divisor         | r11: quotient, r12: rest |
+//! | `memcpy`         | r10: dest, r11: source, r12: length |                          |
+//! | `memset`         | r10: dest, r11: byte, r12: length   |                          |
+//! | `strlen`         | r10: address                        | r11: length              |
+//! | `heap_init`      | r10: start, r11: end                |                          |
+//! | `alloc`          | r10: size                           | r11: address or 0        |
+//!
+//! `divmod` divides unsigned numbers, and `sdivmod` signed ones, rounding
+//! towards zero. As on RISC-V, dividing by zero gives a quotient with all
+//! bits set and the dividend as the rest. `alloc` hands out word-aligned
+//! blocks from the heap given to `heap_init`, and never frees them.
+//!
+//! Routines accessing memory byte by byte read or write whole words, so the
+//! three bytes following each accessed byte must be in memory.
+//!
+//! The routines are written in assembly, in `routines.s`, and their data
+//! references are relocated by the linker.
+
+use crate::{assemble_object, Object};
+
+/// Names of the routines of the library.
+pub const ROUTINES: [&str; 11] = [
+    "print",
+    "print_unsigned",
+    "print_signed",
+    "mult",
+    "divmod",
+    "sdivmod",
+    "memcpy",
+    "memset",
+    "strlen",
+    "heap_init",
+    "alloc",
+];
+
+/// Relocatable object containing the routines, each one defined by a global
+/// symbol, followed by the data of the allocator.
+///
+/// # Panics
+/// This function does not panic, as the tests assemble the library.
+#[must_use]
+pub fn routine_library() -> Object {
+    assemble_object(include_str!("routines.s")).expect("invalid routine library")
+}
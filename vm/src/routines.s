@@ -0,0 +1,289 @@
+; Library of routines, see routines.rs for their arguments and results
+
+.global print, print_unsigned, print_signed, mult, divmod, sdivmod
+.global memcpy, memset, strlen, heap_init, alloc
+
+; Size of the scratch memory reserved on the stack by routines which
+; isolate bytes or bits, as the instruction set has no shift
+.equ SCRATCH, 8
+.equ DIGIT_BIAS, '0' + 10
+
+; reg = reg + value, for a value without operators
+.macro add_imm reg, value
+        loadimm r3 <- #-\value
+        sub \reg <- \reg - r3
+.endm
+
+.macro ret
+        pop r0
+.endm
+
+; Reserve the scratch memory, which starts at SP
+.macro enter
+        add_imm r2, -SCRATCH
+.endm
+
+.macro leave
+        add_imm r2, SCRATCH
+.endm
+
+; r13 = SP + offset
+.macro scratch offset
+        loadimm r13 <- #-\offset
+        sub r13 <- r2 - r13
+.endm
+
+.macro negate_if reg, cond
+        sub r3 <- r1 - \reg
+        move \reg <- r3 if \cond != 0
+.endm
+
+; target = source >> 8, using the scratch memory
+.macro shift_right_8 target, source
+        store [r2] <- \source
+        scratch 4
+        store [r13] <- r1
+        scratch 1
+        load \target <- [r13]
+.endm
+
+; target = source >> 31, using the scratch memory
+.macro sign target, source
+        store [r2] <- \source
+        scratch 4
+        store [r13] <- r1
+        scratch 3
+        load \target <- [r13]
+        ; Bit 7 of the top byte becomes bit 8
+        add \target <- \target + \target
+        shift_right_8 \target, \target
+.endm
+
+; target = source & 0xff, using the scratch memory
+.macro low_byte target, source
+        store [r2] <- \source
+        scratch 1
+        store [r13] <- r1
+        load \target <- [r2]
+.endm
+
+; Replace the byte at the address in dest with the low byte of byte
+.macro store_byte dest, byte
+        load r8 <- [\dest]
+        low_byte r14, r8
+        sub r8 <- r8 - r14
+        low_byte r14, \byte
+        add r8 <- r8 + r14
+        store [\dest] <- r8
+.endm
+
+print:
+        jump_rel print_char if r11 != 0
+        ret
+print_char:
+        load r8 <- [r10]
+        out r8
+        add_imm r10, 1
+        add_imm r11, -1
+        jump_rel print
+
+; Digits are pushed, least significant first, above a marker
+print_unsigned:
+        loadimm r8 <- #10
+        push r8
+print_unsigned_divide:
+        loadimm r11 <- #10
+        jsr divmod
+        push r12
+        move r10 <- r11 if r0 != 0
+        jump_rel print_unsigned_divide if r10 != 0
+print_unsigned_digit:
+        pop r8
+        add_imm r8, -10
+        jump_rel print_unsigned_out if r8 != 0
+        ret
+print_unsigned_out:
+        add_imm r8, DIGIT_BIAS
+        out r8
+        jump_rel print_unsigned_digit
+
+print_signed:
+        enter
+        sign r8, r10
+        leave
+        jump_rel print_signed_negative if r8 != 0
+        jump_rel print_unsigned
+print_signed_negative:
+        loadimm r9 <- #'-'
+        out r9
+        sub r10 <- r1 - r10
+        jump_rel print_unsigned
+
+; Shift and add, from the most significant bit of r11
+mult:
+        enter
+        loadimm r12 <- #0
+        loadimm r9 <- #32
+mult_bit:
+        add r12 <- r12 + r12
+        sign r8, r11
+        jump_rel mult_add if r8 != 0
+        jump_rel mult_next
+mult_add:
+        add r12 <- r12 + r10
+mult_next:
+        add r11 <- r11 + r11
+        add_imm r9, -1
+        jump_rel mult_bit if r9 != 0
+        move r11 <- r12 if r0 != 0
+        leave
+        ret
+
+; Restoring division: r15 is the quotient, r12 the rest, and r8 the bit
+; shifted out of the rest, in which case it exceeds the divisor
+divmod:
+        enter
+        loadimm r12 <- #0
+        loadimm r15 <- #0
+        loadimm r9 <- #32
+divmod_bit:
+        add r15 <- r15 + r15
+        sign r8, r12
+        add r12 <- r12 + r12
+        sign r14, r10
+        add r12 <- r12 + r14
+        add r10 <- r10 + r10
+        jump_rel divmod_subtract if r8 != 0
+        ; Unsigned comparison of the rest and the divisor
+        sign r8, r12
+        sign r14, r11
+        sub r14 <- r8 - r14
+        jump_rel divmod_signs_differ if r14 != 0
+        sub r14 <- r12 - r11
+        sign r8, r14
+        jump_rel divmod_next if r8 != 0
+        jump_rel divmod_subtract
+divmod_signs_differ:
+        jump_rel divmod_subtract if r8 != 0
+        jump_rel divmod_next
+divmod_subtract:
+        sub r12 <- r12 - r11
+        add_imm r15, 1
+divmod_next:
+        add_imm r9, -1
+        jump_rel divmod_bit if r9 != 0
+        move r11 <- r15 if r0 != 0
+        leave
+        ret
+
+; The quotient is negative if the signs differ, the rest has the sign of
+; the dividend
+sdivmod:
+        enter
+        sign r8, r10
+        sign r9, r11
+        leave
+        push r8
+        sub r14 <- r8 - r9
+        ; Dividing by 0 gives a quotient with all bits set, whatever the signs
+        loadimm r15 <- #0
+        move r15 <- r14 if r11 != 0
+        push r15
+        negate_if r10, r8
+        negate_if r11, r9
+        jsr divmod
+        pop r8
+        negate_if r11, r8
+        pop r8
+        negate_if r12, r8
+        ret
+
+memcpy:
+        enter
+memcpy_byte:
+        jump_rel memcpy_copy if r12 != 0
+        leave
+        ret
+memcpy_copy:
+        load r9 <- [r11]
+        store_byte r10, r9
+        add_imm r10, 1
+        add_imm r11, 1
+        add_imm r12, -1
+        jump_rel memcpy_byte
+
+memset:
+        enter
+memset_byte:
+        jump_rel memset_copy if r12 != 0
+        leave
+        ret
+memset_copy:
+        store_byte r10, r11
+        add_imm r10, 1
+        add_imm r12, -1
+        jump_rel memset_byte
+
+strlen:
+        enter
+        move r9 <- r10 if r0 != 0
+        loadimm r11 <- #0
+strlen_byte:
+        load r8 <- [r9]
+        low_byte r8, r8
+        jump_rel strlen_next if r8 != 0
+        leave
+        ret
+strlen_next:
+        add_imm r9, 1
+        add_imm r11, 1
+        jump_rel strlen_byte
+
+heap_init:
+        loadrel r8 <- heap_next
+        store [r8] <- r10
+        loadrel r8 <- heap_end
+        store [r8] <- r11
+        ret
+
+; The size is checked against the available memory before and after being
+; rounded up to a multiple of 4, so that rounding cannot overflow
+alloc:
+        enter
+        loadrel r8 <- heap_next
+        load r9 <- [r8]
+        loadrel r14 <- heap_end
+        load r14 <- [r14]
+        sub r14 <- r14 - r9
+        sub r15 <- r14 - r10
+        sign r15, r15
+        jump_rel alloc_fail if r15 != 0
+        add_imm r10, 3
+        ; Clear the two low bits by shifting them out of the top byte
+        add r10 <- r10 + r10
+        add r10 <- r10 + r10
+        add r10 <- r10 + r10
+        add r10 <- r10 + r10
+        add r10 <- r10 + r10
+        add r10 <- r10 + r10
+        shift_right_8 r10, r10
+        add r10 <- r10 + r10
+        add r10 <- r10 + r10
+        sub r15 <- r14 - r10
+        sign r15, r15
+        jump_rel alloc_fail if r15 != 0
+        move r11 <- r9 if r0 != 0
+        add r9 <- r9 + r10
+        store [r8] <- r9
+        leave
+        ret
+alloc_fail:
+        loadimm r11 <- #0
+        leave
+        ret
+
+.data
+heap_next:
+        .word 0
+heap_end:
+        .word 0
@@ -0,0 +1,81 @@
+//! Symbol maps associating addresses with function names, as written by
+//! `generator.py` into `.sym` files: one `ADDRESS NAME` entry per line.
+
+use std::fmt::Write;
+
+use crate::Backtrace;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    /// Symbols sorted by address
+    symbols: Vec<(u32, String)>,
+}
+
+impl SymbolMap {
+    /// Parse the content of a `.sym` file. Empty lines are ignored.
+    #[must_use]
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut symbols = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (addr, name) = line.trim().split_once(char::is_whitespace)?;
+                Some((addr.parse().ok()?, name.trim().to_owned()))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        symbols.sort();
+        Some(Self { symbols })
+    }
+
+    /// Add a symbol to the map.
+    pub fn insert(&mut self, addr: u32, name: &str) {
+        let index = self.symbols.partition_point(|(a, _)| *a <= addr);
+        self.symbols.insert(index, (addr, name.to_owned()));
+    }
+
+    /// Name of the symbol with the highest address lower than or equal to
+    /// `addr`, that is the function containing `addr`.
+    #[must_use]
+    pub fn lookup(&self, addr: u32) -> Option<&str> {
+        let index = self.symbols.partition_point(|(a, _)| *a <= addr);
+        index
+            .checked_sub(1)
+            .map(|index| self.symbols[index].1.as_str())
+    }
+
+    /// Address of the symbol named `name`.
+    #[must_use]
+    pub fn address_of(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find_map(|(addr, n)| (n == name).then_some(*addr))
+    }
+
+    /// Iterate over the symbols by increasing address.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.symbols
+            .iter()
+            .map(|(addr, name)| (*addr, name.as_str()))
+    }
+
+    /// Render `backtrace` using function names, such as
+    /// `mult <- rfact <- rfact <- main`. Addresses which do not belong to
+    /// any known function are printed as is.
+    #[must_use]
+    pub fn symbolize(&self, backtrace: &Backtrace) -> String {
+        let mut s = String::new();
+        for (i, &addr) in backtrace.frames().iter().enumerate() {
+            if i > 0 {
+                s.push_str(" <- ");
+            }
+            match self.lookup(addr) {
+                Some(name) => s.push_str(name),
+                None => write!(s, "{addr:04}").unwrap(),
+            }
+        }
+        if backtrace.is_truncated() {
+            s.push_str(" <- ...");
+        }
+        s
+    }
+}
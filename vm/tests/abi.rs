@@ -0,0 +1,170 @@
+use interpreter::{AbiDiagnostic, AbiViolation, Error, Machine};
+
+// 0: loadimm r2 <- #1000
+// 4: loadimm r3 <- #4
+// 8: sub r2 <- r2 - r3
+// 12: loadimm r3 <- #23
+// 16: store [r2] <- r3
+// 19: loadimm r0 <- #24
+// 23: exit
+// 24: function body
+const CALL: [u8; 24] = [
+    4, 2, 0xe8, 3, 4, 3, 4, 0, 5, 2, 2, 3, 4, 3, 23, 0, 2, 2, 3, 4, 0, 24, 0, 7,
+];
+
+// 0: loadimm r3 <- #-4
+// 4: sub r2 <- r2 - r3
+// 8: loadimm r3 <- #4
+// 12: sub r3 <- r2 - r3
+// 16: load r0 <- [r3]
+const RETURN: [u8; 19] = [
+    4, 3, 0xfc, 0xff, 5, 2, 2, 3, 4, 3, 4, 0, 5, 3, 2, 3, 3, 0, 3,
+];
+
+fn call_function(body: &[u8]) -> Result<Machine, Error> {
+    let mut code = CALL.to_vec();
+    code.extend(body);
+    code.extend(RETURN);
+    let mut machine = Machine::new(&code).unwrap();
+    machine.check_abi(true);
+    machine.run_on(&mut Vec::new()).map(|()| machine)
+}
+
+fn violation(body: &[u8]) -> AbiDiagnostic {
+    match call_function(body) {
+        Err(Error::AbiViolation(diagnostic)) => diagnostic,
+        r => panic!("unexpected result {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn test_well_behaved_function() {
+    // 24: loadimm r10 <- #1
+    // 28: loadimm r3 <- #1
+    call_function(&[4, 10, 1, 0, 4, 3, 1, 0]).unwrap();
+}
+
+#[test]
+fn test_zero_register_modified() {
+    // 24: loadimm r1 <- #1
+    let diagnostic = violation(&[4, 1, 1, 0]);
+    assert_eq!(
+        AbiViolation::ZeroRegisterModified { value: 1 },
+        diagnostic.violation
+    );
+    assert_eq!(24, diagnostic.ip);
+    assert_eq!(&[24, 23], diagnostic.backtrace.frames());
+}
+
+#[test]
+fn test_callee_saved_not_restored() {
+    // 24: loadimm r5 <- #42
+    let diagnostic = violation(&[4, 5, 42, 0]);
+    assert_eq!(
+        AbiViolation::CalleeSavedNotRestored {
+            reg: 5,
+            expected: 0,
+            actual: 42
+        },
+        diagnostic.violation
+    );
+    // Reported on the return instruction
+    assert_eq!(44, diagnostic.ip);
+    assert_eq!(&[44, 23], diagnostic.backtrace.frames());
+}
+
+#[test]
+fn test_unbalanced_stack() {
+    // 24: loadimm r3 <- #8
+    // 28: sub r2 <- r2 - r3
+    // 32: loadimm r3 <- #23
+    // 36: store [r2] <- r3
+    let diagnostic = violation(&[4, 3, 8, 0, 5, 2, 2, 3, 4, 3, 23, 0, 2, 2, 3]);
+    assert_eq!(
+        AbiViolation::UnbalancedStack {
+            expected: 1000,
+            actual: 992
+        },
+        diagnostic.violation
+    );
+}
+
+#[test]
+fn test_unknown_return_address() {
+    // 24: loadimm r3 <- #50
+    // 28: store [r2] <- r3
+    let diagnostic = violation(&[4, 3, 50, 0, 2, 2, 3]);
+    assert_eq!(
+        AbiViolation::UnknownReturnAddress { addr: 50 },
+        diagnostic.violation
+    );
+    assert_eq!(47, diagnostic.ip);
+
+    // 0: load r0 <- [r2] without any call
+    let mut machine = Machine::new(&[3, 0, 2]).unwrap();
+    machine.set_reg(2, 100).unwrap();
+    machine.check_abi(true);
+    assert!(matches!(
+        machine.step_on(&mut Vec::new()),
+        Err(Error::AbiViolation(AbiDiagnostic {
+            violation: AbiViolation::UnknownReturnAddress { addr: 0 },
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn test_execution_can_resume() {
+    let mut machine = Machine::new(include_bytes!("push_pop.bin")).unwrap();
+    machine.check_abi(true);
+    let mut out = Vec::new();
+    let mut violations = Vec::new();
+    while let Err(Error::AbiViolation(diagnostic)) = machine.run_on(&mut out) {
+        violations.push(diagnostic.violation);
+    }
+    assert_eq!(
+        vec![AbiViolation::ZeroRegisterModified { value: 26 }],
+        violations
+    );
+    assert_eq!(15, machine.regs()[2]);
+}
+
+#[test]
+fn test_generated_programs_follow_the_abi() {
+    for program in [
+        &include_bytes!("function.bin")[..],
+        include_bytes!("multiply.bin"),
+        include_bytes!("fact.bin"),
+        include_bytes!("afact.bin"),
+        include_bytes!("rfact.bin"),
+        include_bytes!("rfact_tr.bin"),
+        include_bytes!("fibo.bin"),
+        include_bytes!("pic.bin"),
+        include_bytes!("../examples/99bottles.bin"),
+        include_bytes!("../examples/factorial.bin"),
+    ] {
+        let mut machine = Machine::new(program).unwrap();
+        machine.set_reg(10, 6).unwrap();
+        machine.set_reg(12, 6).unwrap();
+        machine.check_abi(true);
+        machine.run_on(&mut Vec::new()).unwrap();
+        assert_eq!(Some(1), machine.abi_backtrace().map(|b| b.frames().len()));
+    }
+}
+
+#[test]
+fn test_recursive_backtrace() {
+    // Stop inside the innermost call to mult in rfact
+    let mut machine = Machine::new(include_bytes!("rfact.bin")).unwrap();
+    machine.set_reg(10, 3).unwrap();
+    machine.check_abi(true);
+    let mut out = Vec::new();
+    // mult starts at address 24
+    while machine.regs()[0] != 24 {
+        machine.step_on(&mut out).unwrap();
+    }
+    // mult <- rfact(2) <- rfact(3) <- main
+    let backtrace = machine.abi_backtrace().unwrap();
+    assert_eq!(4, backtrace.frames().len());
+    assert_eq!("0024 <- ", &backtrace.to_string()[..8]);
+}
@@ -0,0 +1,395 @@
+use interpreter::{
+    assemble, assemble_in, assemble_object, disassemble_with, AssemblyError, DisassemblyOptions,
+    Error, Instruction, Machine, Relocation, RelocationKind, SectionKind, Symbol, REGISTER_NAMES,
+};
+use std::path::Path;
+
+fn tests_dir() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests"))
+}
+
+fn run(source: &str) -> (Vec<u8>, Machine) {
+    let mut machine = Machine::new(&assemble_in(source, tests_dir()).unwrap()).unwrap();
+    let mut output = Vec::new();
+    machine.run_on(&mut output).unwrap();
+    (output, machine)
+}
+
+fn error(source: &str) -> (usize, Error) {
+    let error = assemble(source).unwrap_err();
+    (error.line, error.error)
+}
+
+fn encode(instructions: &[Instruction]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for instruction in instructions {
+        let mut encoded = [0; Instruction::MAX_SIZE];
+        let len = instruction.encode(&mut encoded).unwrap();
+        bytes.extend(&encoded[..len]);
+    }
+    bytes
+}
+
+#[test]
+fn test_predefined_macros() {
+    // Same program as the one written by `generator.py`
+    assert_eq!(
+        Ok(include_bytes!("push_pop.bin").to_vec()),
+        assemble("loadimm r2 <- #4096\npush r0\npush r0\npop r1\npop r2\nexit")
+    );
+}
+
+#[test]
+fn test_hello_world() {
+    let source = r#"
+.equ STACK, 4096
+        loadimm r2 <- #STACK
+        loadrel r10 <- message
+        loadimm r11 <- #message_end-message
+        jsr print
+        exit
+.include "asm/print.inc"
+message: .ascii "Hello, world!\n"
+message_end:
+"#;
+    let (output, machine) = run(source);
+    assert_eq!(b"Hello, world!\n", &output[..]);
+    assert_eq!(4096, machine.regs()[2]);
+}
+
+#[test]
+fn test_data_directives() {
+    let source = r#"
+.equ SIZE, 3
+start:  .byte 1, -1, 'A', '\n'
+        .word 0x12345678, -2, end
+        .space SIZE, 0xaa
+        .align 4
+        .ascii "a;b\"c", ""
+        .space 1
+end:    .byte end - start
+        .byte ';', ',', '\'', '"' ; Quotes in characters
+"#;
+    let mut expected = vec![1, 255, b'A', b'\n', 0x78, 0x56, 0x34, 0x12];
+    expected.extend([254, 255, 255, 255, 26, 0, 0, 0, 0xaa, 0xaa, 0xaa, 0]);
+    expected.extend(b"a;b\"c\0\x1a;,'\"");
+    assert_eq!(Ok(expected), assemble(source));
+}
+
+#[test]
+fn test_labels() {
+    let source = "
+        jump_rel skip
+        exit
+skip:   loadrel r4 <- data
+        load r5 <- [r4]
+        jump_rel done if r5 != 0
+        exit
+done:   loadimm r6 <- #data+2
+        exit
+data:   .word 7
+";
+    let (_, machine) = run(source);
+    assert_eq!(
+        (7, machine.regs()[4] + 2),
+        (machine.regs()[5], machine.regs()[6])
+    );
+    // Offsets may still be given explicitly
+    assert_eq!(
+        assemble("jump_rel ip+1\nexit\nexit"),
+        assemble("jump_rel next\nexit\nnext: exit")
+    );
+}
+
+#[test]
+fn test_pseudo_instructions() {
+    assert_eq!(
+        Ok(encode(&[
+            Instruction::Sub {
+                target: 3,
+                op1: 1,
+                op2: 6
+            },
+            Instruction::Sub {
+                target: 4,
+                op1: 5,
+                op2: 3
+            },
+            Instruction::MoveIf {
+                target: 0,
+                source: 7,
+                cond: 0
+            },
+            Instruction::LoadImm {
+                target: 0,
+                value: 42
+            },
+            Instruction::MoveIf {
+                target: 0,
+                source: 7,
+                cond: 8
+            },
+            Instruction::LoadImm {
+                target: 3,
+                value: 42
+            },
+            Instruction::MoveIf {
+                target: 0,
+                source: 3,
+                cond: 8
+            },
+            Instruction::Sub {
+                target: 1,
+                op1: 1,
+                op2: 1
+            },
+        ])),
+        assemble("add r4 <- r5 + r6\njmp r7\njmp 42\njnz r8, r7\njnz r8, 42\nnop")
+    );
+
+    // Count down from 5 to 0, adding every value to r4
+    let source = "
+        loadimm r5 <- #5
+loop:   add r4 <- r4 + r5
+        loadimm r6 <- #1
+        sub r5 <- r5 - r6
+        nop
+        jnz r5, loop
+        jmp end
+        exit
+end:    exit
+";
+    let (_, machine) = run(source);
+    assert_eq!((15, 0), (machine.regs()[4], machine.regs()[5]));
+
+    // r3 is overwritten before being used
+    assert_eq!((1, Error::InvalidSyntax), error("add r4 <- r3 + r5"));
+    assert_eq!((1, Error::InvalidSyntax), error("jnz r3, 42"));
+    assert_eq!((2, Error::InvalidSyntax), error("exit\npush r3"));
+    assert_eq!((1, Error::InvalidSyntax), error("pop tmp"));
+}
+
+#[test]
+fn test_macros() {
+    let source = "
+.macro inc reg, by
+        loadimm r3 <- #-\\by
+        sub \\reg <- \\reg - r3
+.endm
+.macro countdown reg
+loop_\\@:
+        inc \\reg, -1
+        jnz \\reg, loop_\\@
+.endm
+        loadimm r4 <- #3
+        inc r4, 4
+        out_number r4
+        countdown r4
+        loadimm r5 <- #2
+        countdown r5
+        exit
+";
+    let (output, machine) = run(source);
+    assert_eq!(b"7", &output[..]);
+    assert_eq!((0, 0), (machine.regs()[4], machine.regs()[5]));
+}
+
+#[test]
+fn test_errors() {
+    // Wrong number of arguments, reported at the invocation
+    assert_eq!(
+        (3, Error::InvalidSyntax),
+        error(".macro one a\n.endm\none\nexit")
+    );
+    // Unterminated, nested and recursive definitions
+    assert_eq!((2, Error::InvalidSyntax), error(".macro one\nexit"));
+    assert_eq!((2, Error::InvalidSyntax), error(".macro one\n.macro two"));
+    assert_eq!((1, Error::InvalidSyntax), error(".endm"));
+    assert_eq!(
+        (4, Error::InvalidSyntax),
+        error(".macro loop\nloop\n.endm\nloop")
+    );
+    // Symbols
+    assert_eq!(
+        (2, Error::InvalidSyntax),
+        error("exit\nloadimm r4 <- #nowhere")
+    );
+    assert_eq!((2, Error::InvalidSyntax), error("a: exit\na: exit"));
+    assert_eq!((1, Error::InvalidSyntax), error(".space n\nn: exit"));
+    assert_eq!((1, Error::InvalidSyntax), error("loadimm r4 <- #40000"));
+    assert_eq!((1, Error::MemoryOverflow), error(".space 5000"));
+
+    let error = assemble_in(".include \"asm/invalid.inc\"", tests_dir()).unwrap_err();
+    assert!(error.file.as_ref().unwrap().ends_with("invalid.inc"));
+    assert_eq!(2, error.line);
+    assert!(error
+        .to_string()
+        .ends_with("invalid.inc:line 2: InvalidSyntax"));
+    assert_eq!(
+        Err(AssemblyError {
+            file: None,
+            line: 2,
+            error: Error::InvalidSyntax
+        }),
+        assemble_in("exit\n.include \"asm/missing.inc\"", tests_dir())
+    );
+}
+
+#[test]
+fn test_register_names() {
+    for (reg, name) in REGISTER_NAMES.iter().enumerate() {
+        assert_eq!(
+            assemble(&format!("out r{reg}")),
+            assemble(&format!("out {name}"))
+        );
+    }
+    assert_eq!(
+        Ok(Instruction::MoveIf {
+            target: 0,
+            source: 9,
+            cond: 8
+        }),
+        "move ip <- t1 if t0 != 0".parse()
+    );
+    assert_eq!(
+        assemble("store [r2] <- r10\nload r11 <- [r3]\njump_rel ip+0 if r12 != 0"),
+        assemble("store [sp] <- a0\nload a1 <- [tmp]\njump_rel ip+0 if a2 != 0")
+    );
+    // Register names are not symbols
+    assert_eq!((1, Error::InvalidSyntax), error("sp: exit"));
+    assert_eq!((1, Error::InvalidSyntax), error(".equ a0, 1"));
+}
+
+#[test]
+fn test_disassembly_options() {
+    let bytes = assemble(
+        "jmp r9\njnz r8, r9\njmp 42\nnop\nmove r4 <- r5 if r1 != 0\nstore [r2] <- r10\n\
+         jump_rel ip-3 if r11 != 0\nloadimm32 r0 <- #42",
+    )
+    .unwrap();
+    let lines = |aliases, idioms| {
+        let text = disassemble_with(&bytes, DisassemblyOptions { aliases, idioms });
+        assert_eq!(Ok(bytes.clone()), assemble(&text));
+        text.lines()
+            .map(|line| line[9..].to_owned())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        vec![
+            "move r0 <- r9 if r0 != 0",
+            "move r0 <- r9 if r8 != 0",
+            "loadimm r0 <- #42",
+            "sub r1 <- r1 - r1",
+            "move r4 <- r5 if r1 != 0",
+            "store [r2] <- r10",
+            "jump_rel ip-3 if r11 != 0",
+            "loadimm32 r0 <- #42",
+        ],
+        lines(false, false)
+    );
+    assert_eq!(
+        vec![
+            "jmp r9",
+            "jnz r8, r9",
+            "jmp 42",
+            "nop",
+            "move r4 <- r5 if r1 != 0",
+            "store [r2] <- r10",
+            "jump_rel ip-3 if r11 != 0",
+            "loadimm32 r0 <- #42",
+        ],
+        lines(false, true)
+    );
+    assert_eq!(
+        vec![
+            "jmp t1",
+            "jnz t0, t1",
+            "jmp 42",
+            "nop",
+            "move s0 <- s1 if zero != 0",
+            "store [sp] <- a0",
+            "jump_rel ip-3 if a1 != 0",
+            "loadimm32 ip <- #42",
+        ],
+        lines(true, true)
+    );
+    assert_eq!("move ip <- t1 if ip != 0", lines(true, false)[0]);
+}
+
+#[test]
+fn test_objects() {
+    let object = assemble_object(
+        ".global start
+         start: loadimm r4 <- #value+4
+         loadrel r5 <- value
+         jump_rel start
+         loadimm32 r6 <- #start
+         exit
+         .data
+         value: .word start, 7",
+    )
+    .unwrap();
+    assert_eq!(SectionKind::Code, object.sections[0].kind);
+    assert_eq!(20, object.sections[0].bytes.len());
+    assert_eq!(SectionKind::Data, object.sections[1].kind);
+    assert_eq!(20, object.sections[1].address);
+    assert_eq!(vec![0, 0, 0, 0, 7, 0, 0, 0], object.sections[1].bytes);
+    let symbol = |name: &str, address, section, global| Symbol {
+        name: String::from(name),
+        address,
+        section: Some(section),
+        global,
+    };
+    assert_eq!(
+        vec![symbol("start", 0, 0, true), symbol("value", 20, 1, false)],
+        object.symbols
+    );
+    let relocation = |section, offset, kind, symbol: &str, addend| Relocation {
+        section,
+        offset,
+        kind,
+        symbol: String::from(symbol),
+        addend,
+    };
+    // The jump stays within the code, so its offset is not relocated
+    assert_eq!(
+        vec![
+            relocation(0, 2, RelocationKind::Low, "value", 4),
+            relocation(0, 3, RelocationKind::High, "value", 4),
+            relocation(0, 6, RelocationKind::Relative, "value", -2),
+            relocation(0, 13, RelocationKind::Word, "start", 0),
+            relocation(1, 0, RelocationKind::Word, "start", 0),
+        ],
+        object.relocations
+    );
+
+    let error = |source| assemble_object(source).map(|_| ()).unwrap_err().line;
+    assert_eq!(2, error(".equ c, 1\n.global c"));
+    assert_eq!(1, error(".global r4"));
+    assert_eq!(3, error("main: exit\n.data\nx: .word x-main"));
+    assert_eq!(2, error("x: exit\n.byte x"));
+    assert_eq!(2, error(".data\n.data"));
+    // Undefined global symbols are imported
+    let object = assemble_object(".global f\njump_rel f\nloadimm r4 <- #f+1").unwrap();
+    assert_eq!(
+        vec![Symbol {
+            name: String::from("f"),
+            address: 0,
+            section: None,
+            global: true,
+        }],
+        object.symbols
+    );
+    assert_eq!(
+        vec![
+            relocation(0, 1, RelocationKind::Relative, "f", -2),
+            relocation(0, 5, RelocationKind::Low, "f", 1),
+            relocation(0, 6, RelocationKind::High, "f", 1),
+        ],
+        object.relocations
+    );
+    assert_eq!(2, error(".global f, g\nloadimm r4 <- #f-g"));
+    assert!(assemble(".global f\njump_rel f").is_err());
+    // A plain assembly only aligns the data
+    assert_eq!(Ok(vec![7, 0, 0, 0, 1]), assemble("exit\n.data\n.byte 1"));
+}
@@ -27,7 +27,7 @@ fn test_assignment() {
 
     // load
     let mut mem = vec![3, 1, 2];
-    mem.extend(std::iter::repeat(0).take(22));
+    mem.extend(std::iter::repeat_n(0, 22));
     mem.extend(&[0xcd, 0xab, 0x34, 0x12]);
     let (m, _) = create_machine(&mem);
     assert_eq!(0x1234_abcd, m.regs()[1]);
@@ -42,7 +42,7 @@ fn test_assignment() {
     let (m, _) = create_machine(&[5, 10, 2, 1]);
     assert_eq!(15, m.regs()[10]);
     let (m, _) = create_machine(&[5, 10, 4, 1]);
-    assert_eq!(-10, m.regs()[10] as i32);
+    assert_eq!(-10, m.regs()[10].cast_signed());
 
     // out
     let (_, out) = create_machine(&[6, 5]);
@@ -0,0 +1,127 @@
+use interpreter::{AsyncOutput, Error, Machine};
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+/// Poll `future` until it completes or `max_polls` is reached, and return
+/// its output along with the number of polls.
+fn poll_at_most<F: Future>(future: F, max_polls: usize) -> (Option<F::Output>, usize) {
+    let mut future = pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    for polls in 1..=max_polls {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return (Some(output), polls);
+        }
+    }
+    (None, max_polls)
+}
+
+fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+    let (output, polls) = poll_at_most(future, usize::MAX);
+    (output.unwrap(), polls)
+}
+
+#[test]
+fn test_hello_world() {
+    let mut machine = Machine::new(include_bytes!("../examples/hello_world.bin")).unwrap();
+    let mut output = Vec::new();
+    let (result, _) = block_on(machine.run_async(&mut output, 100));
+    assert_eq!(Ok(()), result);
+    assert_eq!(b"Hello, world!\n", &output[..]);
+}
+
+#[test]
+fn test_yields_every_batch() {
+    let program = include_bytes!("../examples/count.bin");
+    let mut machine = Machine::new(program).unwrap();
+    let mut steps = 1usize;
+    while !machine.step_on(&mut Vec::new()).unwrap() {
+        steps += 1;
+    }
+    for batch in [1, 10, 1000] {
+        let mut machine = Machine::new(program).unwrap();
+        let (result, polls) = block_on(machine.run_async(&mut Vec::new(), batch));
+        assert_eq!(Ok(()), result);
+        assert_eq!(steps.div_ceil(batch), polls);
+    }
+}
+
+#[test]
+fn test_cancel_and_resume() {
+    let program = include_bytes!("../examples/count.bin");
+    let mut expected = Vec::new();
+    Machine::new(program)
+        .unwrap()
+        .run_on(&mut expected)
+        .unwrap();
+    let mut machine = Machine::new(program).unwrap();
+    let mut output = Vec::new();
+    let (result, _) = poll_at_most(machine.run_async(&mut output, 5), 10);
+    assert_eq!(None, result);
+    assert!(output.len() < expected.len());
+    // The future has been dropped, the machine can run again
+    let (result, _) = block_on(machine.run_async(&mut output, 5));
+    assert_eq!(Ok(()), result);
+    assert_eq!(expected, output);
+}
+
+/// Sink which is not ready once per write and fails after `capacity` bytes.
+struct SlowSink {
+    written: Vec<u8>,
+    capacity: usize,
+}
+
+struct NotReadyOnce(bool);
+
+impl Future for NotReadyOnce {
+    type Output = ();
+
+    fn poll(mut self: std::pin::Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            Poll::Pending
+        }
+    }
+}
+
+impl AsyncOutput for SlowSink {
+    async fn write_all(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        NotReadyOnce(false).await;
+        if self.written.len() + bytes.len() > self.capacity {
+            return Err(Error::OutputError);
+        }
+        self.written.extend_from_slice(bytes);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_slow_sink() {
+    let mut machine = Machine::new(include_bytes!("../examples/hello_world.bin")).unwrap();
+    let mut sink = SlowSink {
+        written: Vec::new(),
+        capacity: 100,
+    };
+    let (result, polls) = block_on(machine.run_async(&mut sink, 1000));
+    assert_eq!(Ok(()), result);
+    assert_eq!(b"Hello, world!\n", &sink.written[..]);
+    // One extra poll per written character
+    assert!(polls > 14);
+}
+
+#[test]
+fn test_errors() {
+    let mut machine = Machine::new(include_bytes!("../examples/hello_world.bin")).unwrap();
+    let mut sink = SlowSink {
+        written: Vec::new(),
+        capacity: 5,
+    };
+    let (result, _) = block_on(machine.run_async(&mut sink, 1000));
+    assert_eq!(Err(Error::OutputError), result);
+    assert_eq!(b"Hello", &sink.written[..]);
+    let mut machine = Machine::new(&[0]).unwrap();
+    let (result, _) = block_on(machine.run_async(&mut Vec::new(), 1000));
+    assert_eq!(Err(Error::UnknownOpcode(0)), result);
+}
@@ -0,0 +1,163 @@
+use interpreter::{
+    assemble, Debugger, Error, Fault, Machine, PageTable, Protection, SymbolMap, PAGE_EXECUTE,
+    PAGE_READ, PAGE_WRITE,
+};
+
+fn symbols(text: &str) -> SymbolMap {
+    SymbolMap::parse(text).unwrap()
+}
+
+fn stop_at(machine: &mut Machine, addr: u32) {
+    let mut out = Vec::new();
+    while machine.regs()[0] != addr {
+        machine.step_on(&mut out).unwrap();
+    }
+}
+
+#[test]
+fn test_symbol_map() {
+    let map = symbols("0024 mult\n\n0000 main\n0087 rfact\n");
+    assert_eq!(Some("main"), map.lookup(0));
+    assert_eq!(Some("main"), map.lookup(23));
+    assert_eq!(Some("mult"), map.lookup(24));
+    assert_eq!(Some("rfact"), map.lookup(4000));
+    assert_eq!(Some(87), map.address_of("rfact"));
+    assert_eq!(None, map.address_of("fact"));
+    assert_eq!(
+        vec![(0, "main"), (24, "mult"), (87, "rfact")],
+        map.iter().collect::<Vec<_>>()
+    );
+
+    let mut map = symbols("0010 f");
+    assert_eq!(None, map.lookup(9));
+    map.insert(5, "g");
+    assert_eq!(Some("g"), map.lookup(9));
+
+    assert!(SymbolMap::parse("0010").is_none());
+    assert!(SymbolMap::parse("abcd f").is_none());
+}
+
+#[test]
+fn test_recursive_backtrace() {
+    let mut machine = Machine::new(include_bytes!("rfact.bin")).unwrap();
+    machine.set_reg(10, 4).unwrap();
+    machine.check_abi(true);
+    stop_at(&mut machine, 24);
+    let backtrace = machine.backtrace();
+    assert_eq!(
+        "mult <- rfact <- rfact <- rfact <- main",
+        symbols(include_str!("rfact.sym")).symbolize(&backtrace)
+    );
+    // The reconstructed backtrace matches the calls tracked by the
+    // calling convention checker.
+    assert_eq!(Some(backtrace), machine.abi_backtrace());
+}
+
+#[test]
+fn test_backtrace_without_symbols() {
+    let mut machine = Machine::new(include_bytes!("fact.bin")).unwrap();
+    machine.set_reg(10, 3).unwrap();
+    stop_at(&mut machine, 24);
+    // mult, called from fact (return to 134), called from main (return to 23)
+    assert_eq!(&[24, 134, 23], machine.backtrace().frames());
+    assert_eq!("0024 <- 0134 <- 0023", machine.backtrace().to_string());
+    assert_eq!(
+        "0024 <- 0134 <- 0023",
+        SymbolMap::default().symbolize(&machine.backtrace())
+    );
+}
+
+#[test]
+fn test_saved_registers_are_not_frames() {
+    // rfact pushes r10 before recursing, those values must be skipped
+    let mut machine = Machine::new(include_bytes!("rfact.bin")).unwrap();
+    machine.set_reg(10, 3).unwrap();
+    stop_at(&mut machine, 24);
+    assert_eq!(&[24, 187, 149, 23], machine.backtrace().frames());
+}
+
+#[test]
+fn test_fault_backtrace() {
+    let mut machine = Machine::new(include_bytes!("rfact_tr.bin")).unwrap();
+    machine.set_reg(10, 12).unwrap();
+    // Simulate a small stack
+    machine.protect(4000..4040, Protection::Guard).unwrap();
+    let Err(Fault { error, backtrace }) = machine.run_traced_on(&mut Vec::new()) else {
+        panic!("no fault");
+    };
+    assert!(matches!(error, Error::GuardViolation(4036)));
+    assert_eq!(
+        "rfact_tr <- rfact_tr <- rfact_tr <- rfact_tr <- rfact_tr <- rfact_tr <- rfact_tr <- main",
+        symbols(include_str!("rfact_tr.sym")).symbolize(&backtrace)
+    );
+}
+
+#[test]
+fn test_truncated_backtrace() {
+    let mut machine = Machine::new(include_bytes!("rfact.bin")).unwrap();
+    machine.set_reg(10, 30).unwrap();
+    stop_at(&mut machine, 24);
+    let backtrace = machine.backtrace();
+    assert!(backtrace.is_truncated());
+    assert!(symbols(include_str!("rfact.sym"))
+        .symbolize(&backtrace)
+        .ends_with("rfact <- ..."));
+}
+
+#[test]
+fn test_debugger() {
+    let mut machine = Machine::new(include_bytes!("rfact.bin")).unwrap();
+    machine.set_reg(10, 3).unwrap();
+    let mut debugger = Debugger::new(machine, Some(symbols(include_str!("rfact.sym"))));
+    let mut out = Vec::new();
+    let commands = "s 5\nstep 5\nbt\nregs\ns x\nfoo\nc\nc\nquit\ns\n";
+    debugger.repl(commands.as_bytes(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    let lines = out.lines().collect::<Vec<_>>();
+    assert_eq!("(vm) ip = 0019 in main", lines[0]);
+    assert_eq!("(vm) ip = 0111 in rfact", lines[1]);
+    assert_eq!("(vm) rfact <- main", lines[2]);
+    assert_eq!(
+        "(vm)  r0 0x0000006f   r1 0x00000000   r2 0x00000ffc   r3 0x00000017",
+        lines[3]
+    );
+    assert_eq!("(vm) invalid instruction count", lines[7]);
+    assert_eq!("(vm) unknown command foo, try help", lines[8]);
+    assert_eq!("(vm) the program has exited", lines[9]);
+    assert_eq!("(vm) the program has exited", lines[10]);
+    assert_eq!("(vm) ", lines[11]);
+    assert_eq!(12, lines.len());
+    assert_eq!(6, debugger.machine().regs()[11]);
+}
+
+#[test]
+fn test_stack_at_end_of_memory() {
+    let mut machine = Machine::new(include_bytes!("fact.bin")).unwrap();
+    for sp in [u32::MAX, u32::MAX - 3, 4094] {
+        machine.set_reg(2, sp).unwrap();
+        assert_eq!(&[0], machine.backtrace().frames());
+    }
+}
+
+#[test]
+fn test_paged_backtrace() {
+    // Code and stack are at virtual addresses beyond the physical memory
+    let mut machine = Machine::new(&[]).unwrap();
+    let code = assemble("loadimm32 r2 <- #0x8100\njsr f\nexit\nf: exit").unwrap();
+    machine.load_at(256, &code, Some(0)).unwrap();
+    machine
+        .set_page_table(Some(PageTable {
+            base: 3072,
+            pages: 256,
+        }))
+        .unwrap();
+    machine
+        .map_page(0xf000, 256, PAGE_READ | PAGE_EXECUTE)
+        .unwrap();
+    machine
+        .map_page(0x8000, 512, PAGE_READ | PAGE_WRITE)
+        .unwrap();
+    machine.set_reg(0, 0xf000).unwrap();
+    stop_at(&mut machine, 0xf000 + 25);
+    assert_eq!(&[0xf000 + 25, 0xf000 + 24], machine.backtrace().frames());
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::cast_possible_truncation)]
+
 use interpreter::{Machine, MEMORY_SIZE};
 use std::io::{self, Write};
 
@@ -146,7 +148,7 @@ fn test_store() {
     let mut machine = Machine::new(&[2, 0, 1]).unwrap();
     machine.set_reg(1, 0x0102_0304).unwrap();
     expect(&mut machine, false, 3);
-    assert_eq!(&[04, 03, 02, 01], &machine.memory()[3..7]);
+    assert_eq!(&[4, 3, 2, 1], &machine.memory()[3..7]);
 }
 
 #[test]
@@ -191,6 +193,125 @@ fn test_load_imm_out_of_bounds() {
     assert!(machine.step().is_err());
 }
 
+#[test]
+fn test_load_imm32() {
+    // 0: loadimm32 r1, 0x12345678
+    // 6:
+    let mut machine = Machine::new(&[17, 1, 0x78, 0x56, 0x34, 0x12]).unwrap();
+    expect(&mut machine, false, 6);
+    assert_eq!(0x1234_5678, machine.regs()[1]);
+
+    // 0: loadimm32 r1, 0xffff8000 (corresponds to -32768, no sign extension)
+    // 6:
+    let mut machine = Machine::new(&[17, 1, 0x00, 0x80, 0xff, 0xff]).unwrap();
+    expect(&mut machine, false, 6);
+    assert_eq!(0xffff_8000, machine.regs()[1]);
+
+    // 0: loadimm32 r1, 0x00008000 (above the range of loadimm)
+    // 6:
+    let mut machine = Machine::new(&[17, 1, 0x00, 0x80, 0x00, 0x00]).unwrap();
+    expect(&mut machine, false, 6);
+    assert_eq!(0x8000, machine.regs()[1]);
+
+    // 0: loadimm32 r0, 0x0000000a
+    // 6:
+    let mut machine = Machine::new(&[17, 0, 10, 0, 0, 0]).unwrap();
+    expect(&mut machine, false, 10);
+}
+
+#[test]
+fn test_load_imm32_out_of_bounds() {
+    // 0: loadimm32 r100, 0
+    // 6:
+    let mut machine = Machine::new(&[17, 100, 0, 0, 0, 0]).unwrap();
+    assert!(machine.step().is_err());
+
+    // end-5: loadimm32 r1, (truncated)
+    let mut memory = vec![0; MEMORY_SIZE - 5];
+    memory.extend(&[17, 1, 0, 0, 0]);
+    let mut machine = Machine::new(&memory).unwrap();
+    machine.set_reg(0, MEMORY_SIZE as u32 - 5).unwrap();
+    assert!(machine.step().is_err());
+}
+
+#[test]
+fn test_jump_rel() {
+    // 0: jump_rel ip+3
+    // 3: exit
+    // 4: exit
+    // 5: exit
+    // 6: jump_rel ip-5
+    // 9:
+    let mut machine = Machine::new(&[18, 3, 0, 7, 7, 7, 18, 0xfb, 0xff]).unwrap();
+    expect(&mut machine, false, 6);
+    expect(&mut machine, false, 4);
+    expect(&mut machine, true, 5);
+}
+
+#[test]
+fn test_jump_rel_if() {
+    // 0: jump_rel ip+10 if r1 != 0
+    // 4: jump_rel ip-4 if r2 != 0
+    // 8:
+    let mut machine = Machine::new(&[19, 1, 10, 0, 19, 2, 0xfc, 0xff]).unwrap();
+    machine.set_reg(2, 1).unwrap();
+    expect(&mut machine, false, 4);
+    expect(&mut machine, false, 4);
+    machine.set_reg(2, 0).unwrap();
+    expect(&mut machine, false, 8);
+
+    // 0: jump_rel ip+0 if r100 != 0
+    // 4:
+    let mut machine = Machine::new(&[19, 100, 0, 0]).unwrap();
+    assert!(machine.step().is_err());
+}
+
+#[test]
+fn test_load_rel() {
+    // 0: loadrel r1 <- ip+0x100
+    // 4: loadrel r2 <- ip-4
+    // 8:
+    let mut machine = Machine::new(&[20, 1, 0, 1, 20, 2, 0xfc, 0xff]).unwrap();
+    expect(&mut machine, false, 4);
+    assert_eq!(0x104, machine.regs()[1]);
+    expect(&mut machine, false, 8);
+    assert_eq!(4, machine.regs()[2]);
+
+    // 0: loadrel r100 <- ip+0
+    // 4:
+    let mut machine = Machine::new(&[20, 100, 0, 0]).unwrap();
+    assert!(machine.step().is_err());
+}
+
+#[test]
+fn test_load_at() {
+    // 100: loadrel r1 <- ip+0
+    // 104: exit
+    let mut machine = Machine::new(&[]).unwrap();
+    machine.load_at(100, &[20, 1, 0, 0, 7], Some(0)).unwrap();
+    assert_eq!(100, machine.regs()[0]);
+    expect(&mut machine, false, 104);
+    assert_eq!(104, machine.regs()[1]);
+    expect(&mut machine, true, 105);
+
+    // Loading without entry point leaves IP alone
+    let mut machine = Machine::new(&[]).unwrap();
+    machine.load_at(100, &[7], None).unwrap();
+    assert_eq!(0, machine.regs()[0]);
+    assert_eq!(7, machine.memory()[100]);
+}
+
+#[test]
+fn test_load_at_out_of_bounds() {
+    let mut machine = Machine::new(&[]).unwrap();
+    assert!(machine
+        .load_at(MEMORY_SIZE as u32 - 1, &[7, 7], None)
+        .is_err());
+    assert!(machine.load_at(MEMORY_SIZE as u32, &[7], None).is_err());
+    assert!(machine.load_at(0, &[7, 7], Some(2)).is_err());
+    assert!(machine.memory().iter().all(|b| *b == 0));
+}
+
 #[test]
 fn test_sub() {
     // 0: sub r2 <- r1 - r0
@@ -242,7 +363,7 @@ fn test_out_number() {
     // 2:
     let mut machine = Machine::new(&[8, 1]).unwrap();
     let mut out = Vec::new();
-    machine.set_reg(1, -1234i32 as u32).unwrap();
+    machine.set_reg(1, (-1234i32).cast_unsigned()).unwrap();
     expect_on(&mut machine, &mut out, false, 2);
     assert_eq!("-1234".as_bytes(), &out[..]);
 }
@@ -268,7 +389,7 @@ fn test_run() {
     let mut machine = Machine::new(&[5, 1, 1, 0, 5, 1, 1, 0, 7]).unwrap();
     machine.run().unwrap();
     assert_eq!(9, machine.regs()[0]);
-    assert_eq!(-12, machine.regs()[1] as i32);
+    assert_eq!(-12, machine.regs()[1].cast_signed());
 }
 
 #[test]
@@ -288,8 +409,8 @@ fn no_wraparound_past_end_of_memory() {
     // 0:             exit
     // 1:
     let mut memory = [0; MEMORY_SIZE];
-    for i in MEMORY_SIZE - 4..MEMORY_SIZE {
-        memory[i] = 1;
+    for b in &mut memory[MEMORY_SIZE - 4..] {
+        *b = 1;
     }
     memory[0] = 7;
     let mut machine = Machine::new(&memory).unwrap();
@@ -379,6 +500,11 @@ fn sub_with_wraparound_neg() {
     assert_eq!(machine.regs()[1], 2_113_797_824);
 }
 
+// Opcodes which decode to a valid instruction
+fn is_assigned(opcode: u8) -> bool {
+    matches!(opcode, 1..=8 | 17..=32) || (cfg!(feature = "float") && (9..=16).contains(&opcode))
+}
+
 #[test]
 fn test_invalid_opcode() {
     // 0: invalid opcode
@@ -386,9 +512,10 @@ fn test_invalid_opcode() {
     // 2: exit
     // 3: exit
     // 4: exit
-    // 5:
-    let mut memory = [0, 7, 7, 7, 7];
-    for invalid in std::iter::once(0).chain(9..u8::MAX) {
+    // 5: exit
+    // 6:
+    let mut memory = [0, 7, 7, 7, 7, 7];
+    for invalid in (0..u8::MAX).filter(|&o| !is_assigned(o)) {
         memory[0] = invalid;
         let mut machine = Machine::new(&memory).unwrap();
         assert!(machine.step().is_err());
@@ -0,0 +1,90 @@
+use interpreter::{CachedMachine, Machine};
+
+const MAX_STEPS: usize = 10_000;
+
+fn run_both(machine: &Machine) -> (Machine, Vec<u8>) {
+    let (mut reference, mut cached) = (machine.clone(), CachedMachine::new(machine.clone()));
+    let (mut reference_out, mut cached_out) = (Vec::new(), Vec::new());
+    for _ in 0..MAX_STEPS {
+        let expected = reference.step_on(&mut reference_out);
+        assert_eq!(expected, cached.step_on(&mut cached_out));
+        assert_eq!(reference.regs(), cached.machine().regs());
+        assert_eq!(reference.memory(), cached.machine().memory());
+        assert_eq!(reference_out, cached_out);
+        if !matches!(expected, Ok(false)) {
+            break;
+        }
+    }
+    (reference, reference_out)
+}
+
+#[test]
+fn test_examples() {
+    for program in [
+        &include_bytes!("../examples/99bottles.bin")[..],
+        include_bytes!("../examples/count.bin"),
+        include_bytes!("../examples/hello_world.bin"),
+    ] {
+        let mut machine = Machine::new(program).unwrap();
+        machine.run_on(&mut Vec::new()).unwrap();
+        let mut cached = CachedMachine::new(Machine::new(program).unwrap());
+        cached.run_on(&mut Vec::new()).unwrap();
+        assert_eq!(machine.regs(), cached.machine().regs());
+        assert_eq!(machine.memory(), cached.machine().memory());
+    }
+}
+
+#[test]
+fn test_recursive_factorial() {
+    let mut machine = Machine::new(include_bytes!("rfact.bin")).unwrap();
+    machine.set_reg(10, 6).unwrap();
+    let (machine, _) = run_both(&machine);
+    assert_eq!(720, machine.regs()[11]);
+}
+
+#[test]
+fn test_self_modifying_code() {
+    let mut machine = Machine::new(&[
+        4, 10, 1, 0, // 0: r10 <- 1
+        4, 4, 0, 0, // 4: r4 <- 0
+        17, 5, 4, 10, 2, 0, // 8: r5 <- encoding of "r10 <- 2"
+        2, 4, 5, // 14: [r4] <- r5
+        18, 0xec, 0xff, // 17: jump to 0
+    ])
+    .unwrap();
+    let mut cached = CachedMachine::new(machine.clone());
+    for _ in 0..6 {
+        machine.step_on(&mut Vec::new()).unwrap();
+        cached.step_on(&mut Vec::new()).unwrap();
+    }
+    assert_eq!(2, machine.regs()[10]);
+    assert_eq!(machine.regs(), cached.machine().regs());
+}
+
+#[test]
+fn test_random_programs() {
+    // Deterministic xorshift generator, biased towards valid opcodes and
+    // registers so that programs run for a while.
+    let mut state = 0x2545_f491_u32;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state
+    };
+    for _ in 0..500 {
+        let len = next() % 64 + 1;
+        let program = (0..len)
+            .map(|_| match next() % 4 {
+                0 => (next() % 21) as u8,
+                1 => (next() % 16) as u8,
+                _ => next().to_le_bytes()[0],
+            })
+            .collect::<Vec<_>>();
+        let mut machine = Machine::new(&program).unwrap();
+        for r in 4..16 {
+            machine.set_reg(r, next() % 4096).unwrap();
+        }
+        run_both(&machine);
+    }
+}
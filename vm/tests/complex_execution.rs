@@ -15,6 +15,49 @@ fn test_function() {
     assert_eq!(42, machine.regs()[10]);
 }
 
+#[test]
+fn test_large_constant() {
+    let mut machine = Machine::new(include_bytes!("large_constant.bin")).unwrap();
+    machine.run().unwrap();
+    assert_eq!(123_456_789, machine.regs()[10]);
+    assert_eq!(-1_000_000, machine.regs()[11].cast_signed());
+    assert_eq!(32768, machine.regs()[12]);
+}
+
+// Position-independent code computing the sum of 1..=r10 into r11
+#[test]
+fn test_pic_at_any_address() {
+    for base in [0, 1, 1000, 3000] {
+        let mut machine = Machine::new(&[]).unwrap();
+        machine
+            .load_at(base, include_bytes!("pic.bin"), Some(0))
+            .unwrap();
+        machine.set_reg(10, 10).unwrap();
+        machine.run().unwrap();
+        assert_eq!(55, machine.regs()[11]);
+        // r12 holds the address of the pic_add function
+        assert_eq!(base + 43, machine.regs()[12]);
+    }
+}
+
+#[test]
+fn test_pic_several_modules() {
+    let pic = include_bytes!("pic.bin");
+    let mut machine = Machine::new(&[]).unwrap();
+    machine.load_at(100, pic, None).unwrap();
+    machine.load_at(2000, pic, Some(0)).unwrap();
+    machine.set_reg(10, 4).unwrap();
+    machine.run().unwrap();
+    assert_eq!(10, machine.regs()[11]);
+    assert_eq!(2043, machine.regs()[12]);
+
+    machine.set_reg(0, 100).unwrap();
+    machine.set_reg(10, 5).unwrap();
+    machine.run().unwrap();
+    assert_eq!(15, machine.regs()[11]);
+    assert_eq!(143, machine.regs()[12]);
+}
+
 // Multiplication
 #[test]
 fn test_mult() {
@@ -22,10 +65,10 @@ fn test_mult() {
         for right in &[1i32, 2, 3, 50] {
             // mult expect its arguments in r11 and r12 and the result will be in r11
             let mut machine = Machine::new(include_bytes!("multiply.bin")).unwrap();
-            machine.set_reg(11, *left as u32).unwrap();
-            machine.set_reg(12, *right as u32).unwrap();
+            machine.set_reg(11, left.cast_unsigned()).unwrap();
+            machine.set_reg(12, right.cast_unsigned()).unwrap();
             machine.run().unwrap();
-            assert_eq!(*left * *right, machine.regs()[11] as i32);
+            assert_eq!(*left * *right, machine.regs()[11].cast_signed());
         }
     }
 }
@@ -0,0 +1,258 @@
+#[cfg(feature = "float")]
+use interpreter::FloatInstruction;
+use interpreter::{
+    assemble, disassemble, disassemble_with, DisassemblyOptions, Error, Instruction,
+};
+use proptest::prelude::*;
+
+fn reg() -> impl Strategy<Value = usize> {
+    0..16usize
+}
+
+fn imm() -> impl Strategy<Value = i32> {
+    any::<i16>().prop_map(i32::from)
+}
+
+#[cfg(feature = "float")]
+fn float_instruction() -> impl Strategy<Value = FloatInstruction> {
+    prop_oneof![
+        (reg(), reg(), reg()).prop_map(|(target, op1, op2)| FloatInstruction::Add {
+            target,
+            op1,
+            op2
+        }),
+        (reg(), reg(), reg()).prop_map(|(target, op1, op2)| FloatInstruction::Sub {
+            target,
+            op1,
+            op2
+        }),
+        (reg(), reg(), reg()).prop_map(|(target, op1, op2)| FloatInstruction::Mul {
+            target,
+            op1,
+            op2
+        }),
+        (reg(), reg(), reg()).prop_map(|(target, op1, op2)| FloatInstruction::Div {
+            target,
+            op1,
+            op2
+        }),
+        (reg(), reg(), reg()).prop_map(|(target, op1, op2)| FloatInstruction::Cmp {
+            target,
+            op1,
+            op2
+        }),
+        (reg(), reg()).prop_map(|(target, source)| FloatInstruction::FromInt { target, source }),
+        (reg(), reg()).prop_map(|(target, source)| FloatInstruction::ToInt { target, source }),
+        reg().prop_map(|reg| FloatInstruction::Out { reg }),
+    ]
+}
+
+fn instruction() -> BoxedStrategy<Instruction> {
+    let instruction = prop_oneof![
+        (reg(), reg(), reg()).prop_map(|(target, source, cond)| Instruction::MoveIf {
+            target,
+            source,
+            cond
+        }),
+        (reg(), reg()).prop_map(|(target, source)| Instruction::Store { target, source }),
+        (reg(), reg()).prop_map(|(target, source)| Instruction::Load { target, source }),
+        (reg(), imm()).prop_map(|(target, value)| Instruction::LoadImm { target, value }),
+        (reg(), any::<u32>()).prop_map(|(target, value)| Instruction::LoadImm32 { target, value }),
+        (reg(), reg(), reg()).prop_map(|(target, op1, op2)| Instruction::Sub { target, op1, op2 }),
+        reg().prop_map(|reg| Instruction::Out { reg }),
+        Just(Instruction::Exit),
+        reg().prop_map(|reg| Instruction::OutNumber { reg }),
+        imm().prop_map(|offset| Instruction::JumpRel { offset }),
+        (reg(), imm()).prop_map(|(cond, offset)| Instruction::JumpRelIf { cond, offset }),
+        (reg(), imm()).prop_map(|(target, offset)| Instruction::LoadRel { target, offset }),
+        any::<u8>().prop_map(|service| Instruction::Trap { service }),
+        Just(Instruction::EnableInterrupts),
+        Just(Instruction::DisableInterrupts),
+        Just(Instruction::ReturnFromInterrupt),
+        Just(Instruction::Syscall),
+        reg().prop_map(|reg| Instruction::ReturnToUser { reg }),
+        (reg(), reg(), reg(), reg()).prop_map(|(target, source, expected, new)| {
+            Instruction::CompareAndSwap {
+                target,
+                source,
+                expected,
+                new,
+            }
+        }),
+        (reg(), reg(), reg()).prop_map(|(target, source, value)| Instruction::FetchAdd {
+            target,
+            source,
+            value
+        }),
+        reg().prop_map(|target| Instruction::CoreId { target }),
+        (reg(), reg()).prop_map(|(target, entry)| Instruction::Spawn { target, entry }),
+        Just(Instruction::Yield),
+        reg().prop_map(|reg| Instruction::Join { reg }),
+    ];
+    #[cfg(feature = "float")]
+    let instruction = prop_oneof![
+        instruction,
+        float_instruction().prop_map(Instruction::Float)
+    ];
+    instruction.boxed()
+}
+
+proptest! {
+    #[test]
+    fn decode_encoding(instruction in instruction()) {
+        let mut bytes = [0; Instruction::MAX_SIZE];
+        let len = instruction.encode(&mut bytes).unwrap();
+        prop_assert_eq!(instruction.size() as usize, len);
+        prop_assert_eq!(Ok(instruction), Instruction::try_from(&bytes[..len]));
+        prop_assert_eq!(
+            Err(Error::ReadPastMemoryEnd),
+            Instruction::try_from(&bytes[..len - 1])
+        );
+    }
+
+    #[test]
+    fn parse_text(instruction in instruction()) {
+        prop_assert_eq!(Ok(instruction), instruction.to_string().parse());
+    }
+
+    #[test]
+    fn encode_decoded_bytes(bytes in prop::collection::vec(any::<u8>(), 1..8)) {
+        if let Ok(instruction) = Instruction::try_from(&bytes[..]) {
+            let mut encoded = [0; Instruction::MAX_SIZE];
+            let len = instruction.encode(&mut encoded).unwrap();
+            prop_assert_eq!(instruction.size() as usize, len);
+            prop_assert_eq!(&bytes[..len], &encoded[..len]);
+        }
+    }
+
+    #[test]
+    fn assemble_disassembly(bytes in prop::collection::vec(any::<u8>(), 0..64)) {
+        prop_assert_eq!(Ok(bytes.clone()), assemble(&disassemble(&bytes)));
+    }
+
+    #[test]
+    fn assemble_disassembly_with_options(
+        bytes in prop::collection::vec(any::<u8>(), 0..64),
+        aliases in any::<bool>(),
+        idioms in any::<bool>(),
+    ) {
+        let text = disassemble_with(&bytes, DisassemblyOptions { aliases, idioms });
+        prop_assert_eq!(Ok(bytes), assemble(&text));
+    }
+}
+
+#[test]
+fn test_encode_errors() {
+    let mut bytes = [0; Instruction::MAX_SIZE];
+    assert_eq!(
+        Err(Error::InvalidRegister(16)),
+        Instruction::Out { reg: 16 }.encode(&mut bytes)
+    );
+    assert_eq!(
+        Err(Error::MemoryOverflow),
+        Instruction::LoadImm32 {
+            target: 1,
+            value: 0
+        }
+        .encode(&mut bytes[..5])
+    );
+    assert_eq!(Ok(1), Instruction::Exit.encode(&mut bytes[..1]));
+}
+
+#[test]
+fn test_parse_errors() {
+    for text in [
+        "",
+        "exit now",
+        "move r1 <- r2",
+        "move r1 <- r16 if r3 != 0",
+        "loadimm r1 <- 3",
+        "loadimm r1 <- #32768",
+        "loadimm32 r1 <- #4294967296",
+        "jump_rel 3",
+        "jump_rel ip3",
+        "store r1 <- r2",
+        "load r1 <- [r2",
+    ] {
+        assert_eq!(
+            Err(Error::InvalidSyntax),
+            text.parse::<Instruction>(),
+            "{text}"
+        );
+    }
+    assert_eq!(
+        Ok(Instruction::LoadImm32 {
+            target: 1,
+            value: u32::MAX
+        }),
+        "loadimm32 r1 <- #4294967295".parse()
+    );
+    assert_eq!(
+        Ok(Instruction::LoadImm32 {
+            target: 1,
+            value: u32::MAX
+        }),
+        "loadimm32 r1 <- #-1".parse()
+    );
+}
+
+#[test]
+fn test_assemble_errors() {
+    let error = assemble("  0000   exit\n\n  0002   exit").unwrap_err();
+    assert_eq!(
+        (3, Error::InvalidMemoryAddress(2)),
+        (error.line, error.error)
+    );
+    let error = assemble("exit ; done\nhalt").unwrap_err();
+    assert_eq!((2, Error::InvalidSyntax), (error.line, error.error));
+    assert_eq!(Ok(vec![7, 255]), assemble("exit ; done\n.byte 255"));
+}
+
+// The disassembly written by `generator.py` must agree with the decoder,
+// except for operands which are displayed as labels.
+#[test]
+fn test_generator_disassembly() {
+    for (dis, bin) in [
+        (
+            include_str!("push_pop.dis"),
+            &include_bytes!("push_pop.bin")[..],
+        ),
+        (include_str!("function.dis"), include_bytes!("function.bin")),
+        (include_str!("multiply.dis"), include_bytes!("multiply.bin")),
+        (include_str!("fact.dis"), include_bytes!("fact.bin")),
+        (include_str!("afact.dis"), include_bytes!("afact.bin")),
+        (include_str!("rfact.dis"), include_bytes!("rfact.bin")),
+        (include_str!("rfact_tr.dis"), include_bytes!("rfact_tr.bin")),
+        (include_str!("fibo.dis"), include_bytes!("fibo.bin")),
+        (
+            include_str!("large_constant.dis"),
+            include_bytes!("large_constant.bin"),
+        ),
+        (include_str!("pic.dis"), include_bytes!("pic.bin")),
+        (
+            include_str!("../examples/99bottles.dis"),
+            include_bytes!("../examples/99bottles.bin"),
+        ),
+        (
+            include_str!("../examples/hello_world.dis"),
+            include_bytes!("../examples/hello_world.bin"),
+        ),
+    ] {
+        let mut checked = 0;
+        for line in dis.lines().filter(|line| line.starts_with("  ")) {
+            let (addr, text) = line.trim().split_once(char::is_whitespace).unwrap();
+            // Data lines have no address
+            let (Ok(addr), text) = (addr.parse::<usize>(), text.trim()) else {
+                continue;
+            };
+            if let Ok(expected) = text.parse::<Instruction>() {
+                let instruction = Instruction::try_from(&bin[addr..]).unwrap();
+                assert_eq!(expected, instruction);
+                assert_eq!(text, instruction.to_string());
+                checked += 1;
+            }
+        }
+        assert!(checked > 0);
+        assert_eq!(Ok(bin.to_vec()), assemble(&disassemble(bin)));
+    }
+}
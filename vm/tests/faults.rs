@@ -0,0 +1,99 @@
+use interpreter::{
+    assemble, CachedMachine, Error, FaultCause, Machine, FAULT_CAUSE_REG, FAULT_IP_REG,
+    FAULT_VALUE_REG,
+};
+
+const HANDLER: u32 = 100;
+
+fn new_machine(program: &[u8], handler: &str) -> Machine {
+    let mut machine = Machine::new(program).unwrap();
+    machine
+        .load_at(HANDLER, &assemble(handler).unwrap(), None)
+        .unwrap();
+    machine.set_fault_handler(Some(HANDLER));
+    machine
+}
+
+fn fault(machine: &Machine) -> (u32, u32, u32) {
+    let regs = machine.regs();
+    (
+        regs[FAULT_IP_REG],
+        regs[FAULT_CAUSE_REG],
+        regs[FAULT_VALUE_REG],
+    )
+}
+
+#[test]
+fn test_emulate_missing_opcode() {
+    // Opcode 99 increments r10, the handler resumes after it
+    let mut program = assemble("loadimm r10 <- #5").unwrap();
+    program.extend([99, 99]);
+    program.extend(assemble("exit").unwrap());
+    let handler = "loadimm r3 <- #-1
+                   sub r10 <- r10 - r3
+                   sub r0 <- r13 - r3";
+    let mut machine = new_machine(&program, handler);
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(7, machine.regs()[10]);
+    assert_eq!((5, FaultCause::UnknownOpcode.code(), 99), fault(&machine));
+
+    let mut cached = CachedMachine::new(new_machine(&program, handler));
+    cached.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(7, cached.machine().regs()[10]);
+}
+
+#[test]
+fn test_invalid_memory_address() {
+    let program = assemble("loadimm r4 <- #5000\nload r5 <- [r4]").unwrap();
+    let mut machine = new_machine(&program, "exit");
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(
+        (4, FaultCause::InvalidMemoryAddress.code(), 5000),
+        fault(&machine)
+    );
+
+    // Truncated instruction at the end of memory
+    let mut machine = new_machine(&[], "exit");
+    machine.load_at(4094, &[4, 3], None).unwrap();
+    machine.set_reg(0, 4094).unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(
+        (4094, FaultCause::InvalidMemoryAddress.code(), 4096),
+        fault(&machine)
+    );
+}
+
+#[test]
+fn test_invalid_register() {
+    let mut machine = new_machine(&[6, 20], "exit");
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!((0, FaultCause::InvalidRegister.code(), 20), fault(&machine));
+}
+
+#[test]
+fn test_without_handler() {
+    let mut machine = new_machine(&[99], "exit");
+    machine.set_fault_handler(None);
+    assert_eq!(
+        Err(Error::UnknownOpcode(99)),
+        machine.run_on(&mut Vec::new())
+    );
+    // Other errors are not sent to the handler
+    let mut machine = new_machine(&assemble("trap #1").unwrap(), "exit");
+    assert_eq!(
+        Err(Error::UnknownService(1)),
+        machine.run_on(&mut Vec::new())
+    );
+}
+
+#[test]
+fn test_fault_in_handler() {
+    let mut machine = new_machine(&[99], "");
+    machine.load_at(HANDLER, &[98], None).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert_eq!(HANDLER, machine.regs()[0]);
+    assert_eq!(
+        Err(Error::UnknownOpcode(98)),
+        machine.step_on(&mut Vec::new())
+    );
+}
@@ -0,0 +1,126 @@
+#![cfg(feature = "float")]
+
+use interpreter::Machine;
+
+fn run_with(code: &[u8], regs: &[(usize, u32)]) -> (Machine, Vec<u8>) {
+    let mut machine = Machine::new(code).unwrap();
+    for &(reg, value) in regs {
+        machine.set_reg(reg, value).unwrap();
+    }
+    let mut out = vec![];
+    machine.step_on(&mut out).unwrap();
+    (machine, out)
+}
+
+fn f(value: f32) -> u32 {
+    value.to_bits()
+}
+
+#[test]
+fn test_arithmetic() {
+    let regs = [(5, f(1.5)), (6, f(-4.0))];
+
+    // 0: fadd r4 <- r5 + r6
+    let (m, _) = run_with(&[9, 4, 5, 6], &regs);
+    assert_eq!(f(-2.5), m.regs()[4]);
+    assert_eq!(4, m.regs()[0]);
+
+    // 0: fsub r4 <- r5 - r6
+    let (m, _) = run_with(&[10, 4, 5, 6], &regs);
+    assert_eq!(f(5.5), m.regs()[4]);
+
+    // 0: fmul r4 <- r5 * r6
+    let (m, _) = run_with(&[11, 4, 5, 6], &regs);
+    assert_eq!(f(-6.0), m.regs()[4]);
+
+    // 0: fdiv r4 <- r5 / r6
+    let (m, _) = run_with(&[12, 4, 5, 6], &regs);
+    assert_eq!(f(-0.375), m.regs()[4]);
+
+    // 0: fdiv r4 <- r5 / r7 with r7 == 0.0
+    let (m, _) = run_with(&[12, 4, 5, 7], &regs);
+    assert_eq!(f(f32::INFINITY), m.regs()[4]);
+}
+
+#[test]
+fn test_compare() {
+    let regs = [(5, f(1.5)), (6, f(-4.0)), (7, f(f32::NAN))];
+
+    // 0: fcmp r4 <- r5 ? r6
+    let (m, _) = run_with(&[13, 4, 5, 6], &regs);
+    assert_eq!(1, m.regs()[4]);
+    // 0: fcmp r4 <- r6 ? r5
+    let (m, _) = run_with(&[13, 4, 6, 5], &regs);
+    assert_eq!(-1, m.regs()[4].cast_signed());
+    // 0: fcmp r4 <- r5 ? r5
+    let (m, _) = run_with(&[13, 4, 5, 5], &regs);
+    assert_eq!(0, m.regs()[4]);
+    // 0: fcmp r4 <- r5 ? r7
+    let (m, _) = run_with(&[13, 4, 5, 7], &regs);
+    assert_eq!(2, m.regs()[4]);
+}
+
+#[test]
+fn test_conversions() {
+    // 0: itof r4 <- r5
+    // 3:
+    let (m, _) = run_with(&[14, 4, 5], &[(5, (-42i32).cast_unsigned())]);
+    assert_eq!(f(-42.0), m.regs()[4]);
+    assert_eq!(3, m.regs()[0]);
+
+    // 0: ftoi r4 <- r5
+    // 3:
+    let (m, _) = run_with(&[15, 4, 5], &[(5, f(-7.9))]);
+    assert_eq!(-7, m.regs()[4].cast_signed());
+
+    // Out of range values saturate, NaN becomes 0
+    let (m, _) = run_with(&[15, 4, 5], &[(5, f(1e20))]);
+    assert_eq!(i32::MAX.cast_unsigned(), m.regs()[4]);
+    let (m, _) = run_with(&[15, 4, 5], &[(5, f(f32::NAN))]);
+    assert_eq!(0, m.regs()[4]);
+}
+
+#[test]
+fn test_out_float() {
+    // 0: out_float r5
+    // 2:
+    let (m, out) = run_with(&[16, 5], &[(5, f(-2.25))]);
+    assert_eq!(b"-2.25", &out[..]);
+    assert_eq!(2, m.regs()[0]);
+}
+
+#[test]
+fn test_invalid_registers() {
+    for code in [
+        &[9, 100, 1, 1][..],
+        &[10, 1, 100, 1],
+        &[13, 1, 1, 100],
+        &[14, 100, 1],
+        &[15, 1, 100],
+        &[16, 100],
+    ] {
+        let mut machine = Machine::new(code).unwrap();
+        assert!(machine.step_on(&mut vec![]).is_err());
+    }
+}
+
+#[test]
+fn test_average() {
+    // 0: itof r4 <- r10
+    // 3: itof r5 <- r11
+    // 6: fadd r4 <- r4 + r5
+    // 10: loadimm r5 <- #2
+    // 14: itof r5 <- r5
+    // 17: fdiv r4 <- r4 / r5
+    // 21: out_float r4
+    // 23: exit
+    let code = [
+        14, 4, 10, 14, 5, 11, 9, 4, 4, 5, 4, 5, 2, 0, 14, 5, 5, 12, 4, 4, 5, 16, 4, 7,
+    ];
+    let mut machine = Machine::new(&code).unwrap();
+    machine.set_reg(10, 3).unwrap();
+    machine.set_reg(11, 4).unwrap();
+    let mut out = vec![];
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(b"3.5", &out[..]);
+}
@@ -0,0 +1,97 @@
+use interpreter::{
+    render_frame, serial_frame, Error, Machine, FRAMEBUFFER_SIZE, FRAME_START, MEMORY_SIZE,
+};
+
+// Address used by the animation example
+const FRAMEBUFFER: u32 = 3072;
+
+/// Run the animation example and return every presented frame.
+fn animation_frames() -> Vec<[u8; FRAMEBUFFER_SIZE]> {
+    let mut machine = Machine::new(include_bytes!("../examples/animation.bin")).unwrap();
+    machine.map_framebuffer(FRAMEBUFFER).unwrap();
+    let mut frames = Vec::new();
+    loop {
+        match machine.run_on(&mut Vec::new()) {
+            Ok(()) => return frames,
+            Err(Error::FramePresented) => frames.push(*machine.framebuffer().unwrap()),
+            Err(e) => panic!("unexpected error {e:?}"),
+        }
+    }
+}
+
+#[test]
+fn test_animation() {
+    let frames = animation_frames();
+    assert_eq!(64, frames.len());
+    for (n, frame) in frames.iter().enumerate() {
+        // Pixels up to n are red, the others are black
+        for (i, pixel) in frame.chunks(3).enumerate() {
+            let expected: &[u8] = if i <= n { &[255, 0, 0] } else { &[0, 0, 0] };
+            assert_eq!(expected, pixel, "frame {n}, pixel {i}");
+        }
+    }
+}
+
+#[test]
+fn test_render_frame() {
+    let frames = animation_frames();
+    let expected = format!(
+        "ff0000 ff0000 ff0000 ff0000 ff0000 ff0000 ff0000 ff0000\n\
+         ff0000 ff0000 {}\n{}",
+        ["000000"; 6].join(" "),
+        format!("{}\n", ["000000"; 8].join(" ")).repeat(6)
+    );
+    assert_eq!(expected, render_frame(&frames[9]));
+}
+
+#[test]
+fn test_serial_frame() {
+    let mut framebuffer = [0; FRAMEBUFFER_SIZE];
+    framebuffer[0] = 255;
+    framebuffer[1] = 254;
+    framebuffer[191] = 12;
+    let frame = serial_frame(&framebuffer);
+    assert_eq!(FRAME_START, frame[0]);
+    assert_eq!([254, 254, 0], frame[1..4]);
+    assert_eq!(12, frame[192]);
+    assert!(frame[1..].iter().all(|&b| b != FRAME_START));
+}
+
+#[test]
+fn test_present_register() {
+    let mut machine = Machine::new(&[]).unwrap();
+    assert_eq!(None, machine.framebuffer());
+    machine.map_framebuffer(100).unwrap();
+    assert_eq!(Some(&[0; FRAMEBUFFER_SIZE]), machine.framebuffer());
+    // Words overlapping the present register at 292
+    for (addr, presented) in [
+        (288, false),
+        (289, true),
+        (292, true),
+        (295, true),
+        (296, false),
+    ] {
+        machine.set_reg(4, addr).unwrap();
+        machine.set_reg(0, 0).unwrap();
+        // store [r4] <- r5
+        machine.load_at(0, &[2, 4, 5], None).unwrap();
+        let result = machine.step_on(&mut Vec::new());
+        assert_eq!(presented, result == Err(Error::FramePresented), "{addr}");
+        assert_eq!(3, machine.regs()[0]);
+    }
+    machine.unmap_framebuffer();
+    machine.set_reg(4, 292).unwrap();
+    machine.set_reg(0, 0).unwrap();
+    assert_eq!(Ok(false), machine.step_on(&mut Vec::new()));
+}
+
+#[test]
+fn test_map_out_of_memory() {
+    let mut machine = Machine::new(&[]).unwrap();
+    let last = u32::try_from(MEMORY_SIZE - FRAMEBUFFER_SIZE - 4).unwrap();
+    assert_eq!(Ok(()), machine.map_framebuffer(last));
+    assert_eq!(
+        Err(Error::InvalidMemoryAddress(last + 1)),
+        machine.map_framebuffer(last + 1)
+    );
+}
@@ -0,0 +1,83 @@
+use interpreter::{check_golden, discover_programs, expectation_path, Outcome, Verdict};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("vm-golden-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_examples() {
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let programs = discover_programs(&examples).unwrap();
+    assert!(!programs.is_empty());
+    for program in programs {
+        assert!(
+            expectation_path(&program).is_file(),
+            "{}",
+            program.display()
+        );
+        assert_eq!(
+            Verdict::Pass,
+            check_golden(&program, false).unwrap(),
+            "{}",
+            program.display()
+        );
+    }
+}
+
+#[test]
+fn test_hello_world_outcome() {
+    let outcome = Outcome::of(include_bytes!("../examples/hello_world.bin"));
+    assert_eq!("exit", outcome.status);
+    assert_eq!(b"Hello, world!\n", &outcome.output[..]);
+    assert_eq!(4096, outcome.registers[2]);
+}
+
+#[test]
+fn test_error_and_step_limit() {
+    // Unknown opcode 0
+    assert_eq!("error: UnknownOpcode(0)", Outcome::of(&[0]).status);
+    // jump_rel ip-3
+    assert_eq!(
+        "step limit reached",
+        Outcome::with_step_limit(&[18, 0xfd, 0xff], 1000).status
+    );
+}
+
+#[test]
+fn test_parse_expectation() {
+    let outcome = Outcome::of(include_bytes!("../examples/count.bin"));
+    assert_eq!(Some(outcome.clone()), Outcome::parse(&outcome.to_bytes()));
+    let content = b"status exit\nregisters 1 2\noutput\nno newline";
+    let outcome = Outcome::parse(content).unwrap();
+    assert_eq!(vec![1, 2], outcome.registers);
+    assert_eq!(b"no newline", &outcome.output[..]);
+    assert_eq!(content, &outcome.to_bytes()[..]);
+    assert_eq!(None, Outcome::parse(b"status exit\nregisters x\noutput\n"));
+    assert_eq!(None, Outcome::parse(b"status exit\nregisters 1\n"));
+}
+
+#[test]
+fn test_bless() {
+    let dir = scratch_dir("bless");
+    let program = dir.join("hello.bin");
+    fs::write(&program, include_bytes!("../examples/hello_world.bin")).unwrap();
+    assert!(matches!(
+        check_golden(&program, false).unwrap(),
+        Verdict::Missing(_)
+    ));
+    assert_eq!(Verdict::Blessed, check_golden(&program, true).unwrap());
+    assert_eq!(Verdict::Pass, check_golden(&program, false).unwrap());
+    // A different program does not match the blessed outcome
+    fs::write(&program, include_bytes!("../examples/count.bin")).unwrap();
+    let Verdict::Fail { expected, actual } = check_golden(&program, false).unwrap() else {
+        panic!("expected a failure");
+    };
+    assert_eq!(b"Hello, world!\n", &expected.output[..]);
+    assert_eq!(Outcome::of(include_bytes!("../examples/count.bin")), actual);
+    fs::remove_dir_all(dir).unwrap();
+}
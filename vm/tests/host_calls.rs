@@ -0,0 +1,217 @@
+use interpreter::{
+    assemble, CachedMachine, Error, HostCall, Machine, PageTable, Protection, MAX_SERVICES,
+    PAGE_EXECUTE, PAGE_READ, PAGE_WRITE,
+};
+use std::cell::RefCell;
+
+const LOG: u8 = 1;
+const CLOCK: u8 = 2;
+const HALT: u8 = 3;
+const FILL: u8 = 4;
+
+thread_local! {
+    static MESSAGES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+// Log the r11 bytes found at r10
+fn log(call: &mut HostCall) -> Result<bool, Error> {
+    let message = call.read(call.reg(10)?, call.reg(11)? as usize)?;
+    let message = String::from_utf8_lossy(message).into_owned();
+    MESSAGES.with(|m| m.borrow_mut().push(message));
+    Ok(false)
+}
+
+// Increment r10 at every call
+fn clock(call: &mut HostCall) -> Result<bool, Error> {
+    call.set_reg(10, call.reg(10)? + 1)?;
+    Ok(false)
+}
+
+#[allow(clippy::unnecessary_wraps)]
+fn halt(_: &mut HostCall) -> Result<bool, Error> {
+    Ok(true)
+}
+
+// Write r11 words with value r12 starting at r10
+fn fill(call: &mut HostCall) -> Result<bool, Error> {
+    let (addr, count, value) = (call.reg(10)?, call.reg(11)?, call.reg(12)?);
+    for i in 0..count {
+        call.write_u32(addr + 4 * i, value)?;
+    }
+    Ok(false)
+}
+
+fn failing(call: &mut HostCall) -> Result<bool, Error> {
+    Err(Error::InvalidMemoryAddress(call.ip()))
+}
+
+fn machine(source: &str) -> Machine {
+    let mut machine = Machine::new(&assemble(source).unwrap()).unwrap();
+    machine.register_service(LOG, log).unwrap();
+    machine.register_service(CLOCK, clock).unwrap();
+    machine.register_service(HALT, halt).unwrap();
+    machine.register_service(FILL, fill).unwrap();
+    machine
+}
+
+#[test]
+fn test_log() {
+    let mut machine = machine(
+        "loadimm r10 <- #100
+         loadimm r11 <- #2
+         trap #1
+         exit",
+    );
+    machine.load_at(100, b"Hi", None).unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(vec!["Hi"], MESSAGES.with(RefCell::take));
+}
+
+#[test]
+fn test_registers_and_memory() {
+    let mut machine = machine(
+        "trap #2
+         trap #2
+         loadimm r13 <- #10
+         loadimm r10 <- #200
+         loadimm r11 <- #3
+         loadimm r12 <- #-1
+         trap #4
+         load r14 <- [r13]
+         trap #3
+         loadimm r15 <- #1",
+    );
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(Some(&[255; 12][..]), machine.memory().get(200..212));
+    assert_eq!(0, machine.memory()[212]);
+    let word = machine.memory()[10..14].try_into().unwrap();
+    assert_eq!(u32::from_le_bytes(word), machine.regs()[14]);
+    // The halt service stops the machine before the last instruction
+    assert_eq!(0, machine.regs()[15]);
+}
+
+#[test]
+fn test_clock() {
+    let mut machine = machine("trap #2\ntrap #2\ntrap #2\nexit");
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(3, machine.regs()[10]);
+}
+
+#[test]
+fn test_unknown_service() {
+    let mut machine = machine("trap #9");
+    assert_eq!(
+        Err(Error::UnknownService(9)),
+        machine.step_on(&mut Vec::new())
+    );
+    assert!(machine.unregister_service(CLOCK));
+    assert!(!machine.unregister_service(CLOCK));
+    machine
+        .load_at(0, &assemble("trap #2").unwrap(), Some(0))
+        .unwrap();
+    assert_eq!(
+        Err(Error::UnknownService(2)),
+        machine.step_on(&mut Vec::new())
+    );
+}
+
+#[test]
+fn test_handler_errors() {
+    let mut machine = machine("loadimm r10 <- #4095\nloadimm r11 <- #2\ntrap #1");
+    assert_eq!(
+        Err(Error::InvalidMemoryAddress(4095)),
+        machine.run_on(&mut Vec::new())
+    );
+    // Replace the log service
+    machine.register_service(LOG, failing).unwrap();
+    machine.set_reg(0, 8).unwrap();
+    assert_eq!(
+        Err(Error::InvalidMemoryAddress(8)),
+        machine.step_on(&mut Vec::new())
+    );
+}
+
+#[test]
+fn test_protected_writes() {
+    let source = "loadimm r10 <- #200\nloadimm r11 <- #2\nloadimm r12 <- #-1\ntrap #4";
+    let mut machine = machine(source);
+    machine.protect(204..208, Protection::ReadOnly).unwrap();
+    assert_eq!(
+        Err(Error::WriteViolation(204)),
+        machine.run_on(&mut Vec::new())
+    );
+    // Only the word before the protected region has been written
+    assert_eq!(Some(&[255; 4][..]), machine.memory().get(200..204));
+    assert_eq!(Some(&[0; 4][..]), machine.memory().get(204..208));
+}
+
+#[test]
+fn test_paged_host_calls() {
+    let source = "loadimm32 r10 <- #0x8000
+                  loadimm r11 <- #1
+                  loadimm32 r12 <- #0x216948
+                  trap #4
+                  loadimm r11 <- #2
+                  trap #1
+                  loadimm32 r10 <- #0x80ff
+                  trap #4";
+    let mut machine = machine(source);
+    let table = PageTable {
+        base: 3072,
+        pages: 256,
+    };
+    machine.set_page_table(Some(table)).unwrap();
+    machine.map_page(0, 0, PAGE_READ | PAGE_EXECUTE).unwrap();
+    machine
+        .map_page(0x8000, 512, PAGE_READ | PAGE_WRITE)
+        .unwrap();
+    // The word written at 0x80ff would cross into an unmapped page
+    assert_eq!(
+        Err(Error::PageFault(0x8100)),
+        machine.run_on(&mut Vec::new())
+    );
+    assert_eq!(vec!["Hi"], MESSAGES.with(RefCell::take));
+    assert_eq!(Some(&b"Hi!\0"[..]), machine.memory().get(512..516));
+    assert_eq!(0, machine.memory()[512 + 255]);
+}
+
+#[test]
+fn test_cached_host_writes() {
+    // The instruction at `again` is decoded once, then overwritten by the
+    // host before being executed a second time
+    let patch = u32::from_le_bytes(
+        assemble("loadimm r13 <- #2").unwrap()[..]
+            .try_into()
+            .unwrap(),
+    );
+    let source = format!(
+        "loadimm r10 <- #again
+         loadimm r11 <- #1
+         loadimm32 r12 <- #{patch}
+         again: loadimm r13 <- #1
+         jnz r14, end
+         loadimm r14 <- #1
+         trap #4
+         jmp again
+         end: exit"
+    );
+    let mut cached = CachedMachine::new(machine(&source));
+    cached.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(2, cached.machine().regs()[13]);
+}
+
+#[test]
+fn test_too_many_services() {
+    let mut machine = Machine::new(&[]).unwrap();
+    for service in 0..MAX_SERVICES {
+        machine
+            .register_service(u8::try_from(service).unwrap(), halt)
+            .unwrap();
+    }
+    assert_eq!(
+        Err(Error::TooManyServices),
+        machine.register_service(200, halt)
+    );
+    // Replacing an existing service is still possible
+    assert_eq!(Ok(()), machine.register_service(0, clock));
+}
@@ -0,0 +1,177 @@
+use interpreter::{assemble, CachedMachine, Error, Machine, MAX_IRQS, TIMER_IRQ};
+
+const VECTOR_TABLE: u32 = 1000;
+const TIMER: u32 = 2000;
+
+/// Count in r7 until the timer interrupt handler, which increments r6, has
+/// run three times. The timer period is 10 instructions.
+const TIMER_PROGRAM: &str = "
+  0000   loadimm r4 <- #2000
+  0004   loadimm r5 <- #10
+  0008   store [r4] <- r5
+  0011   ei
+  0012   loadimm r3 <- #-1
+  0016   sub r7 <- r7 - r3
+  0020   loadimm r3 <- #3
+  0024   sub r8 <- r6 - r3
+  0028   jump_rel ip-20 if r8 != 0
+  0032   exit
+; timer handler
+  0033   loadimm r9 <- #-1
+  0037   sub r6 <- r6 - r9
+  0041   reti
+";
+
+fn new_machine(source: &str, handlers: &[u32]) -> Machine {
+    let mut machine = Machine::new(&assemble(source).unwrap()).unwrap();
+    for (line, handler) in (0..).zip(handlers) {
+        machine
+            .load_at(VECTOR_TABLE + 4 * line, &handler.to_le_bytes(), None)
+            .unwrap();
+    }
+    machine.set_vector_table(Some(VECTOR_TABLE));
+    machine.map_timer(TIMER).unwrap();
+    machine
+}
+
+#[test]
+fn test_timer() {
+    let mut machine = new_machine(TIMER_PROGRAM, &[33]);
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(3, machine.regs()[6]);
+    // The third interrupt is raised 30 instructions after the store: 6 in
+    // the first two handler runs, ei, then 4 iterations of the 5
+    // instructions loop and the first 3 instructions of the fifth one.
+    assert_eq!(5, machine.regs()[7]);
+    assert!(!machine.in_interrupt());
+    assert!(machine.interrupts_enabled());
+}
+
+#[test]
+fn test_timer_is_deterministic() {
+    let mut cached = CachedMachine::new(new_machine(TIMER_PROGRAM, &[33]));
+    cached.run_on(&mut Vec::new()).unwrap();
+    let mut machine = new_machine(TIMER_PROGRAM, &[33]);
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(machine.regs(), cached.machine().regs());
+}
+
+#[test]
+fn test_handler_entry_and_return() {
+    // Loop forever, the handler for line 3 exits
+    let mut machine = new_machine(
+        "ei
+         jump_rel ip-3
+         exit",
+        &[0, 0, 0, 4],
+    );
+    machine.step_on(&mut Vec::new()).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert_eq!(1, machine.regs()[0]);
+    machine.raise_interrupt(3).unwrap();
+    assert_eq!(Ok(true), machine.step_on(&mut Vec::new()));
+    assert!(machine.in_interrupt());
+    assert!(!machine.interrupts_enabled());
+}
+
+#[test]
+fn test_lowest_line_first() {
+    // Handlers set r10 to their line number, then return
+    let mut machine = new_machine(
+        "  0000   ei
+           0001   exit
+           0002   loadimm r10 <- #2
+           0006   reti
+           0007   loadimm r10 <- #5
+           0011   reti",
+        &[0, 0, 2, 0, 0, 7],
+    );
+    machine.raise_interrupt(5).unwrap();
+    machine.raise_interrupt(2).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    // Enter the handler of line 2 and return
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert_eq!(2, machine.regs()[10]);
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert_eq!(1, machine.regs()[0]);
+    // Line 5 is delivered before executing exit
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert_eq!(5, machine.regs()[10]);
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert_eq!(Ok(true), machine.step_on(&mut Vec::new()));
+}
+
+#[test]
+fn test_disabled_interrupts() {
+    let mut machine = new_machine("ei\ndi\nexit\nexit", &[3]);
+    machine.raise_interrupt(0).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    // Delivered before di
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert!(machine.in_interrupt());
+    assert_eq!(4, machine.regs()[0]);
+
+    let mut machine = new_machine("di\nexit", &[1]);
+    machine.raise_interrupt(0).unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert!(!machine.in_interrupt());
+
+    // Without a vector table, interrupts stay pending
+    let mut machine = new_machine("ei\nexit", &[0]);
+    machine.set_vector_table(None);
+    machine.raise_interrupt(0).unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert!(!machine.in_interrupt());
+}
+
+#[test]
+fn test_stop_timer() {
+    // Count to 20 in r7 while the timer handler increments r6 and stops
+    // the timer
+    let mut machine = new_machine(
+        "  0000   loadimm r4 <- #2000
+           0004   loadimm r5 <- #2
+           0008   store [r4] <- r5
+           0011   ei
+           0012   loadimm r3 <- #-1
+           0016   sub r7 <- r7 - r3
+           0020   loadimm r3 <- #20
+           0024   sub r8 <- r7 - r3
+           0028   jump_rel ip-20 if r8 != 0
+           0032   exit
+           0033   store [r4] <- r1
+           0036   loadimm r9 <- #-1
+           0040   sub r6 <- r6 - r9
+           0044   reti",
+        &[33],
+    );
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(1, machine.regs()[6]);
+    assert_eq!(20, machine.regs()[7]);
+}
+
+#[test]
+fn test_errors() {
+    let mut machine = new_machine("reti", &[]);
+    assert_eq!(
+        Err(Error::NotInInterrupt(0)),
+        machine.step_on(&mut Vec::new())
+    );
+    assert_eq!(
+        Err(Error::InvalidInterrupt(MAX_IRQS)),
+        machine.raise_interrupt(MAX_IRQS)
+    );
+    assert_eq!(
+        Err(Error::InvalidMemoryAddress(4093)),
+        machine.map_timer(4093)
+    );
+    // Vector table entry past the end of memory
+    let mut machine = new_machine("ei\nexit", &[]);
+    machine.set_vector_table(Some(4094));
+    machine.raise_interrupt(TIMER_IRQ).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert_eq!(
+        Err(Error::InvalidMemoryAddress(4096)),
+        machine.step_on(&mut Vec::new())
+    );
+}
@@ -0,0 +1,232 @@
+use interpreter::{
+    assemble, assemble_object, discover_programs, LinkError, Linker, Machine, Object, ObjectError,
+    Outcome, Relocation, RelocationKind, Section, SectionKind, Symbol,
+};
+use std::fs;
+use std::path::Path;
+
+fn generated(name: &str) -> Object {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(name);
+    Object::parse(&fs::read(path).unwrap()).unwrap()
+}
+
+fn symbol(name: &str, address: u32, section: Option<usize>) -> Symbol {
+    Symbol {
+        name: name.to_owned(),
+        address,
+        section,
+        global: true,
+    }
+}
+
+fn relocation(offset: u32, kind: RelocationKind, symbol: &str, addend: i32) -> Relocation {
+    Relocation {
+        section: 0,
+        offset,
+        kind,
+        symbol: symbol.to_owned(),
+        addend,
+    }
+}
+
+/// Module whose code is `source` with a relocation of `kind` against
+/// `target` at `offset`.
+fn module(source: &str, offset: u32, kind: RelocationKind, target: &str) -> Object {
+    let addend = if kind == RelocationKind::Relative {
+        -2
+    } else {
+        0
+    };
+    Object {
+        sections: vec![Section {
+            kind: SectionKind::Code,
+            address: 0,
+            bytes: assemble(source).unwrap(),
+        }],
+        symbols: vec![symbol(target, 0, None)],
+        relocations: vec![relocation(offset, kind, target, addend)],
+        ..Object::default()
+    }
+}
+
+#[test]
+fn test_separate_compilation() {
+    let mut linker = Linker::new();
+    linker.add_module("hello_main.obj", generated("tests/hello_main.obj"));
+    linker.add_library("mult_lib.obj", generated("tests/mult_lib.obj"));
+    linker.add_library("print_lib.obj", generated("tests/print_lib.obj"));
+    let (object, map) = linker.link().unwrap();
+    assert!(object.relocations.is_empty());
+
+    // The unused library is left out
+    let modules = map.sections.iter().map(|p| p.module.as_str());
+    assert_eq!(
+        vec!["hello_main.obj", "print_lib.obj", "hello_main.obj"],
+        modules.collect::<Vec<_>>()
+    );
+    assert_eq!(
+        vec!["main", "print"],
+        map.symbols.iter().map(|(_, n)| n).collect::<Vec<_>>()
+    );
+    let print = map.symbols[1].0;
+    assert_eq!(Some("print"), object.symbol_map().lookup(print + 3));
+    let text = map.to_string();
+    assert!(text.starts_with("0000 "), "{text}");
+    assert!(text.contains(&format!("{print:04} print\n")), "{text}");
+    assert_eq!(Some(1), object.line_of(0));
+
+    let mut output = Vec::new();
+    Machine::load_program(&object.to_bytes())
+        .unwrap()
+        .run_on(&mut output)
+        .unwrap();
+    assert_eq!(b"Hello, world!\n", &output[..]);
+    assert_eq!(
+        Outcome::of(include_bytes!("../examples/hello_world.bin")).output,
+        Outcome::of(&object.to_image()).output
+    );
+
+    // Modules cannot be loaded before being linked
+    assert_eq!(
+        Err(ObjectError::UndefinedSymbol(String::from("print"))),
+        Machine::load_program(&generated("tests/hello_main.obj").to_bytes()).map(|_| ())
+    );
+}
+
+#[test]
+fn test_relink_examples() {
+    // Data sections move to the next word, so every reference is relocated.
+    // Registers holding addresses of data differ.
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    for program in discover_programs(&root.join("examples")).unwrap() {
+        let mut linker = Linker::new();
+        let object = Object::parse(&fs::read(program.with_extension("obj")).unwrap()).unwrap();
+        linker.add_module("example", object);
+        let (object, _) = linker.link().unwrap();
+        let expected = Outcome::with_step_limit(&fs::read(&program).unwrap(), 100_000);
+        let actual = Outcome::with_step_limit(&object.to_bytes(), 100_000);
+        assert_eq!(
+            (expected.status, expected.output),
+            (actual.status, actual.output),
+            "{}",
+            program.display()
+        );
+    }
+}
+
+#[test]
+fn test_relocation_kinds() {
+    // Each module stores the address of `value` in r4, then prints the
+    // word at this address
+    let print = "load r5 <- [r4]\nout_number r5\nexit";
+    let modules = [
+        module(
+            &format!("loadimm r4 <- #0\n{print}"),
+            2,
+            RelocationKind::Low,
+            "value",
+        ),
+        module(
+            &format!("loadimm32 r4 <- #0\n{print}"),
+            2,
+            RelocationKind::Word,
+            "value",
+        ),
+        module(
+            &format!("loadrel r4 <- ip+0\n{print}"),
+            2,
+            RelocationKind::Relative,
+            "value",
+        ),
+    ];
+    for mut first in modules {
+        if first.relocations[0].kind == RelocationKind::Low {
+            first
+                .relocations
+                .push(relocation(3, RelocationKind::High, "value", 0));
+        }
+        let data = Object {
+            sections: vec![Section {
+                kind: SectionKind::Data,
+                address: 0,
+                bytes: 1234u32.to_le_bytes().to_vec(),
+            }],
+            symbols: vec![symbol("value", 0, Some(0))],
+            ..Object::default()
+        };
+        let mut linker = Linker::new();
+        linker.add_module("first", first);
+        linker.add_module("data", data);
+        let (object, _) = linker.link().unwrap();
+        let mut output = Vec::new();
+        Machine::load_program(&object.to_bytes())
+            .unwrap()
+            .run_on(&mut output)
+            .unwrap();
+        assert_eq!(b"1234", &output[..]);
+    }
+}
+
+#[test]
+fn test_empty_modules() {
+    let mut linker = Linker::new();
+    linker.add_module("main", assemble_object("").unwrap());
+    let (object, _) = linker.link().unwrap();
+    assert!(object.sections.is_empty());
+
+    // An empty data section ends up at the end of the code
+    let mut linker = Linker::new();
+    linker.add_module("main", assemble_object("exit").unwrap());
+    linker.add_module("data", assemble_object(".data").unwrap());
+    let (object, _) = linker.link().unwrap();
+    assert_eq!(vec![7], object.to_image());
+}
+
+#[test]
+fn test_link_errors() {
+    assert_eq!(Err(LinkError::NoModule), Linker::new().link().map(|_| ()));
+
+    let mut linker = Linker::new();
+    linker.add_module(
+        "main",
+        module("jump_rel ip+0", 1, RelocationKind::Relative, "f"),
+    );
+    assert_eq!(
+        Err(LinkError::UndefinedSymbol(String::from("f"))),
+        linker.link().map(|_| ())
+    );
+
+    let mut linker = Linker::new();
+    let mut defined = module("exit", 0, RelocationKind::Low, "f");
+    defined.relocations.clear();
+    defined.symbols = vec![symbol("f", 0, Some(0))];
+    linker.add_module("a", defined.clone());
+    linker.add_module("b", defined);
+    assert_eq!(
+        Err(LinkError::DuplicateSymbol(String::from("f"))),
+        linker.link().map(|_| ())
+    );
+
+    // 70000 does not fit in a loadimm immediate
+    let mut linker = Linker::new();
+    let mut far = module("loadimm r4 <- #0", 2, RelocationKind::Low, "f");
+    far.symbols = vec![symbol("f", 0, Some(0))];
+    far.relocations[0].addend = 70_000;
+    linker.add_module("far", far);
+    assert_eq!(
+        Err(LinkError::RelocationOverflow(String::from("f"))),
+        linker.link().map(|_| ())
+    );
+
+    let mut linker = Linker::new();
+    let mut large = module("exit", 0, RelocationKind::Low, "f");
+    large.relocations.clear();
+    large.symbols.clear();
+    large.sections[0].bytes.resize(3000, 0);
+    linker.add_module("a", large.clone());
+    linker.add_module("b", large);
+    assert_eq!(
+        Err(LinkError::MemoryOverflow(6000)),
+        linker.link().map(|_| ())
+    );
+}
@@ -0,0 +1,245 @@
+use interpreter::{
+    assemble, discover_programs, Error, Machine, Object, ObjectError, Outcome, Relocation,
+    RelocationKind, Section, SectionKind, Symbol, FEATURE_FLOAT, MAGIC, MAX_REGIONS,
+};
+use std::fs;
+use std::path::Path;
+
+fn sample() -> Object {
+    let mut object = Object {
+        entry: 4,
+        stack: 3000,
+        sections: vec![
+            Section {
+                kind: SectionKind::Code,
+                address: 0,
+                bytes: assemble("loadimm r5 <- #1\nload r4 <- [r10]\nout_number r4\nexit").unwrap(),
+            },
+            Section {
+                kind: SectionKind::Data,
+                address: 2000,
+                bytes: 42u32.to_le_bytes().to_vec(),
+            },
+            Section {
+                kind: SectionKind::Bss,
+                address: 2004,
+                bytes: vec![0; 8],
+            },
+        ],
+        symbols: vec![
+            Symbol {
+                name: String::from("main"),
+                address: 0,
+                section: Some(0),
+                global: true,
+            },
+            Symbol {
+                name: String::from("start"),
+                address: 4,
+                section: Some(0),
+                global: true,
+            },
+        ],
+        ..Object::default()
+    };
+    object.lines.source = String::from("sample.s");
+    object.lines.lines = vec![(0, 1), (4, 2), (7, 3), (9, 4)];
+    object
+}
+
+#[test]
+fn test_round_trip() {
+    let object = sample();
+    let bytes = object.to_bytes();
+    assert!(Object::is_object(&bytes));
+    assert!(!Object::is_object(include_bytes!("../examples/count.bin")));
+    assert_eq!(Ok(object), Object::parse(&bytes));
+    // The content of bss sections is not stored
+    let mut data = sample();
+    data.sections[2].kind = SectionKind::Data;
+    assert_eq!(bytes.len() + 8, data.to_bytes().len());
+}
+
+#[test]
+fn test_version_1() {
+    // Version 1 has no section indices, global flags nor relocations
+    let mut bytes = MAGIC.to_vec();
+    // Version 1 without features, entry and stack
+    bytes.extend([1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    // A code section at 0 containing exit
+    bytes.extend([1, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 7]);
+    // A symbol at 1, after the exit instruction
+    bytes.extend([1, 0, 0, 0, 1, 0, 0, 0, 4, 0]);
+    bytes.extend(b"exit");
+    // No line information
+    bytes.extend([0, 0, 0, 0, 0, 0]);
+    let object = Object::parse(&bytes).unwrap();
+    assert_eq!(
+        vec![Symbol {
+            name: String::from("exit"),
+            address: 1,
+            section: Some(0),
+            global: true,
+        }],
+        object.symbols
+    );
+    assert_eq!(vec![7], object.sections[0].bytes);
+    let mut machine = Machine::load_program(&bytes).unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+}
+
+#[test]
+fn test_malformed() {
+    let bytes = sample().to_bytes();
+    assert_eq!(Err(ObjectError::BadMagic), Object::parse(b"VMO"));
+    assert_eq!(Err(ObjectError::BadMagic), Object::parse(&[4, 5, 0, 0]));
+    for len in [4, 10, 20, bytes.len() - 1] {
+        assert_eq!(Err(ObjectError::Truncated), Object::parse(&bytes[..len]));
+    }
+
+    let mut version = bytes.clone();
+    version[4] = 9;
+    assert_eq!(
+        Err(ObjectError::UnsupportedVersion(9)),
+        Object::parse(&version)
+    );
+
+    let mut features = bytes.clone();
+    features[6] = 0x82;
+    assert_eq!(
+        Err(ObjectError::UnsupportedFeatures(0x82)),
+        Object::parse(&features)
+    );
+    features[6] = 0x01;
+    assert_eq!(
+        cfg!(feature = "float"),
+        Object::parse(&features).is_ok_and(|o| o.features == FEATURE_FLOAT)
+    );
+
+    let mut kind = bytes;
+    kind[MAGIC.len() + 16] = 7;
+    assert_eq!(
+        Err(ObjectError::InvalidSectionKind(7)),
+        Object::parse(&kind)
+    );
+}
+
+#[test]
+fn test_load_object() {
+    let mut machine = Machine::new(&[]).unwrap();
+    machine.load_object(&sample()).unwrap();
+    assert_eq!(4, machine.regs()[0]);
+    assert_eq!(3000, machine.regs()[2]);
+    assert_eq!(0, machine.regs()[5]);
+    machine.set_reg(10, 2000).unwrap();
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(b"42", &out[..]);
+
+    // Code is read-only and data is not executable
+    let mut machine = Machine::load_program(&sample().to_bytes()).unwrap();
+    machine
+        .load_at(100, &assemble("store [r10] <- r5").unwrap(), None)
+        .unwrap();
+    machine.set_reg(0, 100).unwrap();
+    machine.set_reg(10, 2).unwrap();
+    assert_eq!(
+        Err(Error::WriteViolation(2)),
+        machine.step_on(&mut Vec::new())
+    );
+    machine
+        .load_at(2004, &assemble("exit").unwrap(), None)
+        .unwrap();
+    machine.set_reg(0, 2004).unwrap();
+    assert_eq!(
+        Err(Error::ExecuteViolation(2004)),
+        machine.step_on(&mut Vec::new())
+    );
+
+    let mut object = sample();
+    object.sections[1].address = 4094;
+    assert_eq!(
+        Err(ObjectError::Load(Error::MemoryOverflow)),
+        Machine::load_program(&object.to_bytes()).map(|_| ())
+    );
+
+    // Adjacent sections with the same protection share a region
+    let mut object = sample();
+    object.sections = (0..)
+        .take(MAX_REGIONS + 1)
+        .map(|i: u32| Section {
+            kind: SectionKind::Code,
+            address: 4 * i,
+            bytes: vec![0; 4],
+        })
+        .collect();
+    let mut machine = Machine::load_program(&object.to_bytes()).unwrap();
+    machine.set_reg(10, 35).unwrap();
+    machine.set_reg(0, 100).unwrap();
+    machine
+        .load_at(100, &assemble("store [r10] <- r5").unwrap(), None)
+        .unwrap();
+    assert_eq!(
+        Err(Error::WriteViolation(35)),
+        machine.step_on(&mut Vec::new())
+    );
+
+    // Relocations have to be applied by the linker first
+    let mut object = sample();
+    object.relocations.push(Relocation {
+        section: 0,
+        offset: 2,
+        kind: RelocationKind::Word,
+        symbol: String::from("main"),
+        addend: 0,
+    });
+    assert_eq!(
+        Err(ObjectError::UnresolvedRelocation(String::from("main"))),
+        Machine::load_program(&object.to_bytes()).map(|_| ())
+    );
+}
+
+#[test]
+fn test_symbols_and_lines() {
+    let object = sample();
+    let symbols = object.symbol_map();
+    assert_eq!(Some("main"), symbols.lookup(3));
+    assert_eq!(Some("start"), symbols.lookup(8));
+    assert_eq!(Some(1), object.line_of(0));
+    assert_eq!(Some(2), object.line_of(6));
+    assert_eq!(Some(4), object.line_of(1000));
+    let mut object = sample();
+    object.lines.lines[0].0 = 2;
+    assert_eq!(None, object.line_of(1));
+}
+
+#[test]
+fn test_generated_objects() {
+    // Every generated program behaves the same as an object and as a raw
+    // memory image, except that writes into the code are caught earlier
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    for dir in ["examples", "tests"] {
+        for program in discover_programs(&root.join(dir)).unwrap() {
+            let bin = fs::read(&program).unwrap();
+            let obj = fs::read(program.with_extension("obj")).unwrap();
+            let expected = Outcome::with_step_limit(&bin, 100_000);
+            let actual = Outcome::with_step_limit(&obj, 100_000);
+            if expected.status.starts_with("error") {
+                assert!(actual.status.starts_with("error"), "{}", program.display());
+            } else {
+                assert_eq!(expected, actual, "{}", program.display());
+            }
+            let object = Object::parse(&obj).unwrap();
+            let sym = fs::read_to_string(program.with_extension("sym")).unwrap();
+            assert_eq!(
+                sym.lines().collect::<Vec<_>>(),
+                object
+                    .symbol_map()
+                    .iter()
+                    .map(|(addr, name)| format!("{addr:04} {name}"))
+                    .collect::<Vec<_>>()
+            );
+            assert_eq!((Some(1), Some(2)), (object.line_of(0), object.line_of(4)));
+        }
+    }
+}
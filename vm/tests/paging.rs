@@ -0,0 +1,162 @@
+use interpreter::{
+    assemble, CachedMachine, Error, FaultCause, Machine, PageTable, FAULT_CAUSE_REG,
+    FAULT_VALUE_REG, PAGE_EXECUTE, PAGE_READ, PAGE_WRITE,
+};
+
+const TABLE: PageTable = PageTable {
+    base: 3072,
+    pages: 256,
+};
+const CODE: u32 = 0xf000;
+const CODE_FRAME: u32 = 256;
+const DATA: u32 = 0x8000;
+const DATA_FRAME: u32 = 512;
+
+/// Machine running `source` at the virtual address `CODE`, with a
+/// read-write data page at `DATA`.
+fn new_machine(source: &str) -> Machine {
+    let mut machine = Machine::new(&[]).unwrap();
+    machine
+        .load_at(CODE_FRAME, &assemble(source).unwrap(), Some(0))
+        .unwrap();
+    machine.set_page_table(Some(TABLE)).unwrap();
+    machine
+        .map_page(CODE, CODE_FRAME, PAGE_READ | PAGE_EXECUTE)
+        .unwrap();
+    machine
+        .map_page(DATA, DATA_FRAME, PAGE_READ | PAGE_WRITE)
+        .unwrap();
+    machine.set_reg(0, CODE).unwrap();
+    machine
+}
+
+const PROGRAM: &str = "loadimm32 r4 <- #32768
+                       loadimm r5 <- #42
+                       store [r4] <- r5
+                       load r6 <- [r4]
+                       out_number r6
+                       exit";
+
+#[test]
+fn test_high_addresses() {
+    let mut machine = new_machine(PROGRAM);
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(b"42", &out[..]);
+    assert_eq!(42, machine.memory()[DATA_FRAME as usize]);
+    assert_eq!(CODE + 19, machine.regs()[0]);
+
+    let mut cached = CachedMachine::new(new_machine(PROGRAM));
+    let mut out = Vec::new();
+    cached.run_on(&mut out).unwrap();
+    assert_eq!(b"42", &out[..]);
+}
+
+#[test]
+fn test_permissions() {
+    // Write to a read-only page
+    let mut machine = new_machine("loadimm32 r4 <- #61440\nstore [r4] <- r4");
+    assert_eq!(Err(Error::PageFault(CODE)), machine.run_on(&mut Vec::new()));
+    assert_eq!(0, machine.memory()[CODE_FRAME as usize + 12]);
+
+    // Execute a data page
+    let mut machine = new_machine("loadimm32 r4 <- #32768\nmove r0 <- r4 if r4 != 0");
+    assert_eq!(Err(Error::PageFault(DATA)), machine.run_on(&mut Vec::new()));
+
+    // Unmapped page, and page beyond the end of the table
+    let mut machine = new_machine("load r5 <- [r4]");
+    machine.set_reg(4, 0x100).unwrap();
+    assert_eq!(
+        Err(Error::PageFault(0x100)),
+        machine.run_on(&mut Vec::new())
+    );
+    machine.set_reg(0, CODE).unwrap();
+    machine.set_reg(4, 0x10000).unwrap();
+    assert_eq!(
+        Err(Error::PageFault(0x10000)),
+        machine.run_on(&mut Vec::new())
+    );
+}
+
+#[test]
+fn test_instruction_across_pages() {
+    // The second page of the instruction is mapped to a lower frame
+    let code = assemble("loadimm32 r4 <- #305419896\nexit").unwrap();
+    let mut machine = new_machine("exit");
+    machine.load_at(CODE_FRAME + 252, &code[..4], None).unwrap();
+    machine.load_at(0, &code[4..], None).unwrap();
+    machine.set_reg(0, CODE + 252).unwrap();
+    assert_eq!(
+        Err(Error::PageFault(CODE + 256)),
+        machine.step_on(&mut Vec::new())
+    );
+    machine
+        .map_page(CODE + 256, 0, PAGE_READ | PAGE_EXECUTE)
+        .unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(0x1234_5678, machine.regs()[4]);
+}
+
+#[test]
+fn test_demand_paging() {
+    // The fault handler maps the faulting page, which is always 0x2000, to
+    // the frame at 2048, then retries the instruction
+    let mut machine = new_machine(
+        "loadimm r4 <- #8192
+         loadimm r5 <- #7
+         store [r4] <- r5
+         load r6 <- [r4]
+         exit",
+    );
+    let handler = assemble(
+        "loadimm r3 <- #-1
+         sub r10 <- r10 - r3
+         loadimm r8 <- #3200
+         loadimm r9 <- #2051
+         store [r8] <- r9
+         move r0 <- r13 if r3 != 0",
+    )
+    .unwrap();
+    machine.load_at(1536, &handler, None).unwrap();
+    machine
+        .map_page(0xf800, 1536, PAGE_READ | PAGE_EXECUTE)
+        .unwrap();
+    machine
+        .map_page(3072, 3072, PAGE_READ | PAGE_WRITE)
+        .unwrap();
+    machine.set_fault_handler(Some(0xf800));
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(1, machine.regs()[10]);
+    assert_eq!(7, machine.regs()[6]);
+    assert_eq!(7, machine.memory()[2048]);
+    assert_eq!(
+        FaultCause::PageFault.code(),
+        machine.regs()[FAULT_CAUSE_REG]
+    );
+    assert_eq!(0x2000, machine.regs()[FAULT_VALUE_REG]);
+}
+
+#[test]
+fn test_configuration() {
+    let mut machine = new_machine("exit");
+    assert_eq!(Some(TABLE), machine.page_table());
+    assert_eq!(
+        Err(Error::InvalidMemoryAddress(4000)),
+        machine.set_page_table(Some(PageTable {
+            base: 4000,
+            pages: 100
+        }))
+    );
+    assert_eq!(
+        Err(Error::InvalidMemoryAddress(100)),
+        machine.map_page(0, 100, PAGE_READ)
+    );
+    assert_eq!(
+        Err(Error::PageFault(0x10000)),
+        machine.map_page(0x10000, 0, PAGE_READ)
+    );
+    // Without paging, addresses are physical
+    machine.set_page_table(None).unwrap();
+    machine.set_reg(0, CODE_FRAME).unwrap();
+    assert_eq!(Ok(()), machine.run_on(&mut Vec::new()));
+}
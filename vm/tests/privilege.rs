@@ -0,0 +1,169 @@
+use interpreter::{
+    assemble, Error, FaultCause, Machine, PageTable, FAULT_CAUSE_REG, PAGE_EXECUTE, PAGE_READ,
+    PAGE_SIZE, PAGE_WRITE,
+};
+
+const USER: u32 = 100;
+
+/// Machine running `kernel` in supervisor mode from address 0 and `user`
+/// at `USER`.
+fn new_machine(kernel: &str, user: &str) -> Machine {
+    let mut machine = Machine::new(&assemble(kernel).unwrap()).unwrap();
+    machine
+        .load_at(USER, &assemble(user).unwrap(), None)
+        .unwrap();
+    machine
+}
+
+#[test]
+fn test_syscall() {
+    // The system call increments r9
+    let mut machine = new_machine(
+        "  0000   loadimm r10 <- #100
+           0004   sysret r10
+           0006   loadimm r3 <- #-1
+           0010   sub r9 <- r9 - r3
+           0014   sysret r13",
+        "syscall\nsyscall\nexit",
+    );
+    machine.set_syscall_entry(Some(6));
+    assert!(!machine.user_mode());
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(2, machine.regs()[9]);
+    assert!(machine.user_mode());
+
+    let mut machine = new_machine("syscall", "");
+    assert_eq!(
+        Err(Error::NoSyscallEntry(0)),
+        machine.run_on(&mut Vec::new())
+    );
+}
+
+#[test]
+fn test_privileged_instructions() {
+    for (source, size) in [
+        ("ei", 1),
+        ("di", 1),
+        ("reti", 1),
+        ("trap #1", 2),
+        ("sysret r4", 2),
+    ] {
+        let mut machine = new_machine("", source);
+        machine.set_user_mode(true);
+        machine.set_reg(0, USER).unwrap();
+        assert_eq!(
+            Err(Error::PrivilegedInstruction(USER)),
+            machine.step_on(&mut Vec::new()),
+            "{source}"
+        );
+        assert_eq!(USER, machine.regs()[0]);
+        // Allowed in supervisor mode
+        if size == 1 && source != "reti" {
+            machine.set_user_mode(false);
+            machine.step_on(&mut Vec::new()).unwrap();
+            assert_eq!(USER + size, machine.regs()[0]);
+        }
+    }
+}
+
+#[test]
+fn test_device_memory() {
+    let mut machine = new_machine("", "store [r4] <- r5\nload r5 <- [r4]");
+    machine.map_timer(2000).unwrap();
+    machine.map_framebuffer(3000).unwrap();
+    machine.set_vector_table(Some(1000));
+    for addr in [2000, 1998, 3100, 3192, 1028] {
+        machine.set_user_mode(true);
+        machine.set_reg(0, USER).unwrap();
+        machine.set_reg(4, addr).unwrap();
+        assert_eq!(
+            Err(Error::PrivilegedMemory(addr)),
+            machine.step_on(&mut Vec::new())
+        );
+        machine.set_reg(0, USER + 3).unwrap();
+        assert_eq!(
+            Err(Error::PrivilegedMemory(addr)),
+            machine.step_on(&mut Vec::new())
+        );
+        machine.set_user_mode(false);
+        machine.set_reg(0, USER + 3).unwrap();
+        machine.step_on(&mut Vec::new()).unwrap();
+    }
+    // Next to the vector table
+    machine.set_user_mode(true);
+    machine.set_reg(0, USER).unwrap();
+    machine.set_reg(4, 1032).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+}
+
+#[test]
+fn test_page_table_memory() {
+    let mut machine = new_machine("", "store [r4] <- r5");
+    machine
+        .set_page_table(Some(PageTable {
+            base: 3072,
+            pages: 16,
+        }))
+        .unwrap();
+    for page in 0..16 {
+        let addr = page * PAGE_SIZE;
+        machine
+            .map_page(addr, addr, PAGE_READ | PAGE_WRITE | PAGE_EXECUTE)
+            .unwrap();
+    }
+    machine.set_user_mode(true);
+    machine.set_reg(0, USER).unwrap();
+    machine.set_reg(4, 3072).unwrap();
+    assert_eq!(
+        Err(Error::PrivilegedMemory(3072)),
+        machine.step_on(&mut Vec::new())
+    );
+}
+
+#[test]
+fn test_violation_enters_fault_handler() {
+    // The kernel stops user programs doing something forbidden
+    let mut machine = new_machine(
+        "  0000   loadimm r10 <- #100
+           0004   sysret r10
+           0006   exit",
+        "loadimm r4 <- #7\nei\nloadimm r4 <- #8",
+    );
+    machine.set_fault_handler(Some(6));
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert!(!machine.user_mode());
+    assert_eq!(7, machine.regs()[4]);
+    assert_eq!(
+        FaultCause::PrivilegedInstruction.code(),
+        machine.regs()[FAULT_CAUSE_REG]
+    );
+}
+
+#[test]
+fn test_interrupt_from_user_mode() {
+    // The handler writes the timer period, which requires supervisor mode
+    let mut machine = new_machine(
+        "  0000   loadimm r4 <- #2000
+           0004   store [r4] <- r1
+           0007   reti",
+        "loadimm r5 <- #1\nexit",
+    );
+    machine.load_at(1000, &0u32.to_le_bytes(), None).unwrap();
+    machine.set_vector_table(Some(1000));
+    machine.map_timer(2000).unwrap();
+    machine.set_user_mode(true);
+    machine.set_reg(0, USER).unwrap();
+    machine.raise_interrupt(0).unwrap();
+    // Interrupts can only be enabled by supervisor code
+    machine.set_user_mode(false);
+    machine
+        .load_at(200, &assemble("ei\nsysret r6").unwrap(), None)
+        .unwrap();
+    machine.set_reg(6, USER).unwrap();
+    machine.set_reg(0, 200).unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert!(machine.user_mode());
+    assert!(!machine.in_interrupt());
+    assert_eq!(2000, machine.regs()[4]);
+    assert_eq!(1, machine.regs()[5]);
+}
@@ -0,0 +1,130 @@
+use interpreter::{Error, Machine, Protection, MAX_REGIONS};
+
+fn run(machine: &mut Machine) -> Result<(), Error> {
+    machine.run_on(&mut Vec::new())
+}
+
+// 0: loadimm r3 <- #4
+// 4: sub r2 <- r2 - r3
+// 8: store [r2] <- r3
+// 11: loadimm r0 <- #4
+const PUSH_FOREVER: [u8; 15] = [4, 3, 4, 0, 5, 2, 2, 3, 2, 2, 3, 4, 0, 4, 0];
+
+#[test]
+fn test_write_to_read_only() {
+    // 0: store [r2] <- r3
+    // 3: exit
+    let mut machine = Machine::new(&[2, 2, 3, 7]).unwrap();
+    machine.set_reg(3, 0xdead_beef).unwrap();
+    machine.protect(0..4, Protection::ReadOnly).unwrap();
+    assert!(matches!(run(&mut machine), Err(Error::WriteViolation(0))));
+    assert_eq!(&[2, 2, 3, 7], &machine.memory()[..4]);
+
+    // Partial overlap is detected as well
+    machine.set_reg(0, 0).unwrap();
+    machine.set_reg(2, 2).unwrap();
+    assert!(matches!(run(&mut machine), Err(Error::WriteViolation(2))));
+}
+
+#[test]
+fn test_execute_no_execute() {
+    // 0: loadimm r0 <- #100
+    // 100: exit
+    let mut memory = vec![4, 0, 100, 0];
+    memory.resize(100, 0);
+    memory.push(7);
+    let mut machine = Machine::new(&memory).unwrap();
+    machine.protect(100..200, Protection::NoExecute).unwrap();
+    assert!(matches!(
+        run(&mut machine),
+        Err(Error::ExecuteViolation(100))
+    ));
+
+    // An instruction crossing into the region is refused as well
+    let mut memory = vec![0; 98];
+    memory.extend([4, 1, 0, 0]);
+    let mut machine = Machine::new(&memory).unwrap();
+    machine.set_reg(0, 98).unwrap();
+    machine.protect(100..200, Protection::NoExecute).unwrap();
+    assert!(matches!(
+        machine.step_on(&mut Vec::new()),
+        Err(Error::ExecuteViolation(100))
+    ));
+    assert_eq!(98, machine.regs()[0]);
+}
+
+#[test]
+fn test_guard() {
+    // 0: load r1 <- [r2]
+    // 3: exit
+    let mut machine = Machine::new(&[3, 1, 2, 7]).unwrap();
+    machine.set_reg(2, 1000).unwrap();
+    machine.protect(1002..1003, Protection::Guard).unwrap();
+    assert!(matches!(
+        run(&mut machine),
+        Err(Error::GuardViolation(1002))
+    ));
+}
+
+#[test]
+fn test_stack_overflow_into_code() {
+    let mut machine = Machine::new(&PUSH_FOREVER).unwrap();
+    machine.set_reg(2, 4096).unwrap();
+    machine.protect(100..200, Protection::Guard).unwrap();
+    assert!(matches!(run(&mut machine), Err(Error::GuardViolation(196))));
+    assert_eq!(196, machine.regs()[2]);
+}
+
+#[test]
+fn test_most_recent_region_applies() {
+    // 0: store [r2] <- r3
+    // 3: exit
+    let mut machine = Machine::new(&[2, 2, 3, 7]).unwrap();
+    machine.set_reg(2, 500).unwrap();
+    machine.protect(0..1000, Protection::ReadOnly).unwrap();
+    machine.protect(500..504, Protection::NoExecute).unwrap();
+    run(&mut machine).unwrap();
+
+    machine.clear_protection();
+    machine.protect(500..504, Protection::NoExecute).unwrap();
+    machine.protect(0..1000, Protection::ReadOnly).unwrap();
+    machine.set_reg(0, 0).unwrap();
+    assert!(matches!(run(&mut machine), Err(Error::WriteViolation(500))));
+}
+
+#[test]
+fn test_wx() {
+    let program = include_bytes!("../examples/hello_world.bin");
+    // Code is located before the "Hello, world!\n" string
+    let code_end = u32::try_from(program.len()).unwrap() - 14;
+
+    let mut machine = Machine::new(program).unwrap();
+    machine.enforce_wx(true);
+    assert!(matches!(run(&mut machine), Err(Error::ExecuteViolation(0))));
+
+    let mut machine = Machine::new(program).unwrap();
+    machine.enforce_wx(true);
+    machine.protect(0..code_end, Protection::ReadOnly).unwrap();
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(b"Hello, world!\n", &out[..]);
+
+    // Self-modifying code gets caught
+    let mut machine = Machine::new(&PUSH_FOREVER).unwrap();
+    machine.set_reg(2, 4096).unwrap();
+    machine.enforce_wx(true);
+    machine.protect(0..15, Protection::ReadOnly).unwrap();
+    assert!(matches!(run(&mut machine), Err(Error::WriteViolation(12))));
+}
+
+#[test]
+fn test_too_many_regions() {
+    let mut machine = Machine::new(&[]).unwrap();
+    for _ in 0..MAX_REGIONS {
+        machine.protect(0..1, Protection::Guard).unwrap();
+    }
+    assert!(matches!(
+        machine.protect(0..1, Protection::Guard),
+        Err(Error::TooManyRegions)
+    ));
+}
@@ -0,0 +1,240 @@
+use interpreter::{
+    assemble, assemble_object, routine_library, Linker, Machine, Object, RelocationKind,
+    SectionKind, ROUTINES,
+};
+
+/// Where the library is loaded
+const BASE: u32 = 1000;
+const STACK: u32 = 4000;
+/// Where tests put their data
+const DATA: u32 = 2000;
+
+/// Code calling `routine` of the library loaded at `BASE`, and exiting when
+/// it returns.
+fn caller(routine: &str) -> Vec<u8> {
+    let library = routine_library();
+    let symbol = library.symbols.iter().find(|s| s.name == routine).unwrap();
+    let caller = assemble(&format!(
+        "loadimm r3 <- #4\nsub r2 <- r2 - r3\nloadimm r3 <- #19\nstore [r2] <- r3\n\
+         loadimm r0 <- #{}\nexit",
+        BASE + symbol.address
+    ))
+    .unwrap();
+    assert_eq!(20, caller.len());
+    caller
+}
+
+/// Machine calling `routine` with the ABI checked.
+fn machine(routine: &str, args: &[u32]) -> Machine {
+    let mut machine = Machine::new(&caller(routine)).unwrap();
+    machine
+        .load_at(BASE, &routine_library().to_image(), None)
+        .unwrap();
+    machine.set_reg(2, STACK).unwrap();
+    for (reg, &value) in (10..).zip(args) {
+        machine.set_reg(reg, value).unwrap();
+    }
+    machine.check_abi(true);
+    machine
+}
+
+/// Output of `machine`, and its registers once the routine has returned.
+fn run(mut machine: Machine) -> (String, Vec<u32>) {
+    let mut output = Vec::new();
+    machine.run_on(&mut output).unwrap();
+    assert_eq!(STACK, machine.regs()[2]);
+    (String::from_utf8(output).unwrap(), machine.regs().to_vec())
+}
+
+fn call(routine: &str, args: &[u32]) -> (String, Vec<u32>) {
+    run(machine(routine, args))
+}
+
+fn with_data(routine: &str, args: &[u32], data: &[u8]) -> Machine {
+    let mut machine = machine(routine, args);
+    machine.load_at(DATA, data, None).unwrap();
+    machine
+}
+
+#[test]
+fn test_library() {
+    let library = routine_library();
+    // Only the references to the data of the allocator depend on where the
+    // linker places the sections
+    assert_eq!(4, library.relocations.len());
+    assert!(library.relocations.iter().all(|r| r.section == 0
+        && r.kind == RelocationKind::Relative
+        && ["heap_next", "heap_end"].contains(&r.symbol.as_str())));
+    let globals = library.symbols.iter().filter(|s| s.global);
+    let mut names = globals.map(|s| s.name.as_str()).collect::<Vec<_>>();
+    names.sort_unstable();
+    let mut expected = ROUTINES.to_vec();
+    expected.sort_unstable();
+    assert_eq!(expected, names);
+    // The library survives a round trip through the object format
+    assert_eq!(Ok(library.clone()), Object::parse(&library.to_bytes()));
+}
+
+#[test]
+fn test_print() {
+    let machine = with_data("print", &[DATA + 1, 5], b"xHello, world!");
+    assert_eq!("Hello", run(machine).0);
+    assert_eq!("", call("print", &[DATA, 0]).0);
+}
+
+#[test]
+fn test_print_numbers() {
+    for n in [0, 7, 10, 1234, 4_000_000_000, u32::MAX] {
+        assert_eq!(n.to_string(), call("print_unsigned", &[n]).0);
+    }
+    for n in [0, 42, -1, -1234, i32::MAX, i32::MIN] {
+        assert_eq!(n.to_string(), call("print_signed", &[n.cast_unsigned()]).0);
+    }
+}
+
+#[test]
+fn test_mult() {
+    for (a, b) in [
+        (0, 5),
+        (6, 7),
+        (1234, 5678),
+        (u32::MAX, 3),
+        (65_536, 65_536),
+    ] {
+        assert_eq!(a.wrapping_mul(b), call("mult", &[a, b]).1[11], "{a} * {b}");
+    }
+}
+
+#[test]
+fn test_divmod() {
+    for (n, d) in [
+        (0, 3),
+        (7, 2),
+        (1234, 10),
+        (u32::MAX, 7),
+        (5, 9),
+        (1 << 31, 1 << 31),
+    ] {
+        let regs = call("divmod", &[n, d]).1;
+        assert_eq!((n / d, n % d), (regs[11], regs[12]), "{n} / {d}");
+    }
+    let regs = call("divmod", &[42, 0]).1;
+    assert_eq!((u32::MAX, 42), (regs[11], regs[12]));
+}
+
+#[test]
+fn test_sdivmod() {
+    for (n, d) in [
+        (7, 2_i32),
+        (-7, 2),
+        (7, -2),
+        (-7, -2),
+        (i32::MIN, 3),
+        (0, -5),
+    ] {
+        let regs = call("sdivmod", &[n.cast_unsigned(), d.cast_unsigned()]).1;
+        assert_eq!(
+            (n / d, n % d),
+            (regs[11].cast_signed(), regs[12].cast_signed()),
+            "{n} / {d}"
+        );
+    }
+    for n in [42, -42_i32] {
+        let regs = call("sdivmod", &[n.cast_unsigned(), 0]).1;
+        assert_eq!((-1, n), (regs[11].cast_signed(), regs[12].cast_signed()));
+    }
+}
+
+#[test]
+fn test_memcpy_and_memset() {
+    let mut machine = with_data("memcpy", &[DATA + 9, DATA + 1, 5], b"xabcdefghijklmno");
+    machine.run().unwrap();
+    assert_eq!(b"xabcdefghabcden", &machine.memory()[DATA as usize..][..15]);
+
+    let mut machine = with_data("memset", &[DATA + 2, 0x12A, 3], b"abcdefg");
+    machine.run().unwrap();
+    assert_eq!(b"ab***fg", &machine.memory()[DATA as usize..][..7]);
+}
+
+#[test]
+fn test_strlen() {
+    let machine = with_data("strlen", &[DATA], b"Hello\0world\0");
+    assert_eq!(5, run(machine).1[11]);
+    let machine = with_data("strlen", &[DATA + 5], b"Hello\0world\0");
+    assert_eq!(0, run(machine).1[11]);
+}
+
+#[test]
+fn test_alloc() {
+    let mut machine = machine("heap_init", &[3000, 3020]);
+    machine.run().unwrap();
+    // The allocator state survives between calls
+    let mut allocations = Vec::new();
+    for size in [5, 8, 1, 4, 0, 100] {
+        machine.load_at(0, &caller("alloc"), Some(0)).unwrap();
+        machine.set_reg(10, size).unwrap();
+        machine.run().unwrap();
+        allocations.push(machine.regs()[11]);
+    }
+    assert_eq!(vec![3000, 3008, 3016, 0, 3020, 0], allocations);
+}
+
+#[test]
+fn test_linked() {
+    // A program calls the library through relocations
+    let mut program =
+        assemble_object(".global print_signed\nloadimm r10 <- #-1234\njsr print_signed\nexit")
+            .unwrap();
+    program.stack = STACK;
+    let mut linker = Linker::new();
+    linker.add_module("main", program);
+    linker.add_library("routines", routine_library());
+    let (object, map) = linker.link().unwrap();
+    assert!(map.symbols.iter().any(|(_, name)| name == "divmod"));
+    let mut machine = Machine::load_program(&object.to_bytes()).unwrap();
+    machine.check_abi(true);
+    let mut output = Vec::new();
+    machine.run_on(&mut output).unwrap();
+    assert_eq!(b"-1234", &output[..]);
+}
+
+#[test]
+fn test_linked_heap() {
+    // The data of the program is placed before the one of the library, so
+    // the allocator state moves away from the code of the library
+    let mut program = assemble_object(
+        ".global heap_init, alloc
+         loadimm r10 <- #3000
+         loadimm r11 <- #3020
+         jsr heap_init
+         loadimm r10 <- #5
+         jsr alloc
+         exit
+         .data
+         .word -1, -1",
+    )
+    .unwrap();
+    program.stack = STACK;
+    let mut linker = Linker::new();
+    linker.add_module("main", program);
+    linker.add_library("routines", routine_library());
+    let (object, map) = linker.link().unwrap();
+    let data = |module: &str| {
+        let placement = (map.sections.iter())
+            .find(|p| p.module == module && p.kind == SectionKind::Data)
+            .unwrap();
+        placement.address as usize
+    };
+    assert!(data("main") < data("routines"));
+
+    let mut machine = Machine::load_program(&object.to_bytes()).unwrap();
+    machine.check_abi(true);
+    machine.run().unwrap();
+    assert_eq!(3000, machine.regs()[11]);
+    assert_eq!(&[0xff; 8], &machine.memory()[data("main")..][..8]);
+    let heap = &machine.memory()[data("routines")..][..8];
+    assert_eq!(
+        [3008, 3020],
+        [0, 4].map(|i| u32::from_le_bytes(heap[i..i + 4].try_into().unwrap()))
+    );
+}
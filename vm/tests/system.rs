@@ -0,0 +1,177 @@
+use interpreter::{assemble, Error, Machine, Schedule, System, MAX_CORES};
+
+/// Each core increments the counter at 1000 ten times without any
+/// synchronization.
+const RACY: &str = "
+  0000   loadimm r4 <- #1000
+  0004   loadimm r5 <- #10
+  0008   loadimm r3 <- #-1
+  0012   loadimm r7 <- #1
+  0016   load r6 <- [r4]
+  0019   sub r6 <- r6 - r3
+  0023   store [r4] <- r6
+  0026   sub r5 <- r5 - r7
+  0030   jump_rel ip-18 if r5 != 0
+  0034   exit
+";
+
+/// Same with an atomic increment.
+const ATOMIC: &str = "
+  0000   loadimm r4 <- #1000
+  0004   loadimm r5 <- #10
+  0008   loadimm r7 <- #1
+  0012   fetch_add r6 <- [r4] + r7
+  0016   sub r5 <- r5 - r7
+  0020   jump_rel ip-12 if r5 != 0
+  0024   exit
+";
+
+/// Same with a spinlock at 1004 protecting the counter.
+const LOCKED: &str = "
+  0000   loadimm r4 <- #1000
+  0004   loadimm r8 <- #1004
+  0008   loadimm r5 <- #10
+  0012   loadimm r7 <- #1
+  0016   loadimm r3 <- #-1
+  0020   cas r9 <- [r8] r1 r7
+  0025   jump_rel ip-9 if r9 != 0
+  0029   load r6 <- [r4]
+  0032   sub r6 <- r6 - r3
+  0036   store [r4] <- r6
+  0039   store [r8] <- r1
+  0042   sub r5 <- r5 - r7
+  0046   jump_rel ip-30 if r5 != 0
+  0050   exit
+";
+
+fn new_system(source: &str, cores: usize) -> System {
+    System::new(Machine::new(&assemble(source).unwrap()).unwrap(), cores).unwrap()
+}
+
+fn counter(system: &System) -> u8 {
+    system.machine().memory()[1000]
+}
+
+#[test]
+fn test_core_id() {
+    let mut system = new_system("coreid r4\nexit", 3);
+    system.run_on(&mut Vec::new()).unwrap();
+    for core in 0..3 {
+        assert_eq!(core, system.regs(core as usize).unwrap()[4]);
+        assert!(system.has_exited(core as usize).unwrap());
+    }
+    // A lone machine is core 0
+    let mut machine = Machine::new(&assemble("coreid r4\nexit").unwrap()).unwrap();
+    machine.set_reg(4, 9).unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(0, machine.regs()[4]);
+}
+
+#[test]
+fn test_round_robin() {
+    // In lockstep, every increment of a core is lost
+    let mut system = new_system(RACY, 2);
+    system.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(10, counter(&system));
+
+    let mut system = new_system(ATOMIC, 2);
+    system.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(20, counter(&system));
+}
+
+#[test]
+fn test_random_schedule_is_reproducible() {
+    let run = |seed| {
+        let mut system = new_system(RACY, 3);
+        system.set_schedule(Schedule::Random(seed));
+        system.run_on(&mut Vec::new()).unwrap();
+        counter(&system)
+    };
+    assert_eq!(run(42), run(42));
+    assert!((0..20).map(run).any(|count| count != run(0)));
+}
+
+#[test]
+fn test_compare_and_swap() {
+    let mut system = new_system(LOCKED, 4);
+    system.set_schedule(Schedule::Random(7));
+    system.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(40, counter(&system));
+    assert_eq!(0, system.machine().memory()[1004]);
+
+    // Failed comparison
+    let mut machine = Machine::new(&assemble("cas r9 <- [r8] r7 r6").unwrap()).unwrap();
+    machine.set_reg(8, 100).unwrap();
+    machine.set_reg(6, 5).unwrap();
+    machine.load_at(100, &[3, 0, 0, 0], None).unwrap();
+    machine.step_on(&mut Vec::new()).unwrap();
+    assert_eq!(3, machine.regs()[9]);
+    assert_eq!(3, machine.memory()[100]);
+}
+
+#[test]
+fn test_find_race() {
+    let race = new_system(RACY, 2).find_race(0..50, 1000).unwrap();
+    let run = |seed| {
+        let mut system = new_system(RACY, 2);
+        system.set_schedule(Schedule::Random(seed));
+        system.run_on(&mut Vec::new()).unwrap();
+        counter(&system)
+    };
+    assert_ne!(run(race.reference), run(race.seed));
+
+    assert_eq!(None, new_system(ATOMIC, 3).find_race(0..50, 1000));
+    assert_eq!(None, new_system(LOCKED, 3).find_race(0..50, 10_000));
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(
+        Some(Error::InvalidCore(0)),
+        System::new(Machine::new(&[]).unwrap(), 0).err()
+    );
+    assert_eq!(
+        Some(Error::InvalidCore(MAX_CORES + 1)),
+        System::new(Machine::new(&[]).unwrap(), MAX_CORES + 1).err()
+    );
+    let mut program = assemble("coreid r4").unwrap();
+    program.extend([6, 20]);
+    let mut system = System::new(Machine::new(&program).unwrap(), 2).unwrap();
+    assert_eq!(Err(Error::InvalidCore(2)), system.regs(2));
+    assert_eq!(Err(Error::InvalidRegister(16)), system.set_reg(1, 16, 0));
+    system.set_reg(1, 0, 2).unwrap();
+    assert_eq!(
+        Err(Error::InvalidRegister(20)),
+        system.run_on(&mut Vec::new())
+    );
+    assert_eq!(1, system.current_core());
+}
+
+#[test]
+fn test_per_core_state() {
+    // Core 0 runs in user mode, where `ei` is privileged
+    let mut system = new_system("ei\nexit", 2);
+    system.set_user_mode(0, true).unwrap();
+    assert_eq!(
+        Err(Error::PrivilegedInstruction(0)),
+        system.step_on(&mut Vec::new())
+    );
+    assert_eq!(0, system.current_core());
+    system.step_on(&mut Vec::new()).unwrap();
+    assert!(system.user_mode(0).unwrap());
+    assert!(!system.user_mode(1).unwrap());
+    assert!(!system.machine().user_mode());
+    assert_eq!(Err(Error::InvalidCore(2)), system.user_mode(2));
+
+    // An interrupt is only delivered to the core it is raised on
+    let source = "ei\nloadimm r4 <- #1\nexit\nloadimm r4 <- #2\nexit";
+    let mut system = new_system(source, 2);
+    let machine = system.machine_mut();
+    machine.set_vector_table(Some(200));
+    machine.load_at(212, &6_u32.to_le_bytes(), None).unwrap();
+    system.raise_interrupt(1, 3).unwrap();
+    system.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(1, system.regs(0).unwrap()[4]);
+    assert_eq!(2, system.regs(1).unwrap()[4]);
+    assert!(!system.machine().in_interrupt());
+}
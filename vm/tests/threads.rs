@@ -0,0 +1,146 @@
+use interpreter::{assemble, Error, Machine, MAX_THREADS};
+
+fn new_machine(source: &str) -> Machine {
+    let mut machine = Machine::new(&assemble(source).unwrap()).unwrap();
+    machine.enable_threads(2000, 100).unwrap();
+    machine
+}
+
+#[test]
+fn test_spawn_and_join() {
+    // Two workers increment the counter at 1000 three times each, yielding
+    // after every increment, then store their identifier at the top of
+    // their stack
+    let mut machine = new_machine(
+        "  0000   loadrel r10 <- ip+24
+           0004   spawn r11 <- r10
+           0007   sub r12 <- r11 - r1
+           0011   spawn r11 <- r10
+           0014   join r12
+           0016   join r11
+           0018   loadimm r4 <- #1000
+           0022   load r6 <- [r4]
+           0025   out_number r6
+           0027   exit
+           0028   loadimm r4 <- #1000
+           0032   loadimm r3 <- #-1
+           0036   loadimm r5 <- #3
+           0040   loadimm r9 <- #1
+           0044   load r6 <- [r4]
+           0047   sub r6 <- r6 - r3
+           0051   store [r4] <- r6
+           0054   yield
+           0055   sub r5 <- r5 - r9
+           0059   jump_rel ip-19 if r5 != 0
+           0063   loadimm r7 <- #4
+           0067   sub r8 <- r2 - r7
+           0071   store [r8] <- r11
+           0074   exit",
+    );
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(b"6", &out[..]);
+    assert_eq!(1, machine.memory()[1996]);
+    assert_eq!(2, machine.memory()[1896]);
+    assert_eq!(0, machine.current_thread());
+}
+
+#[test]
+fn test_main_thread_exits_first() {
+    let mut machine = new_machine(
+        "  0000   loadrel r10 <- ip+4
+           0004   spawn r11 <- r10
+           0007   exit
+           0008   yield
+           0009   out_number r11
+           0011   exit",
+    );
+    let mut out = Vec::new();
+    machine.run_on(&mut out).unwrap();
+    assert_eq!(b"1", &out[..]);
+    assert_eq!(1, machine.current_thread());
+}
+
+#[test]
+fn test_deadlock() {
+    // The spawned thread joins the initial one, which joins it
+    let mut machine = new_machine(
+        "  0000   loadrel r10 <- ip+5
+           0004   spawn r11 <- r10
+           0007   join r11
+           0009   join r12
+           0011   exit",
+    );
+    assert_eq!(Err(Error::Deadlock), machine.run_on(&mut Vec::new()));
+
+    // Joining itself
+    let mut machine = new_machine("spawn r11 <- r1\njoin r1");
+    assert_eq!(Err(Error::Deadlock), machine.run_on(&mut Vec::new()));
+}
+
+#[test]
+fn test_identifier_reuse() {
+    // Spawn and join threads which exit immediately, more than MAX_THREADS
+    // times
+    let mut machine = new_machine(
+        "  0000   loadrel r10 <- ip+22
+           0004   loadimm r5 <- #20
+           0008   loadimm r9 <- #1
+           0012   spawn r11 <- r10
+           0015   join r11
+           0017   sub r5 <- r5 - r9
+           0021   jump_rel ip-13 if r5 != 0
+           0025   exit
+           0026   exit",
+    );
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(1, machine.regs()[11]);
+}
+
+#[test]
+fn test_without_threads() {
+    // Yield does nothing and exit stops the machine
+    let mut machine = Machine::new(&assemble("yield\nexit\nexit").unwrap()).unwrap();
+    machine.run_on(&mut Vec::new()).unwrap();
+    assert_eq!(2, machine.regs()[0]);
+
+    let mut machine = Machine::new(&assemble("spawn r11 <- r1").unwrap()).unwrap();
+    assert_eq!(
+        Err(Error::ThreadsDisabled(0)),
+        machine.run_on(&mut Vec::new())
+    );
+    assert_eq!(
+        Err(Error::InvalidMemoryAddress(500)),
+        machine.enable_threads(500, 100)
+    );
+    assert_eq!(
+        Err(Error::InvalidMemoryAddress(5000)),
+        machine.enable_threads(5000, 100)
+    );
+}
+
+#[test]
+fn test_errors() {
+    // The initial thread spawns threads which yield forever
+    let mut machine = new_machine(
+        "  0000   loadrel r10 <- ip+6
+           0004   spawn r11 <- r10
+           0007   jump_rel ip-6
+           0010   yield
+           0011   jump_rel ip-4",
+    );
+    machine.step_on(&mut Vec::new()).unwrap();
+    for _ in 1..MAX_THREADS {
+        machine.step_on(&mut Vec::new()).unwrap();
+        machine.step_on(&mut Vec::new()).unwrap();
+    }
+    assert_eq!(Ok(7), machine.regs()[11].try_into());
+    assert_eq!(Err(Error::TooManyThreads), machine.step_on(&mut Vec::new()));
+
+    let mut machine = new_machine("spawn r11 <- r1\njoin r12");
+    machine.set_reg(12, 9).unwrap();
+    assert_eq!(
+        Err(Error::InvalidThread(9)),
+        machine.run_on(&mut Vec::new())
+    );
+}
@@ -0,0 +1,159 @@
+use interpreter::{assemble, Error, Machine, PageTable, PAGE_EXECUTE, PAGE_READ, PAGE_WRITE};
+
+fn run(machine: &mut Machine) -> Result<(), Error> {
+    machine.run_on(&mut Vec::new())
+}
+
+#[test]
+fn test_read_from_image() {
+    // 0: load r1 <- [r2]
+    // 3: exit
+    // 4: 42
+    let mut machine = Machine::new(&[3, 1, 2, 7, 42, 0, 0, 0]).unwrap();
+    machine.set_reg(2, 4).unwrap();
+    machine.check_uninitialized(true);
+    run(&mut machine).unwrap();
+    assert_eq!(42, machine.regs()[1]);
+}
+
+#[test]
+fn test_read_uninitialized() {
+    // 0: load r1 <- [r2]
+    // 3: exit
+    let mut machine = Machine::new(&[3, 1, 2, 7]).unwrap();
+    machine.set_reg(2, 4092).unwrap();
+    // No detection by default
+    run(&mut machine).unwrap();
+
+    let mut machine = Machine::new(&[3, 1, 2, 7]).unwrap();
+    machine.set_reg(2, 4092).unwrap();
+    machine.check_uninitialized(true);
+    assert!(matches!(
+        run(&mut machine),
+        Err(Error::UninitializedRead { ip: 0, addr: 4092 })
+    ));
+    // The load has been executed and the program can be resumed
+    assert_eq!(3, machine.regs()[0]);
+    run(&mut machine).unwrap();
+}
+
+#[test]
+fn test_partially_initialized_word() {
+    // 0: store [r2] <- r3
+    // 3: load r1 <- [r4]
+    // 6: load r1 <- [r5]
+    // 9: exit
+    // 10: 0xff
+    let mut machine = Machine::new(&[2, 2, 3, 3, 1, 4, 3, 1, 5, 7, 0xff]).unwrap();
+    machine.set_reg(2, 100).unwrap();
+    machine.set_reg(4, 102).unwrap();
+    machine.set_reg(5, 8).unwrap();
+    machine.check_uninitialized(true);
+    assert!(matches!(
+        run(&mut machine),
+        Err(Error::UninitializedRead { ip: 3, addr: 104 })
+    ));
+    // The first three bytes are part of the image, not the fourth one
+    assert!(matches!(
+        run(&mut machine),
+        Err(Error::UninitializedRead { ip: 6, addr: 11 })
+    ));
+    run(&mut machine).unwrap();
+}
+
+#[test]
+fn test_word_across_pages() {
+    // The virtual pages at 0x3e00 and 0x3f00 are mapped to the last physical
+    // page and to the one at 512
+    let source = "loadimm32 r4 <- #0x3efe
+                  store [r4] <- r4
+                  load r5 <- [r4]
+                  loadimm32 r4 <- #0x3efc
+                  load r5 <- [r4]
+                  exit";
+    let mut machine = Machine::new(&assemble(source).unwrap()).unwrap();
+    let table = PageTable {
+        base: 3072,
+        pages: 64,
+    };
+    machine.set_page_table(Some(table)).unwrap();
+    machine.map_page(0, 0, PAGE_READ | PAGE_EXECUTE).unwrap();
+    machine
+        .map_page(0x3e00, 3840, PAGE_READ | PAGE_WRITE)
+        .unwrap();
+    machine
+        .map_page(0x3f00, 512, PAGE_READ | PAGE_WRITE)
+        .unwrap();
+    machine.check_uninitialized(true);
+    assert!(matches!(
+        run(&mut machine),
+        Err(Error::UninitializedRead {
+            ip: 18,
+            addr: 0x3efc
+        })
+    ));
+    // The load has read the stored bytes in the upper half of the word
+    assert_eq!(0x3efe_0000, machine.regs()[5]);
+}
+
+#[test]
+fn test_load_at_initializes() {
+    // 200: load r1 <- [r2]
+    // 203: exit
+    let mut machine = Machine::new(&[]).unwrap();
+    machine
+        .load_at(200, &[3, 1, 2, 7, 1, 2, 3, 4], Some(0))
+        .unwrap();
+    machine.set_reg(2, 204).unwrap();
+    machine.check_uninitialized(true);
+    run(&mut machine).unwrap();
+    assert_eq!(0x0403_0201, machine.regs()[1]);
+}
+
+#[test]
+fn test_read_before_write_stack_slot() {
+    // Read a stack slot below SP before having pushed anything
+    // 0: loadimm r3 <- #4
+    // 4: sub r3 <- r2 - r3
+    // 8: load r10 <- [r3]
+    // 11: exit
+    let mut machine = Machine::new(&[4, 3, 4, 0, 5, 3, 2, 3, 3, 10, 3, 7]).unwrap();
+    machine.set_reg(2, 4000).unwrap();
+    machine.check_uninitialized(true);
+    assert!(matches!(
+        run(&mut machine),
+        Err(Error::UninitializedRead { ip: 8, addr: 3996 })
+    ));
+}
+
+#[test]
+fn test_generated_programs_are_clean() {
+    for program in [
+        &include_bytes!("push_pop.bin")[..],
+        include_bytes!("function.bin"),
+        include_bytes!("afact.bin"),
+        include_bytes!("rfact.bin"),
+        include_bytes!("fibo.bin"),
+    ] {
+        let mut machine = Machine::new(program).unwrap();
+        machine.set_reg(10, 6).unwrap();
+        machine.check_uninitialized(true);
+        run(&mut machine).unwrap();
+    }
+}
+
+#[test]
+fn test_print_reads_past_last_string() {
+    // print loads a whole word to output a single character, so printing the
+    // last character of the last string reads past the end of the image.
+    let program = include_bytes!("../examples/99bottles.bin");
+    let end = u32::try_from(program.len()).unwrap();
+    let mut machine = Machine::new(program).unwrap();
+    machine.check_uninitialized(true);
+    let mut addrs = Vec::new();
+    while let Err(Error::UninitializedRead { addr, .. }) = run(&mut machine) {
+        addrs.push(addr);
+    }
+    assert!(!addrs.is_empty());
+    assert!(addrs.iter().all(|&addr| addr == end));
+}
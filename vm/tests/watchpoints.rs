@@ -0,0 +1,136 @@
+use interpreter::{Error, Machine, WatchKind, WatchpointHit, MAX_WATCHPOINTS};
+
+// 0: store [r2] <- r3
+// 3: load r4 <- [r5]
+// 6: exit
+const CODE: [u8; 7] = [2, 2, 3, 3, 4, 5, 7];
+
+fn new_machine() -> Machine {
+    let mut machine = Machine::new(&CODE).unwrap();
+    machine.set_reg(2, 100).unwrap();
+    machine.set_reg(3, 0x1234_5678).unwrap();
+    machine.set_reg(5, 200).unwrap();
+    machine
+}
+
+fn run(machine: &mut Machine) -> Result<(), Error> {
+    machine.run_on(&mut Vec::new())
+}
+
+#[test]
+fn test_write_watchpoint() {
+    let mut machine = new_machine();
+    machine
+        .add_watchpoint(WatchKind::Write, 102..103, "data")
+        .unwrap();
+    machine
+        .add_watchpoint(WatchKind::Write, 200..204, "other")
+        .unwrap();
+    match run(&mut machine) {
+        Err(Error::Watchpoint(hit)) => assert_eq!(
+            WatchpointHit {
+                label: "data".into(),
+                access: WatchKind::Write,
+                ip: 0,
+                addr: 100,
+                old: 0,
+                new: 0x1234_5678,
+            },
+            hit
+        ),
+        r => panic!("unexpected result {r:?}"),
+    }
+    // The store has been done and the execution can be resumed
+    assert_eq!(&[0x78, 0x56, 0x34, 0x12], &machine.memory()[100..104]);
+    assert_eq!(3, machine.regs()[0]);
+    run(&mut machine).unwrap();
+}
+
+#[test]
+fn test_read_watchpoint() {
+    let mut machine = new_machine();
+    machine
+        .add_watchpoint(WatchKind::Read, 100..104, "store")
+        .unwrap();
+    machine
+        .add_watchpoint(WatchKind::Read, 203..210, "load")
+        .unwrap();
+    match run(&mut machine) {
+        Err(Error::Watchpoint(hit)) => {
+            assert_eq!("load", hit.label);
+            assert_eq!(WatchKind::Read, hit.access);
+            assert_eq!(3, hit.ip);
+            assert_eq!(200, hit.addr);
+        }
+        r => panic!("unexpected result {r:?}"),
+    }
+    assert_eq!(6, machine.regs()[0]);
+    run(&mut machine).unwrap();
+}
+
+#[test]
+fn test_access_watchpoint() {
+    let mut machine = new_machine();
+    machine.set_reg(5, 100).unwrap();
+    machine
+        .add_watchpoint(WatchKind::Access, 100..101, "both")
+        .unwrap();
+    let mut hits = vec![];
+    while let Err(Error::Watchpoint(hit)) = run(&mut machine) {
+        hits.push((hit.access, hit.old, hit.new));
+    }
+    assert_eq!(
+        vec![
+            (WatchKind::Write, 0, 0x1234_5678),
+            (WatchKind::Read, 0x1234_5678, 0x1234_5678)
+        ],
+        hits
+    );
+}
+
+#[test]
+fn test_watchpoint_boundaries() {
+    // The accessed word is 100..104
+    for (range, triggered) in [
+        (96..100, false),
+        (97..101, true),
+        (103..110, true),
+        (104..110, false),
+    ] {
+        let mut machine = new_machine();
+        machine
+            .add_watchpoint(WatchKind::Write, range, "w")
+            .unwrap();
+        assert_eq!(triggered, run(&mut machine).is_err());
+    }
+}
+
+#[test]
+fn test_remove_watchpoints() {
+    let mut machine = new_machine();
+    machine
+        .add_watchpoint(WatchKind::Access, 0..4096, "all")
+        .unwrap();
+    assert!(!machine.remove_watchpoint("none"));
+    assert!(machine.remove_watchpoint("all"));
+    run(&mut machine).unwrap();
+
+    let mut machine = new_machine();
+    machine
+        .add_watchpoint(WatchKind::Access, 0..4096, "all")
+        .unwrap();
+    machine.clear_watchpoints();
+    run(&mut machine).unwrap();
+}
+
+#[test]
+fn test_too_many_watchpoints() {
+    let mut machine = new_machine();
+    for _ in 0..MAX_WATCHPOINTS {
+        machine.add_watchpoint(WatchKind::Read, 0..1, "w").unwrap();
+    }
+    assert!(matches!(
+        machine.add_watchpoint(WatchKind::Read, 0..1, "w"),
+        Err(Error::TooManyWatchpoints)
+    ));
+}